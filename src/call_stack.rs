@@ -0,0 +1,124 @@
+/// Frames kept before the oldest are dropped, for code that never returns
+const MAX_FRAMES: usize = 256;
+
+/// Entry into a routine by CALL, RST or an interrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StackFrame {
+    /// Address of the CALL or RST, or of the interrupted instruction
+    pub call_site: u16,
+    /// Start of the routine
+    pub target: u16,
+    /// SP once the return address was pushed
+    pub sp: u16,
+    pub interrupt: bool,
+}
+
+/// Change to the call stack, kept so a step can be undone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FrameChange {
+    Pushed,
+    Popped(StackFrame),
+    /// The oldest frame made room for a new one
+    Dropped(StackFrame),
+}
+
+/// Shadow of the return addresses on the stack, innermost frame last
+///
+/// Games also pop return addresses themselves or switch stacks, so a return drops every
+/// frame pushed below the SP it returns from instead of expecting the last one.
+#[derive(Debug, Default)]
+pub(crate) struct CallStack {
+    frames: Vec<StackFrame>,
+    /// Changes are collected while recording history
+    pub log: Option<Vec<FrameChange>>,
+}
+
+impl CallStack {
+    pub fn frames(&self) -> &[StackFrame] {
+        &self.frames
+    }
+
+    pub fn call(&mut self, frame: StackFrame) {
+        if self.frames.len() == MAX_FRAMES {
+            let dropped = self.frames.remove(0);
+            self.log_change(FrameChange::Dropped(dropped));
+        }
+        self.frames.push(frame);
+        self.log_change(FrameChange::Pushed);
+    }
+
+    /// A return popping the address at `sp`
+    pub fn ret(&mut self, sp: u16) {
+        while self.frames.last().is_some_and(|frame| frame.sp <= sp) {
+            let popped = self.frames.pop().unwrap();
+            self.log_change(FrameChange::Popped(popped));
+        }
+    }
+
+    /// Reverts `changes` made by a step, which are in the order they happened
+    pub fn undo(&mut self, changes: &[FrameChange]) {
+        for change in changes.iter().rev() {
+            match *change {
+                FrameChange::Pushed => {
+                    self.frames.pop();
+                }
+                FrameChange::Popped(frame) => self.frames.push(frame),
+                FrameChange::Dropped(frame) => self.frames.insert(0, frame),
+            }
+        }
+    }
+
+    fn log_change(&mut self, change: FrameChange) {
+        if let Some(log) = &mut self.log {
+            log.push(change);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cpu::Cpu,
+        memory_mapping::{MemoryMapping, Rom},
+    };
+
+    #[test]
+    fn calls_and_interrupts() {
+        let mut rom = vec![0; 0x8000];
+        // CALL $0200
+        rom[0x100..0x103].copy_from_slice(&[0xCD, 0x00, 0x02]);
+        // $0200: RST $08; NOP; RET
+        rom[0x200..0x203].copy_from_slice(&[0xCF, 0x00, 0xC9]);
+        // $0008: POP HL; RET, drops the RST frame and returns from $0200
+        rom[0x08..0x0A].copy_from_slice(&[0xE1, 0xC9]);
+        let mut cpu = Cpu::new(MemoryMapping::new(Rom { rom }));
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        let frames: Vec<_> = cpu
+            .call_stack
+            .frames()
+            .iter()
+            .map(|f| (f.call_site, f.target, f.interrupt))
+            .collect();
+        assert_eq!(frames, [(0x100, 0x200, false), (0x200, 0x08, false)]);
+
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x103);
+        assert!(cpu.call_stack.frames().is_empty());
+
+        // VBlank interrupt while running $0103
+        cpu.memory.interrupt.set_ime_forced();
+        cpu.memory.interrupt.interrupt_enable.value = 1;
+        cpu.memory.interrupt.interrupt_flag.value = 1;
+        cpu.step().unwrap();
+        let frame = cpu.call_stack.frames()[0];
+        assert_eq!((frame.call_site, frame.target), (0x103, 0x40));
+        assert!(frame.interrupt);
+    }
+}
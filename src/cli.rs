@@ -1,33 +1,105 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use clap::{CommandFactory, Parser, error::ErrorKind};
+use clap::{CommandFactory, Parser, Subcommand, error::ErrorKind};
 
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub(crate) struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Rom file path
-    pub file: PathBuf,
+    #[arg(required = true)]
+    pub file: Option<PathBuf>,
 
     #[arg(long)]
     pub debug: bool,
+
+    /// Pace frames with the display refresh instead of a timer at 1x speed
+    #[arg(long)]
+    pub vsync: bool,
+
+    /// Log every instruction to a file in Gameboy Doctor format
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<PathBuf>,
+
+    /// Add M-cycles, LY and the instruction to every trace line
+    #[arg(long, requires = "trace")]
+    pub trace_extended: bool,
+
+    /// RGBDS symbol file, the ROM's `.sym` is loaded when it exists
+    #[arg(long, value_name = "FILE")]
+    pub symbols: Option<PathBuf>,
+
+    /// Serve the GDB remote protocol on this localhost port
+    #[arg(long, value_name = "PORT")]
+    pub gdb: Option<u16>,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Disassemble a ROM into RGBDS assembly
+    Disasm {
+        /// Rom file path
+        file: PathBuf,
+
+        /// Output file, printed when not given
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
 }
 
 impl Args {
     pub fn new() -> Self {
         let args = Self::parse();
-        if !args.file.exists() {
+        let file = match &args.command {
+            Some(Command::Disasm { file, .. }) => file,
+            None => args.rom(),
+        };
+        if !file.exists() {
             let mut cmd = Args::command();
             cmd.error(
                 ErrorKind::ValueValidation,
-                format!("file `{}` doesn't exist", args.file.to_str().unwrap()),
+                format!("file `{}` doesn't exist", file.to_str().unwrap()),
             )
             .exit();
         }
 
         args
     }
+
+    /// ROM to run, only missing with a subcommand
+    pub fn rom(&self) -> &Path {
+        self.file.as_deref().expect("ROM file is required")
+    }
+
+    /// Symbol file given, or the one next to the ROM
+    pub fn symbols(&self) -> Option<PathBuf> {
+        if self.symbols.is_some() {
+            return self.symbols.clone();
+        }
+        let path = self.rom().with_extension("sym");
+        path.exists().then_some(path)
+    }
 }
 
 #[test]
 fn verify_app() {
     Args::command().debug_assert();
 }
+
+#[test]
+fn disasm_subcommand() {
+    let args = Args::try_parse_from(["emulator", "disasm", "rom.gb", "-o", "out.asm"]).unwrap();
+    assert!(matches!(
+        args.command,
+        Some(Command::Disasm { file, output: Some(output) })
+            if file == Path::new("rom.gb") && output == Path::new("out.asm")
+    ));
+
+    let args = Args::try_parse_from(["emulator", "rom.gb", "--debug"]).unwrap();
+    assert!(args.command.is_none() && args.debug);
+    assert_eq!(args.rom(), Path::new("rom.gb"));
+
+    assert!(Args::try_parse_from(["emulator"]).is_err());
+}
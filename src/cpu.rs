@@ -1,15 +1,32 @@
 use anyhow::{Result, bail};
 
 use crate::{
+    call_stack::{CallStack, StackFrame},
+    disassembler,
+    history::{History, HistoryEntry, InterruptSnapshot, RegisterSnapshot},
     instructions::{self, Instruction, Operand, OperandU8, OperandU16},
     memory_mapping::MemoryMapping,
     registers::{Alu, Direction, Flags, RegisterU16, Registers},
+    save_state::{Apply, SaveState, StateReader, StateWriter},
+    trace::Trace,
 };
 
+/// M-cycles of a frame at normal speed, 154 lines of 456 dots
+pub const FRAME_CYCLES: u32 = 70224 / 4;
+/// M-cycles to push PC and jump to an interrupt vector
+const INTERRUPT_DISPATCH_CYCLES: u16 = 5;
+/// M-cycles the CPU is halted for by STOP switching speed
+const SPEED_SWITCH_CYCLES: u16 = 2050;
+
 #[derive(Debug)]
 pub(crate) struct Cpu<'a> {
     pub registers: Registers,
     pub memory: MemoryMapping<'a>,
+    /// Instruction log, `None` unless tracing
+    pub trace: Option<Trace>,
+    pub call_stack: CallStack,
+    /// Executed instructions, `None` unless recording
+    pub history: Option<History>,
 }
 
 impl<'a> Cpu<'a> {
@@ -17,26 +34,153 @@ impl<'a> Cpu<'a> {
         Cpu {
             registers: Registers::new(),
             memory,
+            trace: None,
+            call_stack: CallStack::default(),
+            history: None,
+        }
+    }
+
+    /// Runs one instruction, or dispatches a pending interrupt, and advances the rest of
+    /// the machine
+    pub(crate) fn step(&mut self) -> Result<u16> {
+        // Taken before handling interrupts, which acknowledges them and applies EI
+        let snapshot = self.start_recording();
+
+        // Dispatch is its own step, the instruction at the vector runs in the next one
+        if let Some(vector) = self.memory.interrupt.handle_interrupts() {
+            return self.dispatch_interrupt(vector, snapshot);
+        }
+
+        let cycles = self.get_instruction().and_then(|(instruction, inc)| {
+            if let Some(trace) = &mut self.trace {
+                trace.log(&self.registers, &self.memory, &instruction)?;
+            }
+            self.run_instruction(instruction, inc)
+        });
+        if let Some(snapshot) = snapshot {
+            self.record(snapshot, None);
+        }
+        self.advance(cycles?)
+    }
+
+    /// Pushes PC and jumps to the interrupt vector, taking 5 M-cycles
+    fn dispatch_interrupt(
+        &mut self,
+        vector: u16,
+        snapshot: Option<(RegisterSnapshot, InterruptSnapshot)>,
+    ) -> Result<u16> {
+        let result = self.call(vector, self.registers.pc, true);
+        if let Some(snapshot) = snapshot {
+            self.record(snapshot, Some(vector));
         }
+        result?;
+        self.advance(INTERRUPT_DISPATCH_CYCLES)
+    }
+
+    /// Runs the timer and PPU for the cycles of a step
+    fn advance(&mut self, cycles: u16) -> Result<u16> {
+        self.memory
+            .timer
+            .do_cycles(&mut self.memory.interrupt, cycles);
+        self.memory.do_cycles(cycles)?;
+        if let Some(trace) = &mut self.trace {
+            trace.add_cycles(cycles);
+        }
+        Ok(cycles)
+    }
+
+    /// Registers before the step while recording the history
+    fn start_recording(&mut self) -> Option<(RegisterSnapshot, InterruptSnapshot)> {
+        self.history.is_some().then(|| {
+            self.memory.write_log = Some(Vec::new());
+            self.call_stack.log = Some(Vec::new());
+            (
+                RegisterSnapshot::new(&self.registers),
+                InterruptSnapshot::new(&self.memory.interrupt),
+            )
+        })
+    }
+
+    /// Adds the step that ran from `registers` to the history, an interrupt dispatch has
+    /// no instruction bytes
+    fn record(
+        &mut self,
+        (registers, interrupts): (RegisterSnapshot, InterruptSnapshot),
+        interrupt: Option<u16>,
+    ) {
+        let read = |addr| self.memory.peek(addr);
+        let bytes = match interrupt {
+            Some(_) => Vec::new(),
+            None => {
+                let len = disassembler::instruction_len(read, registers.pc);
+                (0..len)
+                    .map(|i| read(registers.pc.wrapping_add(i)))
+                    .collect()
+            }
+        };
+        let writes = self.memory.write_log.take().unwrap_or_default();
+        let frames = self.call_stack.log.take().unwrap_or_default();
+        if let Some(history) = &mut self.history {
+            history.push(HistoryEntry {
+                bytes,
+                registers,
+                interrupts,
+                writes,
+                frames,
+                interrupt,
+            });
+        }
+    }
+
+    /// Undoes the last recorded instruction, false without history
+    pub(crate) fn step_back(&mut self) -> bool {
+        self.history.as_mut().is_some_and(|history| {
+            history.step_back(&mut self.registers, &mut self.memory, &mut self.call_stack)
+        })
+    }
+
+    /// Runs until the PPU enters VBlank, or a frame worth of cycles while the LCD is off
+    ///
+    /// `on_step` gets the PC and result of every instruction, errors don't end the frame.
+    /// Returns false when `on_step` stopped the frame early by returning true.
+    pub(crate) fn run_frame(
+        &mut self,
+        mut on_step: impl FnMut(&Self, u16, Result<u16>) -> bool,
+    ) -> bool {
+        let frame = self.memory.vram.frame_count;
+        let limit = match self.memory.double_speed() {
+            true => FRAME_CYCLES * 2,
+            false => FRAME_CYCLES,
+        };
+
+        let mut cycles = 0;
+        while self.memory.vram.frame_count == frame && cycles < limit {
+            let pc = self.registers.pc;
+            let result = self.step();
+            // Failed instructions still count, so the frame ends
+            cycles += *result.as_ref().unwrap_or(&1) as u32;
+            if on_step(self, pc, result) {
+                return false;
+            }
+        }
+        true
     }
 
     pub(crate) fn get_instruction(&self) -> Result<(Instruction, u16)> {
-        let byte = *self.memory.get(self.registers.pc)?;
+        let byte = self.memory.fetch(self.registers.pc)?;
         Ok(match byte {
             0xCB => (
-                instructions::cbprefixed::decode_byte(*self.memory.get(self.registers.pc + 1)?),
+                instructions::cbprefixed::decode_byte(self.memory.fetch(self.registers.pc + 1)?),
                 2,
             ),
             _ => (instructions::unprefixed::decode_byte(byte), 1),
         })
     }
 
-    pub(crate) fn run_instruction(&mut self, instruction: Instruction, inc: u16) -> Result<u8> {
-        if let Some(addr) = self.memory.interrupt.handle_interrupts() {
-            self.call(addr)?;
-        }
-
+    /// Runs a decoded instruction, the cycles include stalls but the machine isn't advanced
+    pub(crate) fn run_instruction(&mut self, instruction: Instruction, inc: u16) -> Result<u16> {
         self.registers.pc += inc;
+        let mut speed_switch = 0;
         let cycles = match instruction {
             Instruction::NOP => 1,
             Instruction::LD(Operand::U8(a), Operand::U8(b)) => {
@@ -51,25 +195,25 @@ impl<'a> Cpu<'a> {
             }
             Instruction::LD22 => {
                 let hl = self.registers.get_u16(&RegisterU16::HL);
-                *self.memory.get_mut(hl)? = self.registers.a;
+                self.memory.set(hl, self.registers.a)?;
                 self.registers.set_u16(&RegisterU16::HL, hl.wrapping_add(1));
                 2
             }
             Instruction::LD2A => {
                 let hl = self.registers.get_u16(&RegisterU16::HL);
-                self.registers.a = *self.memory.get(hl)?;
+                self.registers.a = self.memory.read(hl)?;
                 self.registers.set_u16(&RegisterU16::HL, hl.wrapping_add(1));
                 2
             }
             Instruction::LD32 => {
                 let hl = self.registers.get_u16(&RegisterU16::HL);
-                *self.memory.get_mut(hl)? = self.registers.a;
+                self.memory.set(hl, self.registers.a)?;
                 self.registers.set_u16(&RegisterU16::HL, hl.wrapping_sub(1));
                 2
             }
             Instruction::LD3A => {
                 let hl = self.registers.get_u16(&RegisterU16::HL);
-                self.registers.a = *self.memory.get(hl)?;
+                self.registers.a = self.memory.read(hl)?;
                 self.registers.set_u16(&RegisterU16::HL, hl.wrapping_sub(1));
                 2
             }
@@ -203,7 +347,7 @@ impl<'a> Cpu<'a> {
             Instruction::CALL(condition, op) => {
                 let (addr, _cycles) = self.get_u16(op)?;
                 if condition.is_none_or(|cond| self.registers.get_flag_condition(cond)) {
-                    self.call(addr)?;
+                    self.call(addr, self.registers.pc - 3, false)?;
                     6
                 } else {
                     3
@@ -242,16 +386,16 @@ impl<'a> Cpu<'a> {
             }
             Instruction::PUSH(r) => {
                 let (hi, lo) = self.registers.get_split_u16(&r);
-                *self.memory.get_mut(self.registers.sp - 1)? = hi;
-                *self.memory.get_mut(self.registers.sp - 2)? = lo;
+                self.memory.set(self.registers.sp - 1, hi)?;
+                self.memory.set(self.registers.sp - 2, lo)?;
                 self.registers.sp -= 2;
                 4
             }
             Instruction::POP(r) => {
                 self.registers.set_split_u16(
                     &r,
-                    *self.memory.get_mut(self.registers.sp + 1)?,
-                    *self.memory.get_mut(self.registers.sp)?,
+                    self.memory.read(self.registers.sp + 1)?,
+                    self.memory.read(self.registers.sp)?,
                 );
                 self.registers.sp += 2;
                 3
@@ -335,29 +479,46 @@ impl<'a> Cpu<'a> {
                 4
             }
             Instruction::RST(n) => {
-                self.call(n as u16 * 8)?;
+                self.call(n as u16 * 8, self.registers.pc - 1, false)?;
                 4
             }
+            Instruction::STOP(op) => {
+                self.get_u8(op)?;
+                if !self.memory.switch_speed() {
+                    bail!("not implemented: STOP");
+                }
+                // DIV is reset on STOP
+                self.memory.timer.divider_register = 0;
+                speed_switch = SPEED_SWITCH_CYCLES;
+                1
+            }
             _ => bail!("not implemented: {instruction:?}"),
         };
 
-        self.memory
-            .timer
-            .do_cycles(&mut self.memory.interrupt, cycles);
-        Ok(cycles)
+        // CPU is halted while VRAM DMA copies data and during a speed switch
+        Ok(cycles as u16 + self.memory.hdma.take_stall_cycles() + speed_switch)
     }
 
-    fn call(&mut self, addr: u16) -> Result<()> {
-        *self.memory.get_mut(self.registers.sp - 1)? = (self.registers.pc >> 8) as u8;
-        *self.memory.get_mut(self.registers.sp - 2)? = (self.registers.pc & 0xff) as u8;
+    fn call(&mut self, addr: u16, call_site: u16, interrupt: bool) -> Result<()> {
+        self.memory
+            .set(self.registers.sp - 1, (self.registers.pc >> 8) as u8)?;
+        self.memory
+            .set(self.registers.sp - 2, (self.registers.pc & 0xff) as u8)?;
         self.registers.sp -= 2;
         self.registers.pc = addr;
+        self.call_stack.call(StackFrame {
+            call_site,
+            target: addr,
+            sp: self.registers.sp,
+            interrupt,
+        });
         Ok(())
     }
 
     fn ret(&mut self) -> Result<()> {
-        let addr = (*self.memory.get(self.registers.sp + 1)? as u16) << 8
-            | *self.memory.get(self.registers.sp)? as u16;
+        self.call_stack.ret(self.registers.sp);
+        let addr = (self.memory.read(self.registers.sp + 1)? as u16) << 8
+            | self.memory.read(self.registers.sp)? as u16;
         self.registers.sp += 2;
         self.registers.pc = addr;
         Ok(())
@@ -369,8 +530,8 @@ impl<'a> Cpu<'a> {
             OperandU16::Immediate => {
                 self.registers.pc += 2;
                 (
-                    *self.memory.get(self.registers.pc - 2)? as u16
-                        | ((*self.memory.get(self.registers.pc - 1)? as u16) << 8),
+                    self.memory.fetch(self.registers.pc - 2)? as u16
+                        | ((self.memory.fetch(self.registers.pc - 1)? as u16) << 8),
                     3,
                 )
             }
@@ -391,15 +552,15 @@ impl<'a> Cpu<'a> {
             OperandU8::Register(r) => (self.registers.get_u8(&r), 1),
             OperandU8::Immediate => {
                 self.registers.pc += 1;
-                (*self.memory.get(self.registers.pc - 1)?, 2)
+                (self.memory.fetch(self.registers.pc - 1)?, 2)
             }
             OperandU8::Memory(addr) => {
                 let (a, cycles) = self.get_u16(addr)?;
-                (*self.memory.get(a)?, cycles)
+                (self.memory.read(a)?, cycles)
             }
             OperandU8::MemoryU8(offset) => {
                 let (a, cycles) = self.get_u8(*offset)?;
-                (*self.memory.get(0xff00 | a as u16)?, cycles)
+                (self.memory.read(0xff00 | a as u16)?, cycles)
             }
         })
     }
@@ -412,12 +573,12 @@ impl<'a> Cpu<'a> {
             OperandU8::Immediate => unreachable!("cannot write to immediate"),
             OperandU8::Memory(addr) => {
                 let (a, cycles) = self.get_u16(addr)?;
-                *self.memory.get_mut(a)? = value;
+                self.memory.set(a, value)?;
                 cycles
             }
             OperandU8::MemoryU8(offset) => {
                 let (a, cycles) = self.get_u8(*offset)?;
-                *self.memory.get_mut(0xff00 | a as u16)? = value;
+                self.memory.set(0xff00 | a as u16, value)?;
                 cycles
             }
         })
@@ -428,3 +589,128 @@ impl<'a> Cpu<'a> {
 fn u8_to_i16(a: u8) -> i16 {
     (a as i8) as i16
 }
+
+impl<'a> SaveState for Cpu<'a> {
+    fn save(&self, state: &mut StateWriter) {
+        self.registers.save(state);
+        self.memory.save(state);
+    }
+
+    /// The call stack and history aren't saved, they start over from the loaded state
+    fn parse<'s>(&self, state: &mut StateReader) -> Result<Apply<'s, Self>>
+    where
+        Self: 's,
+    {
+        let registers = self.registers.parse(state)?;
+        let memory = self.memory.parse(state)?;
+        Ok(Box::new(move |this: &mut Self| {
+            this.call_stack.clear();
+            if let Some(history) = &mut this.history {
+                history.clear();
+            }
+            registers(&mut this.registers);
+            memory(&mut this.memory);
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cpu;
+    use crate::memory_mapping::{MemoryMapping, Rom};
+
+    #[test]
+    fn run_frame() {
+        let mut cpu = Cpu::new(MemoryMapping::new(Rom {
+            rom: vec![0; 0x8000],
+        }));
+
+        assert!(cpu.run_frame(|_, _, result| result.is_err()));
+        assert_eq!(cpu.memory.vram.frame_count, 1);
+        assert!(cpu.run_frame(|_, _, _| false));
+        assert_eq!(cpu.memory.vram.frame_count, 2);
+
+        // Stopped by a breakpoint after the first instruction
+        let pc = cpu.registers.pc;
+        assert!(!cpu.run_frame(|_, _, _| true));
+        assert_eq!(cpu.registers.pc, pc + 1);
+        assert_eq!(cpu.memory.vram.frame_count, 2);
+    }
+
+    #[test]
+    fn interrupt_dispatch() {
+        let mut rom = vec![0; 0x8000];
+        // LD A,$12
+        rom[0x100..0x102].copy_from_slice(&[0x3E, 0x12]);
+        // VBlank handler: LD A,$77
+        rom[0x40..0x42].copy_from_slice(&[0x3E, 0x77]);
+        let mut cpu = Cpu::new(MemoryMapping::new(Rom { rom }));
+        cpu.memory.interrupt.set_ime_forced();
+        cpu.memory.interrupt.interrupt_enable.value = 1;
+        cpu.memory.interrupt.interrupt_flag.value = 1;
+
+        // Dispatch runs nothing at the vector
+        assert_eq!(cpu.step().unwrap(), 5);
+        assert_eq!((cpu.registers.pc, cpu.registers.sp), (0x40, 0xFFFC));
+        assert_eq!(cpu.registers.a, 0);
+        assert_eq!(cpu.memory.peek(0xFFFD), 0x01);
+        assert_eq!(cpu.memory.peek(0xFFFC), 0x00);
+        assert_eq!(cpu.memory.interrupt.interrupt_flag.value, 0);
+
+        cpu.step().unwrap();
+        assert_eq!((cpu.registers.pc, cpu.registers.a), (0x42, 0x77));
+    }
+
+    #[test]
+    fn vblank_interrupt() {
+        let mut cpu = Cpu::new(MemoryMapping::new(Rom {
+            rom: vec![0; 0x8000],
+        }));
+        cpu.memory.interrupt.set_ime_forced();
+        cpu.memory.interrupt.interrupt_enable.value = 1;
+
+        // The frame ends with VBlank requested, the next step dispatches it
+        assert!(cpu.run_frame(|_, _, result| result.is_err()));
+        assert_eq!(cpu.memory.interrupt.interrupt_flag.value & 1, 1);
+        let pc = cpu.registers.pc;
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x40);
+        assert_eq!(cpu.call_stack.frames()[0].call_site, pc);
+        assert!(!cpu.memory.interrupt.ime());
+    }
+
+    #[test]
+    fn disabled_interrupt() {
+        let mut cpu = Cpu::new(MemoryMapping::new(Rom {
+            rom: vec![0; 0x8000],
+        }));
+        cpu.memory.interrupt.set_ime_forced();
+        cpu.memory.interrupt.interrupt_enable.value = 0b100;
+        cpu.memory.interrupt.interrupt_flag.value = 0b101;
+
+        // VBlank is requested but only the timer is enabled
+        assert_eq!(cpu.step().unwrap(), 5);
+        assert_eq!(cpu.registers.pc, 0x50);
+        assert_eq!(cpu.memory.interrupt.interrupt_flag.value, 0b001);
+
+        // Handlers aren't interrupted until they enable interrupts again
+        cpu.memory.interrupt.interrupt_enable.value = 0b101;
+        assert_eq!(cpu.step().unwrap(), 1);
+        assert_eq!(cpu.registers.pc, 0x51);
+    }
+
+    #[test]
+    fn speed_switch() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80;
+        // STOP
+        rom[0x100..0x102].copy_from_slice(&[0x10, 0x00]);
+        let mut cpu = Cpu::new(MemoryMapping::new(Rom { rom }));
+        cpu.memory.set(0xFF4D, 0x01).unwrap();
+
+        // STOP itself and 2050 M-cycles halted
+        assert_eq!(cpu.step().unwrap(), 2051);
+        assert!(cpu.memory.double_speed());
+        assert_eq!(cpu.step().unwrap(), 1);
+    }
+}
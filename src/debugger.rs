@@ -1,5 +1,5 @@
 use anyhow::Result;
-use imgui::{Context, StyleColor, TableFlags, Ui};
+use imgui::{Context, Ui};
 use imgui_sdl3_renderer::Renderer;
 use imgui_sdl3_support::SdlPlatform;
 use sdl3::{
@@ -7,7 +7,34 @@ use sdl3::{
     video::{Window, WindowContext},
 };
 
-use crate::instructions::Instruction;
+mod breakpoints;
+mod console;
+mod disassembly;
+mod expression;
+mod gdb;
+mod io;
+mod symbols;
+mod watchpoints;
+
+pub(crate) use breakpoints::Breakpoints;
+pub(crate) use console::Console;
+pub(crate) use disassembly::DisassemblyView;
+pub(crate) use gdb::GdbStub;
+pub(crate) use symbols::Symbols;
+pub(crate) use watchpoints::Watchpoints;
+
+use expression::Expression;
+
+use crate::{
+    cpu::Cpu,
+    disassembler::{self, Disassembly},
+    instructions::Instruction,
+    memory_mapping::MemoryMapping,
+    speed::Speed,
+};
+
+/// Entries of the history shown, the newest ones
+const HISTORY_LINES: usize = 200;
 
 #[derive(Debug, Default)]
 pub enum ExecutionState {
@@ -15,6 +42,85 @@ pub enum ExecutionState {
     Pause,
     Step,
     Execute,
+    /// Runs until the next VBlank, then pauses
+    Frame,
+    /// Runs until PC is back at `address` in the same or an outer frame, after a CALL or RST
+    StepOver {
+        address: u16,
+        sp: u16,
+    },
+    /// Runs until a return pops the frame whose return address is at `sp`
+    StepOut {
+        sp: u16,
+    },
+    RunTo(u16),
+    /// Runs until LY is no longer `ly`
+    Scanline(u8),
+    /// Undoes the last instruction of the history
+    StepBack,
+    /// Runs until the condition holds
+    Until(Expression),
+}
+
+impl ExecutionState {
+    /// Steps over calls, other instructions are a single step
+    pub fn step_over(cpu: &Cpu) -> Self {
+        let next = disassembler::disassemble(|addr| cpu.memory.peek(addr), cpu.registers.pc);
+        match next.instruction {
+            Instruction::CALL(..) | Instruction::RST(_) => Self::StepOver {
+                address: next.address.wrapping_add(next.bytes.len() as u16),
+                sp: cpu.registers.sp,
+            },
+            _ => Self::Step,
+        }
+    }
+
+    /// Leaves the innermost frame of the call stack, or the one with the return address
+    /// at SP when the call wasn't seen
+    pub fn step_out(cpu: &Cpu) -> Self {
+        let frame = cpu.call_stack.frames().last();
+        Self::StepOut {
+            sp: frame.map_or(cpu.registers.sp, |frame| frame.sp),
+        }
+    }
+
+    pub fn scanline(cpu: &Cpu) -> Self {
+        Self::Scanline(cpu.memory.vram.y_coord)
+    }
+
+    pub fn running(&self) -> bool {
+        !matches!(self, Self::Pause | Self::Step | Self::StepBack)
+    }
+
+    /// Whether the run is over after the instruction at `pc`
+    pub fn finished(&self, cpu: &Cpu, pc: u16) -> bool {
+        let registers = &cpu.registers;
+        match *self {
+            Self::StepOver { address, sp } => registers.pc == address && registers.sp >= sp,
+            Self::StepOut { sp } => {
+                // RET, RETI and taken conditional returns that popped the frame's return
+                // address, returns of calls made after popping from the frame stay below
+                let ret = matches!(cpu.memory.peek(pc), 0xC9 | 0xD9 | 0xC0 | 0xC8 | 0xD0 | 0xD8);
+                ret && registers.sp > sp
+            }
+            Self::RunTo(address) => registers.pc == address,
+            Self::Scanline(ly) => cpu.memory.vram.y_coord != ly,
+            Self::Until(ref condition) => condition.is_true(&expression::Context {
+                registers,
+                memory: &cpu.memory,
+                hits: 0,
+            }),
+            _ => false,
+        }
+    }
+}
+
+/// State of the emulator driven by the console or GDB, the same the windows edit
+pub(crate) struct Target<'a, 'b> {
+    pub cpu: &'a mut Cpu<'b>,
+    pub breakpoints: &'a mut Breakpoints,
+    pub watchpoints: &'a mut Watchpoints,
+    pub execution_state: &'a mut ExecutionState,
 }
 
 pub struct Debugger<'a> {
@@ -23,7 +129,13 @@ pub struct Debugger<'a> {
     pub renderer: Renderer<'a>,
 
     pub execution_state: ExecutionState,
-    pub breakpoints: Vec<u16>,
+    pub breakpoints: Breakpoints,
+    pub watchpoints: Watchpoints,
+    pub disassembly: DisassemblyView,
+    pub run_to_input: String,
+    /// Labels loaded from the ROM's `.sym` file
+    pub symbols: Symbols,
+    pub console: Console,
 }
 
 impl<'a> Debugger<'a> {
@@ -48,21 +160,30 @@ impl<'a> Debugger<'a> {
             platform,
             renderer,
             execution_state: ExecutionState::default(),
-            breakpoints: Vec::new(),
+            breakpoints: Breakpoints::default(),
+            watchpoints: Watchpoints::default(),
+            disassembly: DisassemblyView::default(),
+            run_to_input: String::new(),
+            symbols: Symbols::default(),
+            console: Console::default(),
         })
     }
 
-    pub fn should_execute(&mut self) -> bool {
-        match self.execution_state {
-            ExecutionState::Pause => false,
-            ExecutionState::Execute => true,
-            ExecutionState::Step => {
-                self.execution_state = ExecutionState::Pause;
-                true
-            }
+    /// Called when the PPU enters VBlank, which also ends the scanline
+    pub fn frame_finished(&mut self) {
+        if let ExecutionState::Frame | ExecutionState::Scanline(_) = self.execution_state {
+            self.execution_state = ExecutionState::Pause;
         }
     }
 
+    /// Pause hotkey
+    pub fn toggle_pause(&mut self) {
+        self.execution_state = match self.execution_state.running() {
+            true => ExecutionState::Pause,
+            false => ExecutionState::Execute,
+        };
+    }
+
     pub fn render<'b>(
         &mut self,
         canvas: &mut Canvas<Window>,
@@ -76,12 +197,18 @@ impl<'a> Debugger<'a> {
     pub fn display_execution_debugger(
         ui: &mut Ui,
         execution_state: &mut ExecutionState,
-        instruction: Instruction,
+        speed: &mut Speed,
+        cpu: &Cpu,
+        symbols: &Symbols,
+        run_to_input: &mut String,
     ) -> bool {
         let mut reset = false;
+        let next: Disassembly =
+            disassembler::disassemble(|addr| cpu.memory.peek(addr), cpu.registers.pc);
+        let location = symbols.describe(&cpu.memory, cpu.registers.pc);
 
         ui.window("Execution")
-            .size([400., 150.], imgui::Condition::FirstUseEver)
+            .size([400., 200.], imgui::Condition::FirstUseEver)
             .build(|| {
                 ui.text(format!("{} fps", ui.io().framerate as usize));
 
@@ -90,68 +217,280 @@ impl<'a> Debugger<'a> {
                 }
                 ui.same_line();
 
-                let mut pause = !matches!(execution_state, ExecutionState::Execute);
-                ui.checkbox("Pause", &mut pause);
-                if ui.button("Step") && pause {
-                    *execution_state = ExecutionState::Step
-                } else {
+                let mut pause = !execution_state.running();
+                if ui.checkbox("Pause", &mut pause) {
                     *execution_state = match pause {
                         true => ExecutionState::Pause,
                         false => ExecutionState::Execute,
                     }
                 }
+                if ui.button("Step") && pause {
+                    *execution_state = ExecutionState::Step
+                }
+                ui.same_line();
+                if ui.button("Step Over") && pause {
+                    *execution_state = ExecutionState::step_over(cpu)
+                }
+                ui.same_line();
+                if ui.button("Step Out") && pause {
+                    *execution_state = ExecutionState::step_out(cpu)
+                }
+                if ui.button("Scanline") {
+                    *execution_state = ExecutionState::scanline(cpu)
+                }
+                ui.same_line();
+                if ui.button("Frame") {
+                    *execution_state = ExecutionState::Frame
+                }
+                ui.same_line();
+                ui.set_next_item_width(100.);
+                // Hex address or symbol name
+                let run_to = ui
+                    .input_text("###run_to", run_to_input)
+                    .hint("Address")
+                    .enter_returns_true(true)
+                    .build();
+                ui.same_line();
+                if (ui.button("Run To") || run_to)
+                    && let Some(address) = symbols.address(run_to_input.trim()).or_else(|| {
+                        u16::from_str_radix(run_to_input.trim().trim_start_matches('$'), 16).ok()
+                    })
+                {
+                    *execution_state = ExecutionState::RunTo(address)
+                }
+                speed.display(ui);
+
                 if pause {
-                    ui.text(format!("Next Instruction: {instruction}"));
+                    match location {
+                        Some(location) => {
+                            ui.text(format!("PC: ${:04X} ({location})", next.address))
+                        }
+                        None => ui.text(format!("PC: ${:04X}", next.address)),
+                    }
+                    ui.text(format!("Next Instruction: {} ({})", next.text, next.hex()));
                 }
             });
         reset
     }
 
-    pub fn display_breakpoint_debugger(ui: &mut Ui, breakpoints: &mut Vec<u16>, pc: u16) {
+    /// Last executed instructions with the registers before them and their writes
+    pub fn display_history(
+        ui: &mut Ui,
+        cpu: &Cpu,
+        execution_state: &mut ExecutionState,
+        disassembly: &mut DisassemblyView,
+    ) {
+        ui.window("History")
+            .size([600., 300.], imgui::Condition::FirstUseEver)
+            .position([50., 600.], imgui::Condition::FirstUseEver)
+            .build(|| {
+                let Some(history) = &cpu.history else {
+                    ui.text("History is only recorded in debug mode");
+                    return;
+                };
+                let pause = !execution_state.running();
+                if ui.button("Step Back") && pause {
+                    *execution_state = ExecutionState::StepBack;
+                }
+                ui.same_line();
+                ui.text(format!("{} instructions", history.entries().len()));
+
+                ui.child_window("history_entries").build(|| {
+                    let entries = history.entries();
+                    let start = entries.len().saturating_sub(HISTORY_LINES);
+                    for (i, entry) in entries.iter().enumerate().skip(start) {
+                        let pc = entry.pc();
+                        let (hex, instruction) = match entry.interrupt {
+                            Some(vector) => (String::new(), format!("[interrupt] ${vector:04X}")),
+                            None => {
+                                let read = |addr: u16| {
+                                    let offset = addr.wrapping_sub(pc) as usize;
+                                    entry.bytes.get(offset).copied().unwrap_or(0)
+                                };
+                                let line = disassembler::disassemble(read, pc);
+                                (line.hex(), line.text)
+                            }
+                        };
+                        let r = &entry.registers;
+                        let mut text = format!(
+                            "${pc:04X}  {:<8}  {:<16} AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X}",
+                            hex,
+                            instruction,
+                            r.af,
+                            r.bc,
+                            r.de,
+                            r.hl,
+                            r.sp
+                        );
+                        for write in &entry.writes {
+                            text += &format!(
+                                "  [{:04X}] {:02X}->{:02X}",
+                                write.address, write.old, write.new
+                            );
+                        }
+                        if ui.selectable(format!("{text}###history{i}")) {
+                            disassembly.goto(&cpu.memory, pc);
+                        }
+                    }
+                    // Newest entries stay in view while running
+                    if !pause {
+                        ui.set_scroll_here_y_with_ratio(1.);
+                    }
+                });
+            });
+    }
+
+    /// Frames of the shadow call stack, innermost first, clicking one shows it in the
+    /// disassembly
+    pub fn display_call_stack(
+        ui: &mut Ui,
+        cpu: &Cpu,
+        symbols: &Symbols,
+        disassembly: &mut DisassemblyView,
+    ) {
+        ui.window("Call Stack")
+            .size([300., 200.], imgui::Condition::FirstUseEver)
+            .position([850., 600.], imgui::Condition::FirstUseEver)
+            .build(|| {
+                let memory = &cpu.memory;
+                let describe = |address: u16| match symbols.describe(memory, address) {
+                    Some(location) => format!("${address:04X} {location}"),
+                    None => format!("${address:04X}"),
+                };
+
+                // Every frame is at the call site of the frame above it
+                let frames = cpu.call_stack.frames();
+                let mut location = cpu.registers.pc;
+                for (i, frame) in frames.iter().rev().enumerate() {
+                    let interrupt = if frame.interrupt { " [interrupt]" } else { "" };
+                    let label = format!(
+                        "#{i} {}  in {}{interrupt}###call_stack{i}",
+                        describe(location),
+                        describe(frame.target)
+                    );
+                    if ui.selectable_config(label).selected(i == 0).build() {
+                        disassembly.goto(memory, location);
+                    }
+                    location = frame.call_site;
+                }
+                let label = format!("#{} {}###call_stack_root", frames.len(), describe(location));
+                if ui
+                    .selectable_config(label)
+                    .selected(frames.is_empty())
+                    .build()
+                {
+                    disassembly.goto(memory, location);
+                }
+            });
+    }
+
+    pub fn display_breakpoint_debugger(
+        ui: &mut Ui,
+        breakpoints: &mut Breakpoints,
+        watchpoints: &mut Watchpoints,
+        pc: u16,
+        memory: &MemoryMapping,
+        symbols: &Symbols,
+    ) {
         ui.window("Breakpoints")
-            .size([200., 200.], imgui::Condition::FirstUseEver)
+            .size([300., 300.], imgui::Condition::FirstUseEver)
             .position([850., 50.], imgui::Condition::FirstUseEver)
             .build(|| {
-                if ui.button("Break at current PC") && !breakpoints.contains(&pc) {
-                    breakpoints.push(pc);
+                if ui.button("Break at current PC") {
+                    breakpoints.add(pc);
                 }
                 ui.text("0x");
                 ui.same_line();
                 let mut str = String::from("0000");
+                // Hex address or symbol name
                 if ui
                     .input_text("###breakpoint_input", &mut str)
                     .enter_returns_true(true)
                     .build()
-                    && let Ok(n) = u16::from_str_radix(&str, 16)
-                    && !breakpoints.contains(&n)
+                    && let Some(n) = symbols
+                        .address(str.trim())
+                        .or_else(|| u16::from_str_radix(str.trim(), 16).ok())
                 {
-                    breakpoints.push(n);
+                    breakpoints.add(n);
                 }
 
-                if let Some(_table) =
-                    ui.begin_table_with_flags("breakpoints", 2, TableFlags::SIZING_FIXED_FIT)
-                {
-                    ui.table_setup_column("");
-                    ui.table_setup_column("Address");
-                    ui.table_headers_row();
-
-                    for (i, addr) in breakpoints.clone().iter().enumerate() {
-                        ui.table_next_row();
-                        ui.table_set_column_index(0);
-                        if ui.button(format!("-###{addr}")) {
-                            breakpoints.remove(i);
-                        }
-                        ui.table_set_column_index(1);
+                breakpoints.display(ui, pc, memory, symbols);
 
-                        let color = if &pc == addr {
-                            [0., 1., 0., 1.]
-                        } else {
-                            [1., 1., 1., 1.]
-                        };
-                        let _text_color = ui.push_style_color(StyleColor::Text, color);
-                        ui.text(format!("0x{addr:04X}"));
-                    }
-                }
+                watchpoints.display(ui, symbols);
             });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ExecutionState;
+    use crate::{
+        cpu::Cpu,
+        memory_mapping::{MemoryMapping, Rom},
+    };
+
+    /// Steps until the state is finished, like the frontend loop
+    fn run(cpu: &mut Cpu, state: &ExecutionState) {
+        for _ in 0..100 {
+            let pc = cpu.registers.pc;
+            cpu.step().unwrap();
+            if state.finished(cpu, pc) {
+                return;
+            }
+        }
+        panic!("{state:?} didn't finish");
+    }
+
+    #[test]
+    fn step_over_and_out() {
+        let mut rom = vec![0; 0x8000];
+        // CALL $0200; NOP
+        rom[0x100..0x103].copy_from_slice(&[0xCD, 0x00, 0x02]);
+        // $0200: NOP; CALL $0300; RET
+        rom[0x200..0x205].copy_from_slice(&[0x00, 0xCD, 0x00, 0x03, 0xC9]);
+        // $0300: RET
+        rom[0x300] = 0xC9;
+        let mut cpu = Cpu::new(MemoryMapping::new(Rom { rom }));
+
+        let state = ExecutionState::step_over(&cpu);
+        run(&mut cpu, &state);
+        assert_eq!(cpu.registers.pc, 0x103);
+        assert_eq!(cpu.registers.sp, 0xFFFE);
+        assert!(matches!(
+            ExecutionState::step_over(&cpu),
+            ExecutionState::Step
+        ));
+
+        cpu.registers.pc = 0x100;
+        cpu.step().unwrap();
+        // The inner call returns before the frame of the outer one
+        let state = ExecutionState::step_out(&cpu);
+        run(&mut cpu, &state);
+        assert_eq!(cpu.registers.pc, 0x103);
+
+        cpu.registers.pc = 0x100;
+        let state = ExecutionState::RunTo(0x300);
+        run(&mut cpu, &state);
+        assert_eq!(cpu.registers.pc, 0x300);
+    }
+
+    #[test]
+    fn step_out_after_pop() {
+        let mut rom = vec![0; 0x8000];
+        // CALL $0200; NOP
+        rom[0x100..0x103].copy_from_slice(&[0xCD, 0x00, 0x02]);
+        // $0200: PUSH BC; POP BC; CALL $0300; RET
+        rom[0x200..0x206].copy_from_slice(&[0xC5, 0xC1, 0xCD, 0x00, 0x03, 0xC9]);
+        // $0300: RET
+        rom[0x300] = 0xC9;
+        let mut cpu = Cpu::new(MemoryMapping::new(Rom { rom }));
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        // SP is above the one at the request once the inner call returns
+        let state = ExecutionState::step_out(&cpu);
+        run(&mut cpu, &state);
+        assert_eq!(cpu.registers.pc, 0x103);
+        assert_eq!(cpu.registers.sp, 0xFFFE);
+    }
+}
@@ -0,0 +1,302 @@
+use anyhow::{Result, bail};
+use imgui::{StyleColor, TableFlags, Ui};
+
+use super::{
+    Symbols,
+    expression::{Context, Expression},
+};
+use crate::{memory_mapping::MemoryMapping, registers::Registers};
+
+/// Lines of logpoint output kept for the Breakpoints window
+const LOG_LINES: usize = 200;
+
+/// Message of a logpoint, expressions in braces are replaced by their value, e.g. `A={A}`
+#[derive(Debug, Clone)]
+pub(crate) struct LogMessage {
+    parts: Vec<LogPart>,
+}
+
+#[derive(Debug, Clone)]
+enum LogPart {
+    Text(String),
+    Expression(Expression),
+}
+
+impl LogMessage {
+    pub fn parse(text: &str, symbols: &Symbols) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}') else {
+                bail!("missing `}}`");
+            };
+            parts.push(LogPart::Text(rest[..start].to_string()));
+            let expression = &rest[start + 1..start + end];
+            parts.push(LogPart::Expression(Expression::parse(expression, symbols)?));
+            rest = &rest[start + end + 1..];
+        }
+        parts.push(LogPart::Text(rest.to_string()));
+        Ok(Self { parts })
+    }
+
+    /// Values are shown in hex
+    pub fn format(&self, context: &Context) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                LogPart::Text(text) => text.clone(),
+                LogPart::Expression(expression) => match expression.evaluate(context) {
+                    value @ 0..=0xFF => format!("${value:02X}"),
+                    value @ 0x100..=0xFFFF => format!("${value:04X}"),
+                    value => value.to_string(),
+                },
+            })
+            .collect()
+    }
+}
+
+/// Pauses before the instruction at `address` runs, when its condition holds
+///
+/// Logpoints add their message to the log instead of pausing.
+#[derive(Debug, Clone)]
+pub(crate) struct Breakpoint {
+    pub address: u16,
+    pub condition: Option<Expression>,
+    pub log: Option<LogMessage>,
+    /// Times the address was reached, whether the condition held or not
+    pub hits: u32,
+
+    condition_input: String,
+    log_input: String,
+    error: Option<String>,
+}
+
+impl Breakpoint {
+    pub fn new(address: u16) -> Self {
+        Self {
+            address,
+            condition: None,
+            log: None,
+            hits: 0,
+            condition_input: String::new(),
+            log_input: String::new(),
+            error: None,
+        }
+    }
+
+    /// Parses the condition, an empty one always breaks
+    pub fn set_condition(&mut self, text: &str, symbols: &Symbols) -> Result<()> {
+        self.condition_input = text.to_string();
+        self.condition = match text.trim() {
+            "" => None,
+            text => Some(Expression::parse(text, symbols)?),
+        };
+        Ok(())
+    }
+
+    /// Parses the logpoint message, an empty one pauses instead
+    pub fn set_log(&mut self, text: &str, symbols: &Symbols) -> Result<()> {
+        self.log_input = text.to_string();
+        self.log = match text {
+            "" => None,
+            text => Some(LogMessage::parse(text, symbols)?),
+        };
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Breakpoints {
+    pub list: Vec<Breakpoint>,
+    /// Messages of logpoints, oldest first
+    pub log: Vec<String>,
+}
+
+impl Breakpoints {
+    pub fn contains(&self, address: u16) -> bool {
+        self.list.iter().any(|b| b.address == address)
+    }
+
+    pub fn add(&mut self, address: u16) {
+        if !self.contains(address) {
+            self.list.push(Breakpoint::new(address));
+        }
+    }
+
+    pub fn remove(&mut self, address: u16) {
+        self.list.retain(|b| b.address != address);
+    }
+
+    pub fn toggle(&mut self, address: u16) {
+        match self.contains(address) {
+            true => self.remove(address),
+            false => self.add(address),
+        }
+    }
+
+    /// Called before the instruction at PC runs, true when execution should pause
+    pub fn check(&mut self, registers: &Registers, memory: &MemoryMapping) -> bool {
+        let mut pause = false;
+        for breakpoint in self.list.iter_mut() {
+            if breakpoint.address != registers.pc {
+                continue;
+            }
+            breakpoint.hits += 1;
+            let context = Context {
+                registers,
+                memory,
+                hits: breakpoint.hits,
+            };
+            if breakpoint
+                .condition
+                .as_ref()
+                .is_some_and(|condition| !condition.is_true(&context))
+            {
+                continue;
+            }
+            match &breakpoint.log {
+                Some(message) => {
+                    let message = message.format(&context);
+                    if self.log.len() == LOG_LINES {
+                        self.log.remove(0);
+                    }
+                    self.log.push(message);
+                }
+                None => pause = true,
+            }
+        }
+        pause
+    }
+
+    /// Table of breakpoints with their condition and log message editors
+    pub fn display(&mut self, ui: &Ui, pc: u16, memory: &MemoryMapping, symbols: &Symbols) {
+        let mut remove = None;
+        if let Some(_table) =
+            ui.begin_table_with_flags("breakpoints", 6, TableFlags::SIZING_FIXED_FIT)
+        {
+            ui.table_setup_column("");
+            ui.table_setup_column("Address");
+            ui.table_setup_column("Symbol");
+            ui.table_setup_column("Hits");
+            ui.table_setup_column("Condition");
+            ui.table_setup_column("Log");
+            ui.table_headers_row();
+
+            for breakpoint in self.list.iter_mut() {
+                let addr = breakpoint.address;
+                ui.table_next_row();
+                ui.table_set_column_index(0);
+                if ui.button(format!("-###{addr}")) {
+                    remove = Some(addr);
+                }
+                ui.table_set_column_index(1);
+
+                let color = if pc == addr {
+                    [0., 1., 0., 1.]
+                } else {
+                    [1., 1., 1., 1.]
+                };
+                {
+                    let _text_color = ui.push_style_color(StyleColor::Text, color);
+                    ui.text(format!("0x{addr:04X}"));
+                }
+                ui.table_set_column_index(2);
+                ui.text(symbols.describe(memory, addr).unwrap_or_default());
+                ui.table_set_column_index(3);
+                ui.text(breakpoint.hits.to_string());
+
+                // Errors are shown under the inputs until the next edit
+                ui.table_set_column_index(4);
+                ui.set_next_item_width(160.);
+                if ui
+                    .input_text(
+                        format!("###condition{addr}"),
+                        &mut breakpoint.condition_input,
+                    )
+                    .hint("A == $3F")
+                    .enter_returns_true(true)
+                    .build()
+                {
+                    let condition = breakpoint.condition_input.clone();
+                    breakpoint.error = breakpoint
+                        .set_condition(&condition, symbols)
+                        .err()
+                        .map(|e| format!("Condition: {e}"));
+                }
+                ui.table_set_column_index(5);
+                ui.set_next_item_width(160.);
+                if ui
+                    .input_text(format!("###log{addr}"), &mut breakpoint.log_input)
+                    .hint("A={A}")
+                    .enter_returns_true(true)
+                    .build()
+                {
+                    let log = breakpoint.log_input.clone();
+                    breakpoint.error = breakpoint
+                        .set_log(&log, symbols)
+                        .err()
+                        .map(|e| format!("Log: {e}"));
+                }
+                if let Some(error) = &breakpoint.error {
+                    ui.table_next_row();
+                    ui.table_set_column_index(4);
+                    ui.text_colored([1., 0.3, 0.3, 1.], error);
+                }
+            }
+        }
+        if let Some(addr) = remove {
+            self.remove(addr);
+        }
+
+        if !self.log.is_empty() {
+            ui.text("Log");
+            ui.same_line();
+            if ui.small_button("Clear###breakpoint_log_clear") {
+                self.log.clear();
+            }
+            ui.child_window("breakpoint_log")
+                .size([0., 100.])
+                .build(|| {
+                    for line in &self.log {
+                        ui.text(line);
+                    }
+                });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Breakpoints, LogMessage};
+    use crate::{debugger::Symbols, memory_mapping::MemoryMapping, registers::Registers};
+
+    #[test]
+    fn conditions_and_logpoints() {
+        let symbols = Symbols::default();
+        let mut breakpoints = Breakpoints::default();
+        breakpoints.add(0x150);
+        breakpoints.list[0]
+            .set_condition("hits >= 3", &symbols)
+            .unwrap();
+        breakpoints.add(0x200);
+        breakpoints.list[1]
+            .set_log("A={A} HL={HL} hits {hits}", &symbols)
+            .unwrap();
+
+        let mut registers = Registers::new();
+        let memory = MemoryMapping::default();
+        registers.pc = 0x150;
+        assert!(!breakpoints.check(&registers, &memory));
+        assert!(!breakpoints.check(&registers, &memory));
+        assert!(breakpoints.check(&registers, &memory));
+
+        registers.pc = 0x200;
+        registers.a = 0x12;
+        registers.h = 0xC0;
+        assert!(!breakpoints.check(&registers, &memory));
+        assert_eq!(breakpoints.log, ["A=$12 HL=$C000 hits $01"]);
+
+        assert!(breakpoints.list[0].set_condition("A ==", &symbols).is_err());
+        assert!(LogMessage::parse("A={A", &symbols).is_err());
+    }
+}
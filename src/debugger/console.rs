@@ -0,0 +1,482 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result, bail};
+use imgui::{
+    FocusedWidget, HistoryDirection, InputTextCallback, InputTextCallbackHandler, TextCallbackData,
+    Ui,
+};
+
+use super::{
+    ExecutionState, Symbols, Target,
+    expression::{self, Context, Expression, IO_REGISTERS},
+};
+use crate::{
+    save_state,
+    trace::Trace,
+    watchpoint::{WatchKind, Watchpoint},
+};
+
+/// Lines of output kept
+const OUTPUT_LINES: usize = 500;
+/// Bytes shown by `x` without a count
+const EXAMINE_LEN: u16 = 16;
+/// Most bytes shown by `x`, 256 lines that fit in the output
+const EXAMINE_MAX: u16 = 0x1000;
+/// Most instructions run by `step`, about a frame, since the UI waits for them
+const STEP_MAX: u64 = 0x4000;
+
+const COMMANDS: [(&str, &str); 14] = [
+    ("b", "b <addr>: add a breakpoint"),
+    ("d", "d <addr>: delete a breakpoint"),
+    (
+        "w",
+        "w <addr> [end] [r|w|rw|=value]: add a watchpoint, on writes by default",
+    ),
+    ("x", "x[/count] <addr>: show memory"),
+    ("set", "set <register>=<value> or set [addr]=<value>"),
+    (
+        "fill",
+        "fill <start> <end> <value>: write a range of memory",
+    ),
+    (
+        "step",
+        "step [count]: run up to $4000 instructions, stopping at breakpoints",
+    ),
+    ("until", "until <condition>: run until the condition holds"),
+    ("c", "c: continue"),
+    (
+        "trace",
+        "trace on [file] or trace off: log instructions, to <rom>.trace by default",
+    ),
+    ("save", "save state <slot>"),
+    ("load", "load state <slot>"),
+    ("clear", "clear: empty the output"),
+    ("help", "help: list the commands"),
+];
+
+const REGISTERS: [&str; 18] = [
+    "A", "B", "C", "D", "E", "F", "H", "L", "AF", "BC", "DE", "HL", "SP", "PC", "ZF", "NF", "HF",
+    "CF",
+];
+
+/// Typed debugger commands, e.g. `b $0150` or `x/16 $FF40`
+///
+/// Arguments are expressions like breakpoint conditions, so numbers are decimal unless
+/// written `$FF40` or `0xFF40`, and registers and symbols can be used.
+#[derive(Debug, Default)]
+pub(crate) struct Console {
+    input: String,
+    /// Commands entered, oldest first
+    history: Vec<String>,
+    /// Entry shown while browsing the history with the arrow keys
+    history_position: Option<usize>,
+    output: Vec<String>,
+}
+
+impl Console {
+    pub fn display(&mut self, ui: &Ui, target: &mut Target, symbols: &Symbols, rom: &Path) {
+        ui.window("Console")
+            .size([500., 300.], imgui::Condition::FirstUseEver)
+            .position([50., 900.], imgui::Condition::FirstUseEver)
+            .build(|| {
+                let footer = ui.frame_height_with_spacing();
+                ui.child_window("console_output")
+                    .size([0., -footer])
+                    .build(|| {
+                        for line in &self.output {
+                            ui.text(line);
+                        }
+                        // Follows new output unless scrolled up
+                        if ui.scroll_y() >= ui.scroll_max_y() {
+                            ui.set_scroll_here_y_with_ratio(1.);
+                        }
+                    });
+
+                let completion = Completion {
+                    history: &self.history,
+                    position: &mut self.history_position,
+                    symbols,
+                    output: &mut self.output,
+                };
+                ui.set_next_item_width(-1.);
+                if ui
+                    .input_text("###console_input", &mut self.input)
+                    .hint("help")
+                    .enter_returns_true(true)
+                    .callback(
+                        InputTextCallback::COMPLETION | InputTextCallback::HISTORY,
+                        completion,
+                    )
+                    .build()
+                {
+                    let line = std::mem::take(&mut self.input);
+                    self.run(&line, target, symbols, rom);
+                    // Enter deactivates the input, it keeps the focus for the next command
+                    ui.set_keyboard_focus_here_with_offset(FocusedWidget::Previous);
+                }
+            });
+    }
+
+    /// Runs a line and adds it with its result to the output and history
+    pub fn run(&mut self, line: &str, target: &mut Target, symbols: &Symbols, rom: &Path) {
+        let line = line.trim();
+        self.history_position = None;
+        if line.is_empty() {
+            return;
+        }
+        if self.history.last().is_none_or(|last| last != line) {
+            self.history.push(line.to_string());
+        }
+        self.print(format!("> {line}"));
+        if let Err(e) = self.execute(line, target, symbols, rom) {
+            self.print(format!("error: {e:#}"));
+        }
+    }
+
+    fn execute(
+        &mut self,
+        line: &str,
+        target: &mut Target,
+        symbols: &Symbols,
+        rom: &Path,
+    ) -> Result<()> {
+        let (command, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let args = args.trim();
+        let words: Vec<&str> = args.split_whitespace().collect();
+        // `x/16`
+        let (command, count) = match command.split_once('/') {
+            Some((command, count)) => (command, Some(count)),
+            None => (command, None),
+        };
+        let value = |text: &str| -> Result<i64> {
+            let context = Context {
+                registers: &target.cpu.registers,
+                memory: &target.cpu.memory,
+                hits: 0,
+            };
+            Ok(Expression::parse(text, symbols)?.evaluate(&context))
+        };
+        let address = |text: &str| -> Result<u16> {
+            let address = value(text)?;
+            u16::try_from(address).with_context(|| format!("`{text}` isn't an address"))
+        };
+
+        match command {
+            "b" | "break" => {
+                let address = address(args)?;
+                target.breakpoints.add(address);
+                self.print(format!("Breakpoint at ${address:04X}"));
+            }
+            "d" | "delete" => {
+                let address = address(args)?;
+                if !target.breakpoints.contains(address) {
+                    bail!("no breakpoint at ${address:04X}");
+                }
+                target.breakpoints.remove(address);
+            }
+            "w" | "watch" => {
+                let Some((start, rest)) = words.split_first() else {
+                    bail!("usage: w <addr> [end] [r|w|rw|=value]");
+                };
+                let start = address(start)?;
+                let (mut end, mut kind) = (start, WatchKind::Write);
+                for word in rest {
+                    match word.parse() {
+                        Ok(parsed) => kind = parsed,
+                        Err(_) => end = address(word)?,
+                    }
+                }
+                let watchpoint = Watchpoint::new(start, end, kind);
+                if !target.watchpoints.list.contains(&watchpoint) {
+                    target.watchpoints.list.push(watchpoint);
+                }
+                self.print(format!("Watchpoint {watchpoint}"));
+            }
+            "x" => {
+                let len = match count {
+                    Some(count) => count
+                        .parse()
+                        .with_context(|| format!("invalid count `{count}`"))?,
+                    None => EXAMINE_LEN,
+                };
+                let len = len.min(EXAMINE_MAX);
+                let start = address(args)?;
+                for line_start in (0..len).step_by(16) {
+                    let line_address = start.wrapping_add(line_start);
+                    let bytes: Vec<String> = (line_start..len.min(line_start.saturating_add(16)))
+                        .map(|i| format!("{:02X}", target.cpu.memory.peek(start.wrapping_add(i))))
+                        .collect();
+                    self.print(format!("${line_address:04X}: {}", bytes.join(" ")));
+                }
+            }
+            "set" => {
+                let Some((name, text)) = args.split_once('=') else {
+                    bail!("usage: set <register>=<value> or set [addr]=<value>");
+                };
+                let (name, new) = (name.trim(), value(text)?);
+                match name.strip_prefix('[').and_then(|n| n.strip_suffix(']')) {
+                    Some(inner) => {
+                        let address = address(inner)?;
+                        *target.cpu.memory.get_mut(address)? = new as u8;
+                    }
+                    None => expression::set_register(&mut target.cpu.registers, name, new)?,
+                }
+            }
+            "fill" => {
+                let [start, end, byte] = words[..] else {
+                    bail!("usage: fill <start> <end> <value>");
+                };
+                let (start, end, byte) = (address(start)?, address(end)?, value(byte)? as u8);
+                for address in start.min(end)..=start.max(end) {
+                    if let Ok(value) = target.cpu.memory.get_mut(address) {
+                        *value = byte;
+                    }
+                }
+            }
+            "step" => {
+                let count = match args {
+                    "" => 1,
+                    count => value(count)?,
+                };
+                if target.execution_state.running() {
+                    bail!("pause before stepping");
+                }
+                let message = step(target, (count.max(0) as u64).min(STEP_MAX))?;
+                self.print(message);
+            }
+            "until" => {
+                let condition = Expression::parse(args, symbols)?;
+                *target.execution_state = ExecutionState::Until(condition);
+            }
+            "c" | "continue" => *target.execution_state = ExecutionState::Execute,
+            "trace" => match words[..] {
+                ["on", ..] => {
+                    let path = match words.get(1) {
+                        Some(path) => PathBuf::from(path),
+                        None => rom.with_extension("trace"),
+                    };
+                    let file = File::create(&path).with_context(|| {
+                        format!("Couldn't create trace file {}", path.display())
+                    })?;
+                    target.cpu.trace = Some(Trace::new(BufWriter::new(file), false));
+                    self.print(format!("Tracing to {}", path.display()));
+                }
+                ["off"] => target.cpu.trace = None,
+                _ => bail!("usage: trace on [file] or trace off"),
+            },
+            "save" | "load" => {
+                let ["state", slot] = words[..] else {
+                    bail!("usage: {command} state <slot>");
+                };
+                let slot: u8 = slot
+                    .parse()
+                    .with_context(|| format!("invalid slot `{slot}`"))?;
+                match command {
+                    "save" => save_state::save_slot(target.cpu, rom, slot)?,
+                    _ => save_state::load_slot(target.cpu, rom, slot)?,
+                }
+            }
+            "clear" => self.output.clear(),
+            "help" => {
+                for (_, help) in COMMANDS {
+                    self.print(help.to_string());
+                }
+            }
+            _ => bail!("unknown command `{command}`, see `help`"),
+        }
+        Ok(())
+    }
+
+    fn print(&mut self, line: String) {
+        print_line(&mut self.output, line);
+    }
+}
+
+fn print_line(output: &mut Vec<String>, line: String) {
+    if output.len() == OUTPUT_LINES {
+        output.remove(0);
+    }
+    output.push(line);
+}
+
+/// Runs up to `count` instructions, stopping at breakpoints and watchpoints like the
+/// Execute button does
+fn step(target: &mut Target, count: u64) -> Result<String> {
+    let cpu = &mut *target.cpu;
+    cpu.memory.watchpoints.clone_from(&target.watchpoints.list);
+    for i in 1..=count {
+        let pc = cpu.registers.pc;
+        cpu.step()?;
+        if let Some(hit) = cpu.memory.take_watchpoint_hit() {
+            target.watchpoints.hit = Some((pc, hit));
+            return Ok(format!(
+                "Stopped after {i} instructions, PC ${pc:04X}: {hit}"
+            ));
+        }
+        if target.breakpoints.check(&cpu.registers, &cpu.memory) {
+            let pc = cpu.registers.pc;
+            return Ok(format!(
+                "Stopped after {i} instructions at breakpoint ${pc:04X}"
+            ));
+        }
+    }
+    Ok(format!("PC ${:04X}", cpu.registers.pc))
+}
+
+/// Tab completes commands, registers and symbols, the arrow keys browse the history
+struct Completion<'a> {
+    history: &'a [String],
+    position: &'a mut Option<usize>,
+    symbols: &'a Symbols,
+    output: &'a mut Vec<String>,
+}
+
+impl InputTextCallbackHandler for Completion<'_> {
+    fn on_completion(&mut self, mut data: TextCallbackData) {
+        let cursor = data.cursor_pos();
+        let text = &data.str()[..cursor];
+        let start = text.rfind([' ', '=', '[', '/']).map_or(0, |i| i + 1);
+        let word = text[start..].to_ascii_lowercase();
+        if word.is_empty() {
+            return;
+        }
+
+        let candidates: Vec<&str> = match start {
+            0 => COMMANDS.iter().map(|(name, _)| *name).collect(),
+            _ => REGISTERS
+                .into_iter()
+                .chain(IO_REGISTERS.iter().map(|(name, _)| *name))
+                .chain(self.symbols.names())
+                .collect(),
+        };
+        let mut matches: Vec<&str> = candidates
+            .into_iter()
+            .filter(|c| c.to_ascii_lowercase().starts_with(&word))
+            .collect();
+        matches.sort_unstable();
+        let Some(first) = matches.first() else {
+            return;
+        };
+
+        // Completes up to where the matches differ
+        let common = matches.iter().fold(first.len(), |len, m| {
+            first
+                .bytes()
+                .zip(m.bytes())
+                .take(len)
+                .take_while(|(a, b)| a.eq_ignore_ascii_case(b))
+                .count()
+        });
+        let mut completion = first[..common].to_string();
+        match matches.len() {
+            1 if start == 0 => completion.push(' '),
+            1 => {}
+            _ => print_line(self.output, matches.join(" ")),
+        }
+        data.remove_chars(start, cursor - start);
+        data.insert_chars(start, &completion);
+    }
+
+    fn on_history(&mut self, direction: HistoryDirection, mut data: TextCallbackData) {
+        let position = match (direction, *self.position) {
+            (HistoryDirection::Up, None) => self.history.len().checked_sub(1),
+            (HistoryDirection::Up, Some(position)) => Some(position.saturating_sub(1)),
+            (HistoryDirection::Down, Some(position)) if position + 1 < self.history.len() => {
+                Some(position + 1)
+            }
+            (HistoryDirection::Down, _) => None,
+        };
+        *self.position = position;
+        data.clear();
+        if let Some(position) = position {
+            data.push_str(&self.history[position]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::Console;
+    use crate::{
+        cpu::Cpu,
+        debugger::{Breakpoints, ExecutionState, Symbols, Target, Watchpoints},
+        memory_mapping::{MemoryMapping, Rom},
+        watchpoint::{WatchKind, Watchpoint},
+    };
+
+    #[test]
+    fn commands() {
+        let mut rom = vec![0; 0x8000];
+        // LD A,$12; LD ($C000),A; NOP
+        rom[0x100..0x106].copy_from_slice(&[0x3E, 0x12, 0xEA, 0x00, 0xC0, 0x00]);
+        let mut cpu = Cpu::new(MemoryMapping::new(Rom { rom }));
+        let mut breakpoints = Breakpoints::default();
+        let mut watchpoints = Watchpoints::default();
+        let mut execution_state = ExecutionState::Pause;
+        let mut target = Target {
+            cpu: &mut cpu,
+            breakpoints: &mut breakpoints,
+            watchpoints: &mut watchpoints,
+            execution_state: &mut execution_state,
+        };
+        let symbols = Symbols::parse("00:C000 wCounter").unwrap();
+        let mut console = Console::default();
+        let mut run = |line: &str| {
+            console.run(line, &mut target, &symbols, Path::new("test.gb"));
+            console.output.last().cloned().unwrap()
+        };
+
+        assert_eq!(run("b $0150"), "Breakpoint at $0150");
+        assert_eq!(run("w wCounter $C001 rw"), "Watchpoint $C000-$C001 rw");
+        assert_eq!(run("fill $C002 $C003 $AB"), "> fill $C002 $C003 $AB");
+        assert_eq!(run("x/4 $C000"), "$C000: 00 00 AB AB");
+        assert_eq!(
+            run("step 10"),
+            "Stopped after 2 instructions, PC $0102: write $C000: $00 -> $12"
+        );
+        run("set HL=$C0FF");
+        run("set [HL]=A+1");
+        assert_eq!(run("x/1 $C0FF"), "$C0FF: 13");
+        // Large counts are capped to 256 lines
+        assert!(run("x/65535 $0000").starts_with("$0FF0: "));
+        assert_eq!(run("set hits=1"), "error: unknown register `hits`");
+        assert_eq!(run("jump"), "error: unknown command `jump`, see `help`");
+        run("until LY == 0");
+
+        assert!(breakpoints.contains(0x150));
+        assert_eq!(
+            watchpoints.list,
+            [Watchpoint::new(0xC000, 0xC001, WatchKind::ReadWrite)]
+        );
+        assert!(matches!(execution_state, ExecutionState::Until(_)));
+        assert_eq!(cpu.registers.pc, 0x105);
+    }
+
+    #[test]
+    fn step_limit() {
+        let mut cpu = Cpu::new(MemoryMapping::new(Rom {
+            rom: vec![0; 0x8000],
+        }));
+        let mut target = Target {
+            cpu: &mut cpu,
+            breakpoints: &mut Breakpoints::default(),
+            watchpoints: &mut Watchpoints::default(),
+            execution_state: &mut ExecutionState::Pause,
+        };
+        let mut console = Console::default();
+        let symbols = Symbols::default();
+        console.run(
+            "step $FFFFFFFF",
+            &mut target,
+            &symbols,
+            Path::new("test.gb"),
+        );
+        assert_eq!(console.output.last().unwrap(), "PC $4100");
+    }
+}
@@ -0,0 +1,189 @@
+use imgui::{MouseButton, StyleColor, Ui};
+
+use super::{Breakpoints, ExecutionState, Symbols};
+use crate::{
+    disassembler::{self, Disassembly},
+    memory_mapping::MemoryMapping,
+};
+
+/// Instructions shown in the listing
+const LINES: usize = 128;
+/// Bytes disassembled before the address the listing is centered on
+const LOOKBEHIND: u16 = 64;
+
+/// Scrolling listing of the instructions around PC
+#[derive(Debug, Default)]
+pub(crate) struct DisassemblyView {
+    /// Address of the first line
+    top: u16,
+    /// Line to scroll to in the next frame
+    scroll_to: Option<u16>,
+    /// The listing follows PC whenever it changes
+    last_pc: Option<u16>,
+    goto_input: String,
+}
+
+impl DisassemblyView {
+    /// Centers the listing on `address`
+    pub fn goto(&mut self, memory: &MemoryMapping, address: u16) {
+        self.top = listing_start(memory, address);
+        self.scroll_to = Some(address);
+    }
+
+    pub fn display(
+        &mut self,
+        ui: &Ui,
+        memory: &MemoryMapping,
+        pc: u16,
+        breakpoints: &mut Breakpoints,
+        symbols: &Symbols,
+        execution_state: &mut ExecutionState,
+    ) {
+        if self.last_pc != Some(pc) {
+            self.last_pc = Some(pc);
+            self.goto(memory, pc);
+        }
+
+        ui.window("Disassembly")
+            .size([420., 500.], imgui::Condition::FirstUseEver)
+            .position([1250., 250.], imgui::Condition::FirstUseEver)
+            .build(|| {
+                ui.text("Go to");
+                ui.same_line();
+                ui.set_next_item_width(100.);
+                if ui
+                    .input_text("###disassembly_goto", &mut self.goto_input)
+                    .enter_returns_true(true)
+                    .build()
+                    && let Some(address) = symbols
+                        .address(self.goto_input.trim())
+                        .or_else(|| u16::from_str_radix(self.goto_input.trim(), 16).ok())
+                {
+                    self.goto(memory, address);
+                }
+                ui.same_line();
+                if ui.button("PC") {
+                    self.goto(memory, pc);
+                }
+                ui.same_line();
+                ui.text(format!("ROM bank {}", memory.rom.bank()));
+
+                ui.child_window("disassembly_listing").build(|| {
+                    let mut address = self.top;
+                    for _ in 0..LINES {
+                        let line = disassembler::disassemble(|addr| memory.peek(addr), address);
+                        if self.display_line(ui, memory, &line, pc, breakpoints, symbols) {
+                            *execution_state = ExecutionState::RunTo(line.address);
+                        }
+                        address = address.wrapping_add(line.bytes.len() as u16);
+                    }
+                });
+            });
+    }
+
+    /// Gutter toggling a breakpoint, then the instruction which follows its target on click
+    ///
+    /// Returns true when the line was right-clicked to run to it.
+    fn display_line(
+        &mut self,
+        ui: &Ui,
+        memory: &MemoryMapping,
+        line: &Disassembly,
+        pc: u16,
+        breakpoints: &mut Breakpoints,
+        symbols: &Symbols,
+    ) -> bool {
+        let address = line.address;
+        if let Some(label) = symbols.label(memory, address) {
+            ui.text_colored([0.9, 0.8, 0.3, 1.], format!("{label}:"));
+        }
+
+        // Conditional breakpoints show `?`, logpoints `L`
+        let gutter = match breakpoints.list.iter().find(|b| b.address == address) {
+            Some(b) if b.log.is_some() => "L",
+            Some(b) if b.condition.is_some() => "?",
+            Some(_) => "B",
+            None => "-",
+        };
+        {
+            let color = match gutter {
+                "-" => [0.4, 0.4, 0.4, 1.],
+                _ => [1., 0., 0., 1.],
+            };
+            let _text_color = ui.push_style_color(StyleColor::Text, color);
+            if ui.small_button(format!("{gutter}###disassembly_bp{address}")) {
+                breakpoints.toggle(address);
+            }
+        }
+        ui.same_line();
+
+        let color = if address == pc {
+            [0., 1., 0., 1.]
+        } else {
+            [1., 1., 1., 1.]
+        };
+        let _text_color = ui.push_style_color(StyleColor::Text, color);
+        let target = line.target();
+        let text = match target.and_then(|target| Some((target, symbols.label(memory, target)?))) {
+            Some((target, name)) => line.text.replace(&format!("${target:04X}"), name),
+            None => line.text.clone(),
+        };
+        let label = format!(
+            "{}:{address:04X}  {:<8}  {text}###disassembly_line{address}",
+            region(memory, address),
+            line.hex(),
+        );
+        if ui.selectable_config(label).selected(address == pc).build()
+            && let Some(target) = target
+        {
+            self.goto(memory, target);
+        }
+        let run_to = ui.is_item_clicked_with_button(MouseButton::Right);
+        if ui.is_item_hovered() {
+            match target {
+                Some(target) => ui.tooltip_text(format!(
+                    "Click to go to ${target:04X}, right-click to run to this line"
+                )),
+                None => ui.tooltip_text("Right-click to run to this line"),
+            }
+        }
+
+        if self.scroll_to.is_some_and(|scroll_to| address >= scroll_to) {
+            ui.set_scroll_here_y_with_ratio(0.5);
+            self.scroll_to = None;
+        }
+        run_to
+    }
+}
+
+/// Memory region of an address, the banked ROM area shows its current bank
+fn region(memory: &MemoryMapping, address: u16) -> String {
+    match address {
+        0x0000..0x4000 => "ROM0".to_string(),
+        0x4000..0x8000 => format!("ROM{:X}", memory.rom.bank()),
+        0x8000..0xA000 => "VRAM".to_string(),
+        0xA000..0xC000 => "SRAM".to_string(),
+        0xC000..0xE000 => "WRAM".to_string(),
+        0xE000..0xFE00 => "ECHO".to_string(),
+        0xFE00..0xFF00 => "OAM".to_string(),
+        0xFF00..0xFF80 => "I/O".to_string(),
+        _ => "HRAM".to_string(),
+    }
+}
+
+/// Start of a listing whose instructions line up with `address`,
+/// as far back as possible so lines before it are shown
+fn listing_start(memory: &MemoryMapping, address: u16) -> u16 {
+    let read = |addr| memory.peek(addr);
+    for back in (1..=LOOKBEHIND).rev() {
+        let start = address.wrapping_sub(back);
+        let mut offset = 0;
+        while offset < back {
+            offset += disassembler::instruction_len(read, start.wrapping_add(offset));
+        }
+        if offset == back {
+            return start;
+        }
+    }
+    address
+}
@@ -0,0 +1,446 @@
+use std::fmt;
+
+use anyhow::{Result, bail};
+
+use super::Symbols;
+use crate::{
+    memory_mapping::MemoryMapping,
+    registers::{Flags, RegisterU8, RegisterU16, Registers},
+};
+
+/// Hardware registers usable by name, e.g. `LY == 144`
+pub(crate) const IO_REGISTERS: [(&str, u16); 29] = [
+    ("P1", 0xFF00),
+    ("SB", 0xFF01),
+    ("SC", 0xFF02),
+    ("DIV", 0xFF04),
+    ("TIMA", 0xFF05),
+    ("TMA", 0xFF06),
+    ("TAC", 0xFF07),
+    ("IF", 0xFF0F),
+    ("LCDC", 0xFF40),
+    ("STAT", 0xFF41),
+    ("SCY", 0xFF42),
+    ("SCX", 0xFF43),
+    ("LY", 0xFF44),
+    ("LYC", 0xFF45),
+    ("DMA", 0xFF46),
+    ("BGP", 0xFF47),
+    ("OBP0", 0xFF48),
+    ("OBP1", 0xFF49),
+    ("WY", 0xFF4A),
+    ("WX", 0xFF4B),
+    ("KEY1", 0xFF4D),
+    ("VBK", 0xFF4F),
+    ("HDMA5", 0xFF55),
+    ("BCPS", 0xFF68),
+    ("BCPD", 0xFF69),
+    ("OCPS", 0xFF6A),
+    ("OCPD", 0xFF6B),
+    ("SVBK", 0xFF70),
+    ("IE", 0xFFFF),
+];
+
+/// Binary operators from the lowest to the highest precedence
+const PRECEDENCE: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// State an expression is evaluated against
+pub(crate) struct Context<'a, 'b> {
+    pub registers: &'a Registers,
+    pub memory: &'a MemoryMapping<'b>,
+    /// Times the breakpoint was reached, including this one
+    pub hits: u32,
+}
+
+#[derive(Debug, Clone)]
+enum Variable {
+    U8(RegisterU8),
+    U16(RegisterU16),
+    F,
+    Pc,
+    Flag(Flags),
+    Hits,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Number(i64),
+    Variable(Variable),
+    /// Byte at an address, `[HL]`
+    Memory(Box<Node>),
+    Unary(char, Box<Node>),
+    Binary(&'static str, Box<Node>, Box<Node>),
+}
+
+/// Parsed condition over registers, flags and memory, e.g. `A == $3F && [HL] != 0`
+///
+/// Values are integers, comparisons and `&&`/`||` give 0 or 1 and anything but 0 is true.
+/// Registers and flags (`ZF`, `NF`, `HF`, `CF`), hardware registers by name, `hits`
+/// and symbols, which are their address, can be used.
+#[derive(Debug, Clone)]
+pub(crate) struct Expression {
+    text: String,
+    root: Node,
+}
+
+impl Expression {
+    pub fn parse(text: &str, symbols: &Symbols) -> Result<Self> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            symbols,
+        };
+        let root = parser.binary(0)?;
+        if let Some(token) = parser.tokens.get(parser.position) {
+            bail!("unexpected `{token}`");
+        }
+        Ok(Self {
+            text: text.trim().to_string(),
+            root,
+        })
+    }
+
+    pub fn evaluate(&self, context: &Context) -> i64 {
+        evaluate(&self.root, context)
+    }
+
+    pub fn is_true(&self, context: &Context) -> bool {
+        self.evaluate(context) != 0
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+fn evaluate(node: &Node, context: &Context) -> i64 {
+    match node {
+        Node::Number(n) => *n,
+        Node::Variable(variable) => variable.value(context),
+        Node::Memory(address) => context.memory.peek(evaluate(address, context) as u16) as i64,
+        Node::Unary(op, a) => {
+            let a = evaluate(a, context);
+            match op {
+                '-' => a.wrapping_neg(),
+                '~' => !a,
+                _ => (a == 0) as i64,
+            }
+        }
+        Node::Binary(op, a, b) => {
+            let a = evaluate(a, context);
+            // Short circuit like C
+            match *op {
+                "&&" if a == 0 => return 0,
+                "||" if a != 0 => return 1,
+                _ => {}
+            }
+            let b = evaluate(b, context);
+            match *op {
+                "||" | "&&" => (b != 0) as i64,
+                "|" => a | b,
+                "^" => a ^ b,
+                "&" => a & b,
+                "==" => (a == b) as i64,
+                "!=" => (a != b) as i64,
+                "<" => (a < b) as i64,
+                "<=" => (a <= b) as i64,
+                ">" => (a > b) as i64,
+                ">=" => (a >= b) as i64,
+                "<<" => a.wrapping_shl(b as u32),
+                ">>" => a.wrapping_shr(b as u32),
+                "+" => a.wrapping_add(b),
+                "-" => a.wrapping_sub(b),
+                "*" => a.wrapping_mul(b),
+                "/" => a.checked_div(b).unwrap_or(0),
+                _ => a.checked_rem(b).unwrap_or(0),
+            }
+        }
+    }
+}
+
+impl Variable {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_uppercase().as_str() {
+            "A" => Self::U8(RegisterU8::A),
+            "B" => Self::U8(RegisterU8::B),
+            "C" => Self::U8(RegisterU8::C),
+            "D" => Self::U8(RegisterU8::D),
+            "E" => Self::U8(RegisterU8::E),
+            "H" => Self::U8(RegisterU8::H),
+            "L" => Self::U8(RegisterU8::L),
+            "F" => Self::F,
+            "AF" => Self::U16(RegisterU16::AF),
+            "BC" => Self::U16(RegisterU16::BC),
+            "DE" => Self::U16(RegisterU16::DE),
+            "HL" => Self::U16(RegisterU16::HL),
+            "SP" => Self::U16(RegisterU16::SP),
+            "PC" => Self::Pc,
+            "ZF" => Self::Flag(Flags::Z),
+            "NF" => Self::Flag(Flags::N),
+            "HF" => Self::Flag(Flags::H),
+            "CF" => Self::Flag(Flags::CY),
+            "HITS" => Self::Hits,
+            _ => return None,
+        })
+    }
+
+    fn value(&self, context: &Context) -> i64 {
+        let registers = context.registers;
+        match self {
+            Self::U8(r) => registers.get_u8(r) as i64,
+            Self::U16(r) => registers.get_u16(r) as i64,
+            Self::F => registers.f.value as i64,
+            Self::Pc => registers.pc as i64,
+            Self::Flag(flag) => registers.get_flag(*flag) as i64,
+            Self::Hits => context.hits as i64,
+        }
+    }
+
+    /// Values are truncated to the register, false for `hits`
+    fn set(&self, registers: &mut Registers, value: i64) -> bool {
+        match self {
+            Self::U8(r) => registers.set_u8(r, value as u8),
+            Self::U16(r) => registers.set_u16(r, value as u16),
+            Self::F => registers.f.value = value as u8 & 0xF0,
+            Self::Pc => registers.pc = value as u16,
+            Self::Flag(flag) => registers.f.set(*flag, value != 0),
+            Self::Hits => return false,
+        }
+        true
+    }
+}
+
+/// Writes a register or flag by the name used in expressions, e.g. `A` or `ZF`
+pub(crate) fn set_register(registers: &mut Registers, name: &str, value: i64) -> Result<()> {
+    match Variable::from_name(name) {
+        Some(variable) if variable.set(registers, value) => Ok(()),
+        _ => bail!("unknown register `{name}`"),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Identifier(String),
+    /// Operators, parentheses and brackets
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(n) => write!(f, "{n}"),
+            Self::Identifier(name) => write!(f, "{name}"),
+            Self::Symbol(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 24] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "|", "^", "&", "<", ">", "+", "-", "*", "/",
+    "%", "!", "~", "(", ")", "[", "]",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            tokens.push(Token::Symbol(symbol));
+            symbol.len()
+        } else if c.is_ascii_digit() || c == '$' {
+            let len = rest
+                .char_indices()
+                .skip(1)
+                .find(|(_, c)| !c.is_ascii_alphanumeric())
+                .map_or(rest.len(), |(i, _)| i);
+            tokens.push(Token::Number(parse_number(&rest[..len])?));
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '.' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Identifier(rest[..len].to_string()));
+            len
+        } else {
+            bail!("unexpected `{c}`");
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// `$3F`, `0x3F`, `0b0011` or `63`
+fn parse_number(text: &str) -> Result<i64> {
+    let (digits, radix) = if let Some(hex) = text.strip_prefix('$') {
+        (hex, 16)
+    } else if let Some(hex) = text.strip_prefix("0x") {
+        (hex, 16)
+    } else if let Some(binary) = text.strip_prefix("0b") {
+        (binary, 2)
+    } else {
+        (text, 10)
+    };
+    match i64::from_str_radix(digits, radix) {
+        Ok(n) => Ok(n),
+        Err(_) => bail!("invalid number `{text}`"),
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    symbols: &'a Symbols,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Consumes the next token when it's one of `symbols`
+    fn operator(&mut self, symbols: &[&'static str]) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Symbol(s)) if symbols.contains(s) => {
+                self.position += 1;
+                Some(s)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<()> {
+        match self.next() {
+            Some(Token::Symbol(s)) if s == symbol => Ok(()),
+            Some(token) => bail!("expected `{symbol}`, found `{token}`"),
+            None => bail!("expected `{symbol}`"),
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Node> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut node = self.binary(level + 1)?;
+        while let Some(op) = self.operator(PRECEDENCE[level]) {
+            let rhs = self.binary(level + 1)?;
+            node = Node::Binary(op, Box::new(node), Box::new(rhs));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node> {
+        match self.operator(&["-", "!", "~"]) {
+            Some(op) => Ok(Node::Unary(
+                op.chars().next().unwrap(),
+                Box::new(self.unary()?),
+            )),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Node> {
+        Ok(match self.next() {
+            Some(Token::Number(n)) => Node::Number(n),
+            Some(Token::Identifier(name)) => {
+                if let Some(variable) = Variable::from_name(&name) {
+                    Node::Variable(variable)
+                } else if let Some((_, address)) = IO_REGISTERS
+                    .iter()
+                    .find(|(io, _)| io.eq_ignore_ascii_case(&name))
+                {
+                    Node::Memory(Box::new(Node::Number(*address as i64)))
+                } else if let Some(address) = self.symbols.address(&name) {
+                    Node::Number(address as i64)
+                } else {
+                    bail!("unknown name `{name}`")
+                }
+            }
+            Some(Token::Symbol("(")) => {
+                let node = self.binary(0)?;
+                self.expect(")")?;
+                node
+            }
+            Some(Token::Symbol("[")) => {
+                let node = self.binary(0)?;
+                self.expect("]")?;
+                Node::Memory(Box::new(node))
+            }
+            Some(token) => bail!("unexpected `{token}`"),
+            None => bail!("unexpected end of expression"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Context, Expression};
+    use crate::{
+        debugger::Symbols,
+        memory_mapping::MemoryMapping,
+        registers::{Flags, Registers},
+    };
+
+    #[test]
+    fn evaluate() {
+        let mut registers = Registers::new();
+        registers.a = 0x3F;
+        registers.h = 0xC0;
+        registers.l = 0x10;
+        registers.f.set(Flags::Z, true);
+        let mut memory = MemoryMapping::default();
+        memory.set(0xC010, 7).unwrap();
+        memory.vram.y_coord = 144;
+        let symbols = Symbols::parse("00:C010 wCounter").unwrap();
+        let context = Context {
+            registers: &registers,
+            memory: &memory,
+            hits: 10,
+        };
+
+        let cases = [
+            ("A == $3F && [HL] != 0", 1),
+            ("LY == 144", 1),
+            ("hits >= 10", 1),
+            ("hits > 10 || !ZF", 0),
+            ("[wCounter] * 2 + 1", 15),
+            ("HL - 0x10", 0xC000),
+            ("(A & 0b1111) << 4", 0xF0),
+            ("-1 + 3 * 2", 5),
+            ("A / 0", 0),
+            ("CF | ZF << 1", 2),
+        ];
+        for (text, value) in cases {
+            let expression = Expression::parse(text, &symbols).unwrap();
+            assert_eq!(expression.evaluate(&context), value, "{text}");
+        }
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let symbols = Symbols::default();
+        let error = |text| Expression::parse(text, &symbols).unwrap_err().to_string();
+        assert_eq!(error("A =="), "unexpected end of expression");
+        assert_eq!(error("A == 1)"), "unexpected `)`");
+        assert_eq!(error("[HL"), "expected `]`");
+        assert_eq!(error("foo"), "unknown name `foo`");
+        assert_eq!(error("$XY"), "invalid number `$XY`");
+        assert_eq!(error("A = 1"), "unexpected `=`");
+    }
+}
@@ -0,0 +1,444 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+};
+
+use anyhow::{Context, Result};
+
+use super::{ExecutionState, Target, Watchpoints};
+use crate::{
+    registers::{RegisterU16, Registers},
+    watchpoint::{WatchKind, Watchpoint},
+};
+
+/// Registers of GDB's z80 target: AF BC DE HL SP PC IX IY AF' BC' DE' HL' IR
+const REGISTER_COUNT: usize = 13;
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Packet {
+    Command(String),
+    /// Ctrl-C sent while the target runs
+    Interrupt,
+    /// Checksum mismatch, GDB sends it again
+    Invalid,
+}
+
+/// Server of the GDB remote serial protocol on a localhost port
+///
+/// Registers follow GDB's z80 layout so `set architecture z80` works, the ones the SM83
+/// lacks read as zero. Polled once per loop of the frontend, a single client at a time.
+#[derive(Debug)]
+pub(crate) struct GdbStub {
+    listener: TcpListener,
+    stream: Option<TcpStream>,
+    buffer: Vec<u8>,
+    /// Waiting for execution to pause to send a stop reply
+    resumed: bool,
+    interrupted: bool,
+}
+
+impl GdbStub {
+    pub fn bind(port: u16) -> Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .with_context(|| format!("Couldn't listen for GDB on port {port}"))?;
+        listener.set_nonblocking(true)?;
+        println!("Waiting for GDB on localhost:{port}");
+        Ok(Self {
+            listener,
+            stream: None,
+            buffer: Vec::new(),
+            resumed: false,
+            interrupted: false,
+        })
+    }
+
+    /// Accepts a client, answers its packets and reports when execution paused
+    pub fn poll(&mut self, target: &mut Target) {
+        if self.stream.is_none() {
+            let Ok((stream, address)) = self.listener.accept() else {
+                return;
+            };
+            if stream.set_nonblocking(true).is_err() {
+                return;
+            }
+            println!("GDB connected from {address}");
+            self.stream = Some(stream);
+            self.buffer.clear();
+            self.resumed = false;
+            // GDB expects a stopped target when attaching
+            *target.execution_state = ExecutionState::Pause;
+        }
+
+        if !self.receive() {
+            self.disconnect();
+            return;
+        }
+        while let Some(packet) = next_packet(&mut self.buffer) {
+            let reply = match packet {
+                Packet::Invalid => {
+                    self.send_raw(b"-");
+                    continue;
+                }
+                Packet::Interrupt => {
+                    self.interrupted = true;
+                    *target.execution_state = ExecutionState::Pause;
+                    continue;
+                }
+                Packet::Command(command) => {
+                    self.send_raw(b"+");
+                    self.handle(&command, target)
+                }
+            };
+            if let Some(reply) = reply {
+                self.send(&reply);
+            }
+            if self.stream.is_none() {
+                return;
+            }
+        }
+
+        if self.resumed && matches!(target.execution_state, ExecutionState::Pause) {
+            self.resumed = false;
+            let reply = self.stop_reply(target.watchpoints);
+            self.send(&reply);
+        }
+    }
+
+    /// Reply to a command, `None` for commands answered later or not at all
+    fn handle(&mut self, command: &str, target: &mut Target) -> Option<String> {
+        let (name, args) = command.split_at(command.len().min(1));
+        let reply = match name {
+            "?" => format!("S{SIGTRAP:02x}"),
+            "g" => registers(&target.cpu.registers)
+                .iter()
+                .map(|&value| hex(&value.to_le_bytes()))
+                .collect(),
+            "G" => {
+                let Some(bytes) = parse_hex_bytes(args) else {
+                    return Some("E01".to_string());
+                };
+                for (i, value) in bytes.chunks_exact(2).enumerate() {
+                    let value = u16::from_le_bytes([value[0], value[1]]);
+                    set_register(&mut target.cpu.registers, i, value);
+                }
+                "OK".to_string()
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(i) if i < REGISTER_COUNT => {
+                    hex(&registers(&target.cpu.registers)[i].to_le_bytes())
+                }
+                _ => "E01".to_string(),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(i, value)| {
+                    let bytes = parse_hex_bytes(value)?;
+                    let value = u16::from_le_bytes([bytes[0], *bytes.get(1).unwrap_or(&0)]);
+                    Some((usize::from_str_radix(i, 16).ok()?, value))
+                });
+                match parsed {
+                    Some((i, value)) if i < REGISTER_COUNT => {
+                        set_register(&mut target.cpu.registers, i, value);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "m" => match parse_range(args) {
+                Some((address, len)) => {
+                    let bytes: Vec<u8> = (0..len)
+                        .map(|i| target.cpu.memory.peek(address.wrapping_add(i)))
+                        .collect();
+                    hex(&bytes)
+                }
+                None => "E01".to_string(),
+            },
+            "M" => {
+                let parsed = args
+                    .split_once(':')
+                    .and_then(|(range, data)| Some((parse_range(range)?, parse_hex_bytes(data)?)));
+                let Some(((address, _), bytes)) = parsed else {
+                    return Some("E01".to_string());
+                };
+                // Written like the memory viewer does, without side effects
+                for (i, byte) in (0..).zip(bytes) {
+                    if let Ok(value) = target.cpu.memory.get_mut(address.wrapping_add(i)) {
+                        *value = byte;
+                    }
+                }
+                "OK".to_string()
+            }
+            "Z" | "z" => set_breakpoint(name == "Z", args, target),
+            "c" | "s" => {
+                if let Ok(address) = u16::from_str_radix(args, 16) {
+                    target.cpu.registers.pc = address;
+                }
+                *target.execution_state = match name {
+                    "c" => ExecutionState::Execute,
+                    _ => ExecutionState::Step,
+                };
+                target.watchpoints.hit = None;
+                self.resumed = true;
+                self.interrupted = false;
+                return None;
+            }
+            "D" => {
+                *target.execution_state = ExecutionState::Execute;
+                self.send("OK");
+                self.disconnect();
+                return None;
+            }
+            "k" => {
+                self.disconnect();
+                return None;
+            }
+            "H" => "OK".to_string(),
+            _ if command.starts_with("qSupported") => "PacketSize=1000".to_string(),
+            _ if command == "qAttached" => "1".to_string(),
+            // Unsupported commands get an empty reply
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    /// `T05watch:c000;` after a watchpoint, `S05` otherwise
+    fn stop_reply(&self, watchpoints: &Watchpoints) -> String {
+        if self.interrupted {
+            return format!("S{SIGINT:02x}");
+        }
+        match &watchpoints.hit {
+            Some((_, hit)) => {
+                let kind = if hit.write { "watch" } else { "rwatch" };
+                format!("T{SIGTRAP:02x}{kind}:{:x};", hit.address)
+            }
+            None => format!("S{SIGTRAP:02x}"),
+        }
+    }
+
+    /// Reads what the client sent, false once it closed the connection
+    fn receive(&mut self) -> bool {
+        let Some(stream) = &mut self.stream else {
+            return true;
+        };
+        let mut chunk = [0; 1024];
+        loop {
+            match stream.read(&mut chunk) {
+                Ok(0) => return false,
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return true,
+                Err(_) => return false,
+            }
+        }
+    }
+
+    fn send(&mut self, reply: &str) {
+        let checksum = reply.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        self.send_raw(format!("${reply}#{checksum:02x}").as_bytes());
+    }
+
+    fn send_raw(&mut self, bytes: &[u8]) {
+        let Some(stream) = &mut self.stream else {
+            return;
+        };
+        // Replies are written whole, only reads are non-blocking
+        let sent = stream
+            .set_nonblocking(false)
+            .and_then(|_| stream.write_all(bytes))
+            .and_then(|_| stream.set_nonblocking(true));
+        if sent.is_err() {
+            self.disconnect();
+        }
+    }
+
+    fn disconnect(&mut self) {
+        if self.stream.take().is_some() {
+            println!("GDB disconnected");
+        }
+        self.resumed = false;
+    }
+}
+
+/// Takes the next packet out of `buffer`, acknowledgements are skipped
+fn next_packet(buffer: &mut Vec<u8>) -> Option<Packet> {
+    loop {
+        match buffer.first()? {
+            0x03 => {
+                buffer.remove(0);
+                return Some(Packet::Interrupt);
+            }
+            b'$' => break,
+            _ => {
+                buffer.remove(0);
+            }
+        }
+    }
+    let end = buffer.iter().position(|&b| b == b'#')?;
+    if buffer.len() < end + 3 {
+        return None;
+    }
+    let packet: Vec<u8> = buffer.drain(..end + 3).collect();
+    let data = &packet[1..end];
+    let checksum = std::str::from_utf8(&packet[end + 1..])
+        .ok()
+        .and_then(|s| u8::from_str_radix(s, 16).ok());
+    let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    match checksum == Some(sum) {
+        true => Some(Packet::Command(String::from_utf8_lossy(data).into_owned())),
+        false => Some(Packet::Invalid),
+    }
+}
+
+fn registers(registers: &Registers) -> [u16; REGISTER_COUNT] {
+    let mut values = [0; REGISTER_COUNT];
+    values[0] = registers.get_u16(&RegisterU16::AF);
+    values[1] = registers.get_u16(&RegisterU16::BC);
+    values[2] = registers.get_u16(&RegisterU16::DE);
+    values[3] = registers.get_u16(&RegisterU16::HL);
+    values[4] = registers.sp;
+    values[5] = registers.pc;
+    values
+}
+
+/// Writes to registers missing from the SM83 are ignored
+fn set_register(registers: &mut Registers, i: usize, value: u16) {
+    match i {
+        0 => registers.set_u16(&RegisterU16::AF, value),
+        1 => registers.set_u16(&RegisterU16::BC, value),
+        2 => registers.set_u16(&RegisterU16::DE, value),
+        3 => registers.set_u16(&RegisterU16::HL, value),
+        4 => registers.sp = value,
+        5 => registers.pc = value,
+        _ => {}
+    }
+}
+
+/// `Z0,addr,kind` software breakpoints, `Z2`-`Z4` write, read and access watchpoints
+fn set_breakpoint(insert: bool, args: &str, target: &mut Target) -> String {
+    let mut fields = args.split(',');
+    let (Some(kind), Some(address), Some(len)) = (fields.next(), fields.next(), fields.next())
+    else {
+        return "E01".to_string();
+    };
+    let (Ok(address), Ok(len)) = (
+        u16::from_str_radix(address, 16),
+        u16::from_str_radix(len, 16),
+    ) else {
+        return "E01".to_string();
+    };
+    let kind = match kind {
+        // Hardware breakpoints are the same as software ones here
+        "0" | "1" => {
+            match insert {
+                true => target.breakpoints.add(address),
+                false => target.breakpoints.remove(address),
+            }
+            return "OK".to_string();
+        }
+        "2" => WatchKind::Write,
+        "3" => WatchKind::Read,
+        "4" => WatchKind::ReadWrite,
+        _ => return String::new(),
+    };
+    let end = address.wrapping_add(len.max(1) - 1);
+    let watchpoint = Watchpoint::new(address, end, kind);
+    let list = &mut target.watchpoints.list;
+    match insert {
+        true if !list.contains(&watchpoint) => list.push(watchpoint),
+        true => {}
+        false => list.retain(|w| *w != watchpoint),
+    }
+    "OK".to_string()
+}
+
+/// `addr,length` in hex
+fn parse_range(args: &str) -> Option<(u16, u16)> {
+    let (address, len) = args.split_once(',')?;
+    Some((
+        u16::from_str_radix(address, 16).ok()?,
+        u16::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+fn parse_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if text.is_empty() || !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GdbStub, Packet, Target, next_packet};
+    use crate::{
+        cpu::Cpu,
+        debugger::{Breakpoints, ExecutionState, Watchpoints},
+        memory_mapping::{MemoryMapping, Rom},
+        watchpoint::{WatchKind, Watchpoint},
+    };
+
+    #[test]
+    fn packets() {
+        let mut buffer = b"+$g#67$m0,2#f".to_vec();
+        assert_eq!(next_packet(&mut buffer), Some(Packet::Command("g".into())));
+        // Incomplete until the checksum arrived
+        assert_eq!(next_packet(&mut buffer), None);
+        buffer.extend_from_slice(b"b\x03$c#00");
+        assert_eq!(
+            next_packet(&mut buffer),
+            Some(Packet::Command("m0,2".into()))
+        );
+        assert_eq!(next_packet(&mut buffer), Some(Packet::Interrupt));
+        assert_eq!(next_packet(&mut buffer), Some(Packet::Invalid));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn commands() {
+        let mut stub = GdbStub::bind(0).unwrap();
+        let mut cpu = Cpu::new(MemoryMapping::new(Rom {
+            rom: vec![0; 0x8000],
+        }));
+        let mut breakpoints = Breakpoints::default();
+        let mut watchpoints = Watchpoints::default();
+        let mut execution_state = ExecutionState::Pause;
+        let mut target = Target {
+            cpu: &mut cpu,
+            breakpoints: &mut breakpoints,
+            watchpoints: &mut watchpoints,
+            execution_state: &mut execution_state,
+        };
+        let mut handle = |command: &str| stub.handle(command, &mut target);
+
+        let registers = handle("g").unwrap();
+        assert_eq!(registers.len(), 13 * 4);
+        // Little endian SP=$FFFE and PC=$0100
+        assert_eq!(&registers[16..24], "feff0001");
+        assert_eq!(handle("P5=5001").unwrap(), "OK");
+        assert_eq!(handle("p5").unwrap(), "5001");
+
+        assert_eq!(handle("Mc000,2:1234").unwrap(), "OK");
+        assert_eq!(handle("mc000,3").unwrap(), "123400");
+
+        assert_eq!(handle("Z0,150,1").unwrap(), "OK");
+        assert_eq!(handle("Z2,c000,2").unwrap(), "OK");
+        assert_eq!(handle("c"), None);
+        assert_eq!(handle("z2,c000,2").unwrap(), "OK");
+        assert_eq!(handle("Z4,ff40,1").unwrap(), "OK");
+
+        assert!(breakpoints.contains(0x150));
+        assert_eq!(
+            watchpoints.list,
+            [Watchpoint::new(0xFF40, 0xFF40, WatchKind::ReadWrite)]
+        );
+        assert!(matches!(execution_state, ExecutionState::Execute));
+        assert_eq!(cpu.registers.pc, 0x150);
+    }
+}
@@ -0,0 +1,428 @@
+use imgui::{TableFlags, TreeNodeFlags, Ui};
+
+use super::Debugger;
+use crate::{
+    graphics::{LcdControl, LcdStatus},
+    interrupt::InterruptPosition,
+    memory_mapping::MemoryMapping,
+    timer::TimerController,
+};
+
+/// Bits of a hardware register, single bits are toggled with a checkbox
+struct Field {
+    name: &'static str,
+    mask: u8,
+    /// Meaning of each value of the bits, shown as a number without them
+    values: &'static [&'static str],
+    /// Status bits that don't read back what was written, writing them does something else
+    read_only: bool,
+}
+
+const fn field(name: &'static str, mask: u8, values: &'static [&'static str]) -> Field {
+    Field {
+        name,
+        mask,
+        values,
+        read_only: false,
+    }
+}
+
+const fn status(name: &'static str, mask: u8, values: &'static [&'static str]) -> Field {
+    Field {
+        name,
+        mask,
+        values,
+        read_only: true,
+    }
+}
+
+impl Field {
+    fn describe(&self, value: u8) -> String {
+        let bits = (value & self.mask) >> self.mask.trailing_zeros();
+        match self.values.get(bits as usize) {
+            Some(meaning) => meaning.to_string(),
+            None => bits.to_string(),
+        }
+    }
+}
+
+struct IoRegister {
+    name: &'static str,
+    address: u16,
+    fields: &'static [Field],
+}
+
+const fn register(name: &'static str, address: u16, fields: &'static [Field]) -> IoRegister {
+    IoRegister {
+        name,
+        address,
+        fields,
+    }
+}
+
+const ON_OFF: &[&str] = &["Off", "On"];
+const TILE_MAP: &[&str] = &["9800-9BFF", "9C00-9FFF"];
+const SHADES: &[&str] = &["White", "Light gray", "Dark gray", "Black"];
+const DUTY: &[&str] = &["12.5%", "25%", "50%", "75%"];
+
+const PALETTE: &[Field] = &[
+    field("Color 0", 0x03, SHADES),
+    field("Color 1", 0x0C, SHADES),
+    field("Color 2", 0x30, SHADES),
+    field("Color 3", 0xC0, SHADES),
+];
+const INTERRUPTS: &[Field] = &[
+    field("VBlank", InterruptPosition::VBlank as u8, &[]),
+    field("LCD", InterruptPosition::Lcd as u8, &[]),
+    field("Timer", InterruptPosition::Timer as u8, &[]),
+    field("Serial", InterruptPosition::Serial as u8, &[]),
+    field("Joypad", InterruptPosition::Joypad as u8, &[]),
+];
+const LENGTH_DUTY: &[Field] = &[field("Duty", 0xC0, DUTY), field("Length", 0x3F, &[])];
+const ENVELOPE: &[Field] = &[
+    field("Volume", 0xF0, &[]),
+    field("Increase", 0x08, &[]),
+    field("Pace", 0x07, &[]),
+];
+const PERIOD_HIGH: &[Field] = &[
+    field("Trigger", 0x80, &[]),
+    field("Length enable", 0x40, &[]),
+    field("Period high", 0x07, &[]),
+];
+const PALETTE_SPEC: &[Field] = &[
+    field("Auto increment", 0x80, &[]),
+    field("Index", 0x3F, &[]),
+];
+
+const GROUPS: [(&str, &[IoRegister]); 6] = [
+    (
+        "Joypad and serial",
+        &[
+            register(
+                "P1",
+                0xFF00,
+                &[
+                    field("Buttons", 0x20, &["Selected", "Off"]),
+                    field("D-pad", 0x10, &["Selected", "Off"]),
+                    field("Inputs", 0x0F, &[]),
+                ],
+            ),
+            register("SB", 0xFF01, &[]),
+            register(
+                "SC",
+                0xFF02,
+                &[
+                    field("Transfer", 0x80, &[]),
+                    field("Clock", 0x01, &["External", "Internal"]),
+                ],
+            ),
+        ],
+    ),
+    (
+        "Timer",
+        &[
+            register("DIV", 0xFF04, &[]),
+            register("TIMA", 0xFF05, &[]),
+            register("TMA", 0xFF06, &[]),
+            register(
+                "TAC",
+                0xFF07,
+                &[
+                    field("Enable", TimerController::Enable as u8, ON_OFF),
+                    field(
+                        "Clock",
+                        TimerController::ClockSelect as u8,
+                        &["4096 Hz", "262144 Hz", "65536 Hz", "16384 Hz"],
+                    ),
+                ],
+            ),
+        ],
+    ),
+    (
+        "Interrupts",
+        &[
+            register("IF", 0xFF0F, INTERRUPTS),
+            register("IE", 0xFFFF, INTERRUPTS),
+        ],
+    ),
+    (
+        "LCD",
+        &[
+            register(
+                "LCDC",
+                0xFF40,
+                &[
+                    field("LCD", LcdControl::Enable as u8, ON_OFF),
+                    field("Window map", LcdControl::WindowTileMap as u8, TILE_MAP),
+                    field("Window", LcdControl::WindowEnable as u8, ON_OFF),
+                    field(
+                        "Tile data",
+                        LcdControl::BGWindowTileData as u8,
+                        &["8800-97FF", "8000-8FFF"],
+                    ),
+                    field("BG map", LcdControl::BGTileMap as u8, TILE_MAP),
+                    field("OBJ size", LcdControl::OBJSize as u8, &["8x8", "8x16"]),
+                    field("OBJ", LcdControl::OBJEnable as u8, ON_OFF),
+                    field("BG/Window", LcdControl::BGWindowEnable as u8, ON_OFF),
+                ],
+            ),
+            register(
+                "STAT",
+                0xFF41,
+                &[
+                    field(
+                        "Mode",
+                        LcdStatus::PPUMode as u8,
+                        &["HBlank", "VBlank", "OAM scan", "Drawing"],
+                    ),
+                    status("LYC == LY", LcdStatus::LYCEqLY as u8, &[]),
+                    field("HBlank interrupt", LcdStatus::Mode0Int as u8, &[]),
+                    field("VBlank interrupt", LcdStatus::Mode1Int as u8, &[]),
+                    field("OAM interrupt", LcdStatus::Mode2Int as u8, &[]),
+                    field("LYC interrupt", LcdStatus::LYCInt as u8, &[]),
+                ],
+            ),
+            register("SCY", 0xFF42, &[]),
+            register("SCX", 0xFF43, &[]),
+            register("LY", 0xFF44, &[]),
+            register("LYC", 0xFF45, &[]),
+            register("DMA", 0xFF46, &[]),
+            register("BGP", 0xFF47, PALETTE),
+            register("OBP0", 0xFF48, PALETTE),
+            register("OBP1", 0xFF49, PALETTE),
+            register("WY", 0xFF4A, &[]),
+            register("WX", 0xFF4B, &[]),
+        ],
+    ),
+    (
+        "Sound",
+        &[
+            register(
+                "NR10",
+                0xFF10,
+                &[
+                    field("Pace", 0x70, &[]),
+                    field("Decrease", 0x08, &[]),
+                    field("Step", 0x07, &[]),
+                ],
+            ),
+            register("NR11", 0xFF11, LENGTH_DUTY),
+            register("NR12", 0xFF12, ENVELOPE),
+            register("NR13", 0xFF13, &[]),
+            register("NR14", 0xFF14, PERIOD_HIGH),
+            register("NR21", 0xFF16, LENGTH_DUTY),
+            register("NR22", 0xFF17, ENVELOPE),
+            register("NR23", 0xFF18, &[]),
+            register("NR24", 0xFF19, PERIOD_HIGH),
+            register("NR30", 0xFF1A, &[field("DAC", 0x80, ON_OFF)]),
+            register("NR31", 0xFF1B, &[]),
+            register(
+                "NR32",
+                0xFF1C,
+                &[field("Volume", 0x60, &["Mute", "100%", "50%", "25%"])],
+            ),
+            register("NR33", 0xFF1D, &[]),
+            register("NR34", 0xFF1E, PERIOD_HIGH),
+            register("NR41", 0xFF20, &[field("Length", 0x3F, &[])]),
+            register("NR42", 0xFF21, ENVELOPE),
+            register(
+                "NR43",
+                0xFF22,
+                &[
+                    field("Shift", 0xF0, &[]),
+                    field("Width", 0x08, &["15 bit", "7 bit"]),
+                    field("Divider", 0x07, &[]),
+                ],
+            ),
+            register(
+                "NR44",
+                0xFF23,
+                &[
+                    field("Trigger", 0x80, &[]),
+                    field("Length enable", 0x40, &[]),
+                ],
+            ),
+            register(
+                "NR50",
+                0xFF24,
+                &[
+                    field("VIN left", 0x80, &[]),
+                    field("Left volume", 0x70, &[]),
+                    field("VIN right", 0x08, &[]),
+                    field("Right volume", 0x07, &[]),
+                ],
+            ),
+            register("NR51", 0xFF25, &[]),
+            register(
+                "NR52",
+                0xFF26,
+                &[
+                    field("APU", 0x80, ON_OFF),
+                    status("CH4", 0x08, &[]),
+                    status("CH3", 0x04, &[]),
+                    status("CH2", 0x02, &[]),
+                    status("CH1", 0x01, &[]),
+                ],
+            ),
+        ],
+    ),
+    (
+        "CGB",
+        &[
+            register(
+                "KEY1",
+                0xFF4D,
+                &[
+                    status("Double speed", 0x80, &[]),
+                    field("Switch armed", 0x01, &[]),
+                ],
+            ),
+            register("VBK", 0xFF4F, &[field("Bank", 0x01, &[])]),
+            register("HDMA1", 0xFF51, &[]),
+            register("HDMA2", 0xFF52, &[]),
+            register("HDMA3", 0xFF53, &[]),
+            register("HDMA4", 0xFF54, &[]),
+            register(
+                "HDMA5",
+                0xFF55,
+                &[
+                    // Writing starts a transfer
+                    status("Transfer", 0x80, &["Active", "Inactive"]),
+                    field("Blocks remaining - 1", 0x7F, &[]),
+                ],
+            ),
+            register("BCPS", 0xFF68, PALETTE_SPEC),
+            register("BCPD", 0xFF69, &[]),
+            register("OCPS", 0xFF6A, PALETTE_SPEC),
+            register("OCPD", 0xFF6B, &[]),
+            register("SVBK", 0xFF70, &[field("Bank", 0x07, &[])]),
+        ],
+    ),
+];
+
+impl Debugger<'_> {
+    /// Hardware registers with their bits decoded, edits are written like the CPU does
+    pub fn display_io(ui: &Ui, memory: &mut MemoryMapping) {
+        ui.window("I/O")
+            .size([450., 600.], imgui::Condition::FirstUseEver)
+            .position([1100., 50.], imgui::Condition::FirstUseEver)
+            .build(|| {
+                for (group, registers) in GROUPS {
+                    if !ui.collapsing_header(group, TreeNodeFlags::DEFAULT_OPEN) {
+                        continue;
+                    }
+                    if let Some(_table) =
+                        ui.begin_table_with_flags(group, 4, TableFlags::SIZING_FIXED_FIT)
+                    {
+                        for register in registers {
+                            display_register(ui, memory, register);
+                        }
+                    }
+                }
+            });
+    }
+}
+
+fn display_register(ui: &Ui, memory: &mut MemoryMapping, register: &IoRegister) {
+    let name = register.name;
+    ui.table_next_row();
+    ui.table_set_column_index(0);
+    ui.text(format!("${:04X}", register.address));
+    ui.table_set_column_index(1);
+    ui.text(name);
+    ui.table_set_column_index(2);
+    let Ok(&value) = memory.get(register.address) else {
+        ui.text_disabled("not emulated");
+        return;
+    };
+    // Written on Enter, or when a bit is toggled
+    let mut write = None;
+    let mut str = format!("{value:02X}");
+    ui.set_next_item_width(30.);
+    if ui
+        .input_text(format!("###io_{name}"), &mut str)
+        .chars_hexadecimal(true)
+        .enter_returns_true(true)
+        .build()
+    {
+        write = u8::from_str_radix(&str, 16).ok();
+    }
+
+    ui.table_set_column_index(3);
+    for field in register.fields {
+        if field.mask.count_ones() == 1 && !field.read_only {
+            let mut set = value & field.mask != 0;
+            if ui.checkbox(
+                format!("{}###io_{name}_{}", field.name, field.name),
+                &mut set,
+            ) {
+                write = Some(value ^ field.mask);
+            }
+            if !field.values.is_empty() {
+                ui.same_line();
+                ui.text_disabled(field.describe(value));
+            }
+        } else {
+            ui.text(format!("{}: {}", field.name, field.describe(value)));
+        }
+    }
+
+    if let Some(new) = write {
+        // Read only registers like LY ignore the write
+        memory.poke(register.address, new).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GROUPS;
+
+    #[test]
+    fn decode() {
+        let find = |name: &str| {
+            GROUPS
+                .iter()
+                .flat_map(|(_, registers)| registers.iter())
+                .find(|register| register.name == name)
+                .unwrap()
+        };
+        let describe = |name: &str, value: u8| -> Vec<String> {
+            find(name)
+                .fields
+                .iter()
+                .map(|field| field.describe(value))
+                .collect()
+        };
+
+        assert_eq!(
+            describe("LCDC", 0x91),
+            [
+                "On",
+                "9800-9BFF",
+                "Off",
+                "8000-8FFF",
+                "9800-9BFF",
+                "8x8",
+                "Off",
+                "On"
+            ]
+        );
+        assert_eq!(describe("STAT", 0x83), ["Drawing", "0", "0", "0", "0", "0"]);
+        assert_eq!(describe("TAC", 0x05), ["On", "262144 Hz"]);
+        assert_eq!(
+            describe("BGP", 0xE4),
+            ["White", "Light gray", "Dark gray", "Black"]
+        );
+        assert_eq!(describe("HDMA5", 0xFF), ["Inactive", "127"]);
+        assert_eq!(describe("HDMA5", 0x05), ["Active", "5"]);
+
+        // Fields of a register don't overlap
+        for register in GROUPS.iter().flat_map(|(_, registers)| registers.iter()) {
+            let mut bits = 0;
+            for field in register.fields {
+                assert_eq!(bits & field.mask, 0, "{}", register.name);
+                bits |= field.mask;
+            }
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+use anyhow::{Context, Result, bail};
+
+use crate::memory_mapping::MemoryMapping;
+
+/// Labels of an RGBDS `.sym` file, e.g. `01:4000 Main`
+#[derive(Debug, Default)]
+pub(crate) struct Symbols {
+    /// Names by (bank, address)
+    labels: BTreeMap<(u16, u16), String>,
+    addresses: HashMap<String, (u16, u16)>,
+}
+
+impl Symbols {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read symbols {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid symbols {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut symbols = Self::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((location, name)) = line.split_once(char::is_whitespace) else {
+                bail!("Line {}: expected `bank:address name`", i + 1);
+            };
+            let location = location
+                .split_once(':')
+                .and_then(|(bank, address)| {
+                    Some((
+                        u16::from_str_radix(bank, 16).ok()?,
+                        u16::from_str_radix(address, 16).ok()?,
+                    ))
+                })
+                .with_context(|| format!("Line {}: invalid location `{location}`", i + 1))?;
+            let name = name.trim().to_string();
+            symbols.addresses.insert(name.clone(), location);
+            // The first label of an address is usually the global one
+            symbols.labels.entry(location).or_insert(name);
+        }
+        Ok(symbols)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.addresses.keys().map(String::as_str)
+    }
+
+    /// Address of a label, ignoring its bank
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).map(|(_, address)| *address)
+    }
+
+    /// Label exactly at `address` in the currently mapped bank
+    pub fn label(&self, memory: &MemoryMapping, address: u16) -> Option<&str> {
+        self.labels
+            .get(&(bank(memory, address), address))
+            .map(String::as_str)
+    }
+
+    /// Closest label at or before `address` in the same memory region
+    pub fn nearest(&self, memory: &MemoryMapping, address: u16) -> Option<(&str, u16)> {
+        let bank = bank(memory, address);
+        let ((_, start), name) = self.labels.range((bank, 0)..=(bank, address)).next_back()?;
+        (region(*start) == region(address)).then_some((name.as_str(), address - start))
+    }
+
+    /// `label+$offset`, or the label alone when it's exact
+    pub fn describe(&self, memory: &MemoryMapping, address: u16) -> Option<String> {
+        self.nearest(memory, address)
+            .map(|(name, offset)| match offset {
+                0 => name.to_string(),
+                _ => format!("{name}+${offset:X}"),
+            })
+    }
+}
+
+/// Bank mapped at `address`, as numbered in `.sym` files
+fn bank(memory: &MemoryMapping, address: u16) -> u16 {
+    match address {
+        0x4000..0x8000 => memory.rom.bank() as u16,
+        0xD000..0xE000 => (memory.wram.bank_select as u16 & 0b111).max(1),
+        _ => 0,
+    }
+}
+
+/// Labels don't extend past the end of their region
+fn region(address: u16) -> u16 {
+    match address {
+        0x0000..0x4000 => 0,
+        0x4000..0x8000 => 1,
+        0x8000..0xA000 => 2,
+        0xA000..0xC000 => 3,
+        0xC000..0xD000 => 4,
+        0xD000..0xE000 => 5,
+        0xE000..0xFF80 => 6,
+        _ => 7,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Symbols;
+    use crate::memory_mapping::MemoryMapping;
+
+    #[test]
+    fn parse_and_lookup() {
+        let symbols = Symbols::parse(
+            "; File generated by rgblink\n\
+             00:0150 Main\n\
+             00:0158 Main.loop\n\
+             01:4000 Init\n\
+             00:c000 wCounter ; comment\n\
+             00:ff80 hFlag\n",
+        )
+        .unwrap();
+        let memory = MemoryMapping::default();
+
+        assert_eq!(symbols.address("Main.loop"), Some(0x158));
+        assert_eq!(symbols.address("Init"), Some(0x4000));
+        assert_eq!(symbols.address("Missing"), None);
+
+        assert_eq!(symbols.label(&memory, 0x150), Some("Main"));
+        assert_eq!(symbols.label(&memory, 0x151), None);
+        assert_eq!(symbols.describe(&memory, 0x153).as_deref(), Some("Main+$3"));
+        assert_eq!(
+            symbols.describe(&memory, 0x15A).as_deref(),
+            Some("Main.loop+$2")
+        );
+        assert_eq!(
+            symbols.describe(&memory, 0x4010).as_deref(),
+            Some("Init+$10")
+        );
+        assert_eq!(
+            symbols.describe(&memory, 0xC001).as_deref(),
+            Some("wCounter+$1")
+        );
+        // Below the first label and past the end of the region
+        assert_eq!(symbols.describe(&memory, 0x100), None);
+        assert_eq!(symbols.describe(&memory, 0x8000), None);
+        assert_eq!(
+            symbols.describe(&memory, 0xFF81).as_deref(),
+            Some("hFlag+$1")
+        );
+
+        assert!(Symbols::parse("0150 Main").is_err());
+        assert!(Symbols::parse("00:0150").is_err());
+    }
+}
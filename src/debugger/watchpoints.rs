@@ -0,0 +1,89 @@
+use imgui::{TableFlags, Ui};
+
+use super::Symbols;
+use crate::watchpoint::{WatchKind, Watchpoint, WatchpointHit};
+
+const KINDS: [&str; 4] = ["Read", "Write", "Read/Write", "Write value"];
+
+/// Watchpoints edited in the Breakpoints window, copied into memory when they change
+#[derive(Debug, Default)]
+pub(crate) struct Watchpoints {
+    pub list: Vec<Watchpoint>,
+    /// PC of the instruction and the access that stopped execution last
+    pub hit: Option<(u16, WatchpointHit)>,
+
+    range_input: String,
+    kind: usize,
+    value_input: String,
+}
+
+impl Watchpoints {
+    pub fn display(&mut self, ui: &Ui, symbols: &Symbols) {
+        ui.separator();
+        ui.text("Watchpoints");
+        if let Some((pc, hit)) = &self.hit {
+            ui.text_colored([1., 0.5, 0., 1.], format!("PC ${pc:04X}: {hit}"));
+        }
+
+        ui.set_next_item_width(120.);
+        let add = ui
+            .input_text("###watchpoint_range", &mut self.range_input)
+            .hint("C000-C0FF")
+            .enter_returns_true(true)
+            .build();
+        ui.same_line();
+        ui.set_next_item_width(100.);
+        ui.combo_simple_string("###watchpoint_kind", &mut self.kind, &KINDS);
+        if self.kind == 3 {
+            ui.same_line();
+            ui.text("$");
+            ui.same_line();
+            ui.set_next_item_width(30.);
+            ui.input_text("###watchpoint_value", &mut self.value_input)
+                .chars_hexadecimal(true)
+                .build();
+        }
+        ui.same_line();
+        if (ui.button("Add###watchpoint_add") || add)
+            && let Some(watchpoint) = self.parse_input(symbols)
+            && !self.list.contains(&watchpoint)
+        {
+            self.list.push(watchpoint);
+        }
+
+        if let Some(_table) =
+            ui.begin_table_with_flags("watchpoints", 2, TableFlags::SIZING_FIXED_FIT)
+        {
+            for (i, watchpoint) in self.list.clone().iter().enumerate() {
+                ui.table_next_row();
+                ui.table_set_column_index(0);
+                if ui.button(format!("-###watchpoint{i}")) {
+                    self.list.remove(i);
+                }
+                ui.table_set_column_index(1);
+                ui.text(watchpoint.to_string());
+            }
+        }
+    }
+
+    /// Range of hex addresses or symbols, e.g. `C000-C0FF` or `wCounter`
+    fn parse_input(&self, symbols: &Symbols) -> Option<Watchpoint> {
+        let address = |s: &str| {
+            let s = s.trim();
+            symbols
+                .address(s)
+                .or_else(|| u16::from_str_radix(s.trim_start_matches('$'), 16).ok())
+        };
+        let (start, end) = match self.range_input.split_once('-') {
+            Some((start, end)) => (address(start)?, address(end)?),
+            None => (address(&self.range_input)?, address(&self.range_input)?),
+        };
+        let kind = match self.kind {
+            0 => WatchKind::Read,
+            1 => WatchKind::Write,
+            2 => WatchKind::ReadWrite,
+            _ => WatchKind::Value(u8::from_str_radix(&self.value_input, 16).ok()?),
+        };
+        Some(Watchpoint::new(start, end, kind))
+    }
+}
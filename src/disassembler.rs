@@ -0,0 +1,291 @@
+use crate::{
+    instructions::{
+        FlagCondition, Instruction, Operand, OperandU8, OperandU16, cbprefixed, unprefixed,
+    },
+    registers::RegisterU16,
+};
+
+mod rom;
+#[cfg(feature = "frontend")]
+pub(crate) use rom::disassemble_rom;
+
+/// An instruction decoded at an address with its operands resolved
+#[derive(Debug, Clone)]
+pub(crate) struct Disassembly {
+    pub address: u16,
+    pub instruction: Instruction,
+    /// Opcode and immediate bytes
+    pub bytes: Vec<u8>,
+    /// RGBDS-like text, e.g. `LD A,$3F` or `JR NZ,$0150`
+    pub text: String,
+}
+
+impl Disassembly {
+    /// Bytes as hex, e.g. `20 FB`
+    pub fn hex(&self) -> String {
+        self.bytes
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Address the instruction jumps or calls to, when it's known without executing it
+    pub fn target(&self) -> Option<u16> {
+        let operands = Operands::new(self.address, &self.bytes);
+        match self.instruction {
+            Instruction::JR(..) => Some(operands.jr_target()),
+            Instruction::JP(_, OperandU16::Immediate)
+            | Instruction::CALL(_, OperandU16::Immediate) => Some(operands.imm16),
+            Instruction::RST(n) => Some(n as u16 * 8),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes the instruction at `address`, `read` returns the memory at an address
+pub(crate) fn disassemble(read: impl Fn(u16) -> u8, address: u16) -> Disassembly {
+    let (instruction, len) = decode(&read, address);
+    let bytes: Vec<u8> = (0..len).map(|i| read(address.wrapping_add(i))).collect();
+
+    let text = Operands::new(address, &bytes).text(&instruction);
+
+    Disassembly {
+        address,
+        instruction,
+        bytes,
+        text,
+    }
+}
+
+/// Length in bytes of the instruction at `address`, without resolving its text
+pub(crate) fn instruction_len(read: impl Fn(u16) -> u8, address: u16) -> u16 {
+    decode(&read, address).1
+}
+
+fn decode(read: &impl Fn(u16) -> u8, address: u16) -> (Instruction, u16) {
+    let opcode = read(address);
+    match opcode {
+        0xCB => (cbprefixed::decode_byte(read(address.wrapping_add(1))), 2),
+        _ => {
+            let instruction = unprefixed::decode_byte(opcode);
+            let len = 1 + immediate_len(&instruction);
+            (instruction, len)
+        }
+    }
+}
+
+/// Number of bytes following the opcode
+fn immediate_len(instruction: &Instruction) -> u16 {
+    fn u8_len(operand: &OperandU8) -> u16 {
+        match operand {
+            OperandU8::Register(_) => 0,
+            OperandU8::Immediate => 1,
+            OperandU8::Memory(m) => u16_len(m),
+            OperandU8::MemoryU8(m) => u8_len(m),
+        }
+    }
+    fn u16_len(operand: &OperandU16) -> u16 {
+        match operand {
+            OperandU16::RegisterPair(_) => 0,
+            OperandU16::Immediate => 2,
+        }
+    }
+    fn len(operand: &Operand) -> u16 {
+        match operand {
+            Operand::U8(a) => u8_len(a),
+            Operand::U16(a) => u16_len(a),
+        }
+    }
+
+    match instruction {
+        Instruction::LD(a, b) | Instruction::ADD(a, b) => len(a) + len(b),
+        Instruction::ADC(a, b)
+        | Instruction::SUB(a, b)
+        | Instruction::SBC(a, b)
+        | Instruction::AND(a, b)
+        | Instruction::XOR(a, b)
+        | Instruction::OR(a, b)
+        | Instruction::CP(a, b)
+        | Instruction::LDH(a, b) => u8_len(a) + u8_len(b),
+        Instruction::JP(_, a) | Instruction::CALL(_, a) => u16_len(a),
+        Instruction::STOP(_) | Instruction::JR(..) | Instruction::LDF8 => 1,
+        _ => 0,
+    }
+}
+
+/// Immediate values of an instruction at `address`
+struct Operands {
+    address: u16,
+    opcode: u8,
+    imm8: u8,
+    imm16: u16,
+}
+
+impl Operands {
+    fn new(address: u16, bytes: &[u8]) -> Self {
+        let byte = |i| bytes.get(i).copied().unwrap_or_default();
+        Self {
+            address,
+            opcode: byte(0),
+            imm8: byte(1),
+            imm16: u16::from_le_bytes([byte(1), byte(2)]),
+        }
+    }
+
+    fn u8(&self, operand: &OperandU8) -> String {
+        match operand {
+            OperandU8::Register(r) => r.to_string(),
+            OperandU8::Immediate => format!("${:02X}", self.imm8),
+            OperandU8::Memory(m) => format!("({})", self.u16(m)),
+            OperandU8::MemoryU8(m) => match **m {
+                OperandU8::Immediate => format!("(${:04X})", 0xFF00 | self.imm8 as u16),
+                _ => format!("($FF00+{})", self.u8(m)),
+            },
+        }
+    }
+
+    fn u16(&self, operand: &OperandU16) -> String {
+        match operand {
+            OperandU16::RegisterPair(r) => r.to_string(),
+            OperandU16::Immediate => format!("${:04X}", self.imm16),
+        }
+    }
+
+    fn operand(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::U8(a) => self.u8(a),
+            Operand::U16(a) => self.u16(a),
+        }
+    }
+
+    /// Signed offset of `ADD SP,e8` and `LD HL,SP+e8`
+    fn offset(&self) -> i8 {
+        self.imm8 as i8
+    }
+
+    /// Destination of a relative jump
+    fn jr_target(&self) -> u16 {
+        self.address
+            .wrapping_add(2)
+            .wrapping_add_signed(self.offset() as i16)
+    }
+
+    fn text(&self, instruction: &Instruction) -> String {
+        fn condition(condition: &Option<FlagCondition>) -> String {
+            condition
+                .as_ref()
+                .map_or(String::new(), |c| format!("{c},"))
+        }
+
+        // The accumulator rotates decode like their CB prefixed versions
+        match self.opcode {
+            0x07 => return "RLCA".to_string(),
+            0x0F => return "RRCA".to_string(),
+            0x17 => return "RLA".to_string(),
+            0x1F => return "RRA".to_string(),
+            _ => {}
+        }
+
+        match instruction {
+            Instruction::LD(a, b) => format!("LD {},{}", self.operand(a), self.operand(b)),
+            Instruction::LDF8 => format!("LD HL,SP{:+}", self.offset()),
+            Instruction::INC(a) => format!("INC {}", self.operand(a)),
+            Instruction::DEC(a) => format!("DEC {}", self.operand(a)),
+            Instruction::ADD(Operand::U16(OperandU16::RegisterPair(RegisterU16::SP)), _) => {
+                format!("ADD SP,{}", self.offset())
+            }
+            Instruction::ADD(a, b) => format!("ADD {},{}", self.operand(a), self.operand(b)),
+            Instruction::STOP(_) => "STOP".to_string(),
+            Instruction::JR(c, _) => format!("JR {}${:04X}", condition(c), self.jr_target()),
+            Instruction::ADC(a, b) => format!("ADC {},{}", self.u8(a), self.u8(b)),
+            Instruction::SUB(a, b) => format!("SUB {},{}", self.u8(a), self.u8(b)),
+            Instruction::SBC(a, b) => format!("SBC {},{}", self.u8(a), self.u8(b)),
+            Instruction::AND(a, b) => format!("AND {},{}", self.u8(a), self.u8(b)),
+            Instruction::XOR(a, b) => format!("XOR {},{}", self.u8(a), self.u8(b)),
+            Instruction::OR(a, b) => format!("OR {},{}", self.u8(a), self.u8(b)),
+            Instruction::CP(a, b) => format!("CP {},{}", self.u8(a), self.u8(b)),
+            Instruction::RET(Some(c)) => format!("RET {c}"),
+            Instruction::JP(c, a) => format!("JP {}{}", condition(c), self.u16(a)),
+            Instruction::CALL(c, a) => format!("CALL {}{}", condition(c), self.u16(a)),
+            Instruction::RST(n) => format!("RST ${:02X}", n * 8),
+            Instruction::LDH(a, b) => format!("LDH {},{}", self.u8(a), self.u8(b)),
+            Instruction::RLC(a) => format!("RLC {}", self.u8(a)),
+            Instruction::RRC(a) => format!("RRC {}", self.u8(a)),
+            Instruction::RL(a) => format!("RL {}", self.u8(a)),
+            Instruction::RR(a) => format!("RR {}", self.u8(a)),
+            Instruction::SLA(a) => format!("SLA {}", self.u8(a)),
+            Instruction::SRA(a) => format!("SRA {}", self.u8(a)),
+            Instruction::SWAP(a) => format!("SWAP {}", self.u8(a)),
+            Instruction::SRL(a) => format!("SRL {}", self.u8(a)),
+            Instruction::BIT(bit, a) => format!("BIT {bit},{}", self.u8(a)),
+            Instruction::RES(bit, a) => format!("RES {bit},{}", self.u8(a)),
+            Instruction::SET(bit, a) => format!("SET {bit},{}", self.u8(a)),
+            // No operands to resolve
+            _ => instruction.to_string().replace(", ", ","),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disassemble;
+
+    fn disassemble_bytes(bytes: &[u8], address: u16) -> (String, usize) {
+        let read = |addr: u16| {
+            bytes
+                .get(addr.wrapping_sub(address) as usize)
+                .copied()
+                .unwrap_or_default()
+        };
+        let disassembly = disassemble(read, address);
+        assert_eq!(disassembly.bytes, bytes[..disassembly.bytes.len()]);
+        (disassembly.text, disassembly.bytes.len())
+    }
+
+    #[test]
+    fn resolved_operands() {
+        let cases: [(&[u8], u16, &str, usize); 18] = [
+            (&[0x00], 0x100, "NOP", 1),
+            (&[0x3E, 0x3F], 0x100, "LD A,$3F", 2),
+            (&[0x20, 0xFE], 0x150, "JR NZ,$0150", 2),
+            (&[0x18, 0x05], 0x200, "JR $0207", 2),
+            (&[0xE0, 0x44], 0x100, "LDH ($FF44),A", 2),
+            (&[0xF2], 0x100, "LDH A,($FF00+C)", 1),
+            (&[0xFF], 0x100, "RST $38", 1),
+            (&[0xC3, 0x50, 0x01], 0x100, "JP $0150", 3),
+            (&[0xCC, 0x34, 0x12], 0x100, "CALL Z,$1234", 3),
+            (&[0xE9], 0x100, "JP HL", 1),
+            (&[0xEA, 0x00, 0xC0], 0x100, "LD ($C000),A", 3),
+            (&[0x08, 0xF0, 0xFF], 0x100, "LD ($FFF0),SP", 3),
+            (&[0xE8, 0xFD], 0x100, "ADD SP,-3", 2),
+            (&[0xF8, 0x05], 0x100, "LD HL,SP+5", 2),
+            (&[0xCB, 0x7C], 0x100, "BIT 7,H", 2),
+            (&[0x07], 0x100, "RLCA", 1),
+            (&[0xCB, 0x07], 0x100, "RLC A", 2),
+            (&[0x10, 0x00], 0x100, "STOP", 2),
+        ];
+        for (bytes, address, text, len) in cases {
+            assert_eq!(
+                disassemble_bytes(bytes, address),
+                (text.to_string(), len),
+                "{bytes:02X?}"
+            );
+        }
+    }
+
+    #[test]
+    fn targets() {
+        let target = |bytes: &[u8], address: u16| {
+            let read = |addr: u16| bytes[addr.wrapping_sub(address) as usize];
+            disassemble(read, address).target()
+        };
+        assert_eq!(target(&[0x20, 0xFE], 0x150), Some(0x150));
+        assert_eq!(target(&[0x18, 0x80], 0x200), Some(0x182));
+        assert_eq!(target(&[0xC3, 0x50, 0x01], 0x100), Some(0x150));
+        assert_eq!(target(&[0xCC, 0x34, 0x12], 0x100), Some(0x1234));
+        assert_eq!(target(&[0xFF], 0x100), Some(0x38));
+        assert_eq!(target(&[0xE9], 0x100), None);
+        assert_eq!(target(&[0xC9], 0x100), None);
+    }
+}
@@ -0,0 +1,349 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+};
+
+use super::{Disassembly, disassemble};
+use crate::instructions::Instruction;
+
+const BANK_SIZE: usize = 0x4000;
+/// Bytes per `db` line
+const DATA_LINE: usize = 8;
+
+/// Entry point, interrupt vectors and RST targets, where traversal starts
+const ENTRY_POINTS: [(u16, &str); 14] = [
+    (0x0100, "EntryPoint"),
+    (0x0040, "VBlankInterrupt"),
+    (0x0048, "StatInterrupt"),
+    (0x0050, "TimerInterrupt"),
+    (0x0058, "SerialInterrupt"),
+    (0x0060, "JoypadInterrupt"),
+    (0x0000, "Rst00"),
+    (0x0008, "Rst08"),
+    (0x0010, "Rst10"),
+    (0x0018, "Rst18"),
+    (0x0020, "Rst20"),
+    (0x0028, "Rst28"),
+    (0x0030, "Rst30"),
+    (0x0038, "Rst38"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Byte {
+    Data,
+    /// First byte of an instruction
+    Code,
+    /// Immediate or second byte of a CB prefixed instruction
+    Operand,
+}
+
+/// Code and data of a ROM found by following every reachable jump and call
+///
+/// Bank 0 is always at 0x0000-0x3FFF. Code in a switchable bank is assumed to only
+/// jump within its own bank. Jumps from bank 0 into 0x4000-0x7FFF go to the bank last
+/// selected with `LD A,n` and `LD [$2000-$3FFF],A` on the way there, or to bank 1 when
+/// the ROM has no more than 2 banks, and aren't followed otherwise.
+struct Listing<'a> {
+    rom: &'a [u8],
+    bytes: Vec<Byte>,
+    instructions: HashMap<usize, Disassembly>,
+    /// ROM offsets of jump and call targets by the offset of the instruction
+    targets: HashMap<usize, usize>,
+    /// Names by ROM offset
+    labels: BTreeMap<usize, String>,
+}
+
+/// RGBDS assembly of a whole ROM, code is separated from data by recursive traversal
+pub(crate) fn disassemble_rom(rom: &[u8]) -> String {
+    let mut listing = Listing {
+        rom,
+        bytes: vec![Byte::Data; rom.len()],
+        instructions: HashMap::new(),
+        targets: HashMap::new(),
+        labels: BTreeMap::new(),
+    };
+    for (address, name) in ENTRY_POINTS {
+        if address as usize >= rom.len() {
+            continue;
+        }
+        listing.labels.insert(address as usize, name.to_string());
+        listing.trace(address);
+    }
+    listing.output()
+}
+
+impl<'a> Listing<'a> {
+    fn banks(&self) -> usize {
+        self.rom.len().div_ceil(BANK_SIZE)
+    }
+
+    /// Bank holding `address` when jumping there from code in `from`, with the bank
+    /// `selected` in the MBC if it's known
+    fn bank_at(&self, from: usize, address: u16, selected: Option<usize>) -> Option<usize> {
+        match address {
+            0x0000..0x4000 => Some(0),
+            0x4000..0x8000 if from != 0 => Some(from),
+            // Selecting bank 0 maps bank 1 like on most MBCs
+            0x4000..0x8000 if selected.is_some() => selected.map(|bank| bank.max(1)),
+            0x4000..0x8000 if self.banks() <= 2 => Some(1),
+            _ => None,
+        }
+    }
+
+    fn offset(&self, bank: usize, address: u16) -> Option<usize> {
+        let offset = bank * BANK_SIZE + (address as usize & (BANK_SIZE - 1));
+        (offset < self.rom.len()).then_some(offset)
+    }
+
+    /// Name of the label at `offset`, if one can be placed there
+    fn label(&self, offset: usize) -> Option<&String> {
+        match self.bytes[offset] {
+            Byte::Operand => None,
+            _ => self.labels.get(&offset),
+        }
+    }
+
+    /// Decodes instructions from the entry point in bank 0
+    fn trace(&mut self, entry: u16) {
+        let mut queue = vec![(0, entry, None)];
+        while let Some((mut bank, mut address, mut selected)) = queue.pop() {
+            // Value of A when it was loaded by the previous instruction
+            let mut a = None;
+            while let Some(next_bank) = self.bank_at(bank, address, selected) {
+                bank = next_bank;
+                let Some(offset) = self.offset(bank, address) else {
+                    break;
+                };
+                if self.bytes[offset] != Byte::Data {
+                    break;
+                }
+
+                let read = |addr: u16| self.offset(bank, addr).map_or(0, |o| self.rom[o]);
+                let line = disassemble(read, address);
+                let len = line.bytes.len();
+                // Instructions don't cross banks or overlap others
+                if matches!(line.instruction, Instruction::ILLEGAL(_))
+                    || (address as usize & (BANK_SIZE - 1)) + len > BANK_SIZE
+                    || offset + len > self.rom.len()
+                    || self.bytes[offset + 1..offset + len]
+                        .iter()
+                        .any(|b| *b != Byte::Data)
+                {
+                    break;
+                }
+                self.bytes[offset] = Byte::Code;
+                self.bytes[offset + 1..offset + len].fill(Byte::Operand);
+
+                // LD A,n and LD [$2000-$3FFF],A, the ROM bank register of the common MBCs.
+                // The bank isn't known anymore when A wasn't loaded right before.
+                (a, selected) = match line.bytes[..] {
+                    [0x3E, value] => (Some(value as usize), selected),
+                    [0xEA, _, 0x20..0x40] => (None, a),
+                    _ => (None, selected),
+                };
+
+                if let Some(target) = line.target()
+                    && let Some(target_bank) = self.bank_at(bank, target, selected)
+                    && let Some(target_offset) = self.offset(target_bank, target)
+                {
+                    let kind = match line.instruction {
+                        Instruction::CALL(..) | Instruction::RST(_) => "Call",
+                        _ => "Jump",
+                    };
+                    self.labels
+                        .entry(target_offset)
+                        .or_insert_with(|| format!("{kind}_{target_bank:03X}_{target:04X}"));
+                    self.targets.insert(offset, target_offset);
+                    queue.push((target_bank, target, selected));
+                }
+
+                let end = matches!(
+                    line.instruction,
+                    Instruction::JP(None, _)
+                        | Instruction::JR(None, _)
+                        | Instruction::RET(None)
+                        | Instruction::RETI
+                );
+                self.instructions.insert(offset, line);
+                if end {
+                    break;
+                }
+                address = address.wrapping_add(len as u16);
+            }
+        }
+    }
+
+    /// Instruction text in RGBDS syntax, with the target replaced by its label
+    fn instruction_text(&self, offset: usize, line: &Disassembly) -> String {
+        // RGBDS assembles STOP to $10,$00, other second bytes only survive as data
+        if let (Instruction::STOP(_), [opcode, operand @ 1..=0xFF]) =
+            (&line.instruction, &line.bytes[..])
+        {
+            return format!("db ${opcode:02X},${operand:02X}");
+        }
+
+        let mut text = line
+            .text
+            .replace("($FF00+C)", "[C]")
+            .replace('(', "[")
+            .replace(')', "]");
+        if !matches!(line.instruction, Instruction::RST(_))
+            && let Some(target) = line.target()
+            && let Some(target_offset) = self.targets.get(&offset)
+            && let Some(label) = self.label(*target_offset)
+        {
+            text = text.replace(&format!("${target:04X}"), label);
+        }
+        text
+    }
+
+    fn output(&self) -> String {
+        let mut out = String::new();
+        for bank in 0..self.banks() {
+            match bank {
+                0 => writeln!(out, "SECTION \"ROM Bank $000\", ROM0[$0000]"),
+                _ => writeln!(
+                    out,
+                    "\nSECTION \"ROM Bank ${bank:03X}\", ROMX[$4000], BANK[${bank:X}]"
+                ),
+            }
+            .unwrap();
+
+            let end = ((bank + 1) * BANK_SIZE).min(self.rom.len());
+            let mut offset = bank * BANK_SIZE;
+            while offset < end {
+                if let Some(label) = self.label(offset) {
+                    writeln!(out, "\n{label}:").unwrap();
+                }
+
+                if let Some(line) = self.instructions.get(&offset) {
+                    writeln!(out, "    {}", self.instruction_text(offset, line)).unwrap();
+                    offset += line.bytes.len();
+                    continue;
+                }
+
+                // Data runs end at code, labels and line length
+                let start = offset;
+                offset += 1;
+                while offset < end
+                    && offset - start < DATA_LINE
+                    && self.bytes[offset] == Byte::Data
+                    && !self.labels.contains_key(&offset)
+                {
+                    offset += 1;
+                }
+                let data: Vec<_> = self.rom[start..offset]
+                    .iter()
+                    .map(|b| format!("${b:02X}"))
+                    .collect();
+                writeln!(out, "    db {}", data.join(",")).unwrap();
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disassemble_rom;
+
+    #[test]
+    fn traversal() {
+        let mut rom = vec![0xFF; 0x8000];
+        // EntryPoint: NOP, JP $0150
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        // $0150: LD A,$3F; CALL $4000; JR NZ,$0150; LDH [$FF44],A; RET
+        rom[0x150..0x15C].copy_from_slice(&[
+            0x3E, 0x3F, 0xCD, 0x00, 0x40, 0x20, 0xF9, 0xE0, 0x44, 0x07, 0xC9, 0x12,
+        ]);
+        // $4000 in bank 1: LD A,[$FF00+C]; RETI
+        rom[0x4000..0x4002].copy_from_slice(&[0xF2, 0xD9]);
+        rom[0x104..0x150].fill(0);
+
+        let asm = disassemble_rom(&rom);
+        let expected = "\
+EntryPoint:
+    NOP
+    JP Jump_000_0150
+    db $00,$00,$00,$00,$00,$00,$00,$00";
+        assert!(asm.contains(expected), "{asm}");
+        let expected = "
+Jump_000_0150:
+    LD A,$3F
+    CALL Call_001_4000
+    JR NZ,Jump_000_0150
+    LDH [$FF44],A
+    RLCA
+    RET
+    db $12,$FF,";
+        assert!(asm.contains(expected), "{asm}");
+        let expected = "
+SECTION \"ROM Bank $001\", ROMX[$4000], BANK[$1]
+
+Call_001_4000:
+    LDH A,[C]
+    RETI
+    db $FF,";
+        assert!(asm.contains(expected), "{asm}");
+    }
+
+    #[test]
+    fn switchable_bank_with_mbc() {
+        let mut rom = vec![0; 0x10000];
+        // EntryPoint: CALL $4000; JR $0100
+        rom[0x100..0x105].copy_from_slice(&[0xCD, 0x00, 0x40, 0x18, 0xFB]);
+        let asm = disassemble_rom(&rom);
+        // The bank at 0x4000 isn't known, the target stays an address
+        assert!(asm.contains("    CALL $4000\n    JR EntryPoint\n"), "{asm}");
+        assert!(asm.contains("SECTION \"ROM Bank $003\", ROMX[$4000], BANK[$3]"));
+    }
+
+    #[test]
+    fn bank_switch() {
+        let mut rom = vec![0; 0x10000];
+        // EntryPoint: LD A,$02; LD [$2000],A; CALL $4000; LD A,[HL]; LD [$2100],A; JP $4000
+        rom[0x100..0x10C].copy_from_slice(&[
+            0x3E, 0x02, 0xEA, 0x00, 0x20, 0xCD, 0x00, 0x40, 0x7E, 0xEA, 0x00, 0x21,
+        ]);
+        rom[0x10C..0x10F].copy_from_slice(&[0xC3, 0x00, 0x40]);
+        // $4000 in bank 2: INC A; RET
+        rom[0x8000..0x8002].copy_from_slice(&[0x3C, 0xC9]);
+
+        let asm = disassemble_rom(&rom);
+        assert!(
+            asm.contains(
+                "    CALL Call_002_4000
+"
+            ),
+            "{asm}"
+        );
+        // A isn't known at the second bank switch
+        assert!(
+            asm.contains(
+                "    JP $4000
+"
+            ),
+            "{asm}"
+        );
+        let expected = "
+SECTION \"ROM Bank $002\", ROMX[$4000], BANK[$2]
+
+Call_002_4000:
+    INC A
+    RET
+    db $00,";
+        assert!(asm.contains(expected), "{asm}");
+    }
+
+    #[test]
+    fn stop_operand() {
+        let mut rom = vec![0xFF; 0x8000];
+        // EntryPoint: STOP; STOP with $E0 as second byte; RET
+        rom[0x100..0x105].copy_from_slice(&[0x10, 0x00, 0x10, 0xE0, 0xC9]);
+        let asm = disassemble_rom(&rom);
+        assert!(
+            asm.contains("EntryPoint:\n    STOP\n    db $10,$E0\n    RET\n"),
+            "{asm}"
+        );
+    }
+}
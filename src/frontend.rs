@@ -0,0 +1,346 @@
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+    process::exit,
+};
+
+use anyhow::{Context, Error};
+
+use crate::{
+    cli::{Args, Command},
+    cpu::Cpu,
+    debugger::{Debugger, ExecutionState, GdbStub, Symbols, Target},
+    disassembler,
+    history::History,
+    memory_mapping::{MemoryMapping, Rom},
+    rewind::Rewind,
+    save_state,
+    sdl::{Action, SdlInstance},
+    speed::Speed,
+    trace::Trace,
+};
+
+/// Instructions kept by the History window
+const HISTORY_LEN: usize = 10_000;
+
+/// Errors are listed in the debugger, or printed without it
+fn report_error(errors: &mut Vec<(u16, String)>, debug: bool, pc: u16, e: Error) {
+    match debug {
+        true => errors.push((pc, format!("{e:?}"))),
+        false => eprintln!("{e:?}"),
+    }
+}
+
+fn gameboy_emulator(
+    args: &Args,
+    sdl: &mut SdlInstance,
+    debugger: &mut Debugger,
+    rewind: &mut Rewind,
+    speed: &mut Speed,
+    gdb: &mut Option<GdbStub>,
+) -> Result<bool, Error> {
+    let mut texture_creator = sdl.canvas.texture_creator();
+
+    let memory = MemoryMapping::new(Rom::new(args.rom())?);
+    let mut cpu = Cpu::new(memory);
+    if let Some(path) = &args.trace {
+        let file = File::create(path)
+            .with_context(|| format!("Couldn't create trace file {}", path.display()))?;
+        cpu.trace = Some(Trace::new(BufWriter::new(file), args.trace_extended));
+    }
+    if args.debug {
+        cpu.history = Some(History::new(HISTORY_LEN));
+    }
+
+    cpu.memory.vram.create_textures(&mut texture_creator)?;
+    rewind.clear();
+
+    let mut errors: Vec<(u16, String)> = Vec::new();
+
+    'main: loop {
+        // Handle sdl events
+        for action in sdl.handle_event(debugger) {
+            let result = match action {
+                Action::Quit => break 'main,
+                Action::SaveState(slot) => save_state::save_slot(&cpu, args.rom(), slot),
+                Action::LoadState(slot) => save_state::load_slot(&mut cpu, args.rom(), slot),
+                Action::Rewind(held) => {
+                    rewind.rewinding = held;
+                    Ok(())
+                }
+                Action::FastForward(held) => {
+                    speed.fast_forward = held;
+                    Ok(())
+                }
+                Action::FrameAdvance => {
+                    debugger.execution_state = ExecutionState::Frame;
+                    Ok(())
+                }
+                Action::TogglePause => {
+                    debugger.toggle_pause();
+                    Ok(())
+                }
+                // Stepping hotkeys only apply while paused, like the buttons
+                Action::Step | Action::StepOver | Action::StepOut | Action::StepBack
+                    if debugger.execution_state.running() =>
+                {
+                    Ok(())
+                }
+                Action::Step => {
+                    debugger.execution_state = ExecutionState::Step;
+                    Ok(())
+                }
+                Action::StepOver => {
+                    debugger.execution_state = ExecutionState::step_over(&cpu);
+                    Ok(())
+                }
+                Action::StepOut => {
+                    debugger.execution_state = ExecutionState::step_out(&cpu);
+                    Ok(())
+                }
+                Action::Scanline => {
+                    debugger.execution_state = ExecutionState::scanline(&cpu);
+                    Ok(())
+                }
+                Action::StepBack => {
+                    debugger.execution_state = ExecutionState::StepBack;
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                report_error(&mut errors, args.debug, cpu.registers.pc, e);
+            }
+        }
+
+        if let Some(gdb) = gdb {
+            gdb.poll(&mut Target {
+                cpu: &mut cpu,
+                breakpoints: &mut debugger.breakpoints,
+                watchpoints: &mut debugger.watchpoints,
+                execution_state: &mut debugger.execution_state,
+            });
+        }
+
+        if cpu.memory.watchpoints != debugger.watchpoints.list {
+            cpu.memory
+                .watchpoints
+                .clone_from(&debugger.watchpoints.list);
+        }
+
+        // Run a frame, or a single instruction when stepping
+        let running = match debugger.execution_state {
+            _ if rewind.rewinding => {
+                // One snapshot is restored for every displayed frame
+                rewind.step_back(&mut cpu)?;
+                false
+            }
+            ExecutionState::Pause => false,
+            ExecutionState::Step => {
+                debugger.execution_state = ExecutionState::Pause;
+                let pc = cpu.registers.pc;
+                if let Err(e) = cpu.step() {
+                    report_error(&mut errors, args.debug, pc, e);
+                }
+                if let Some(hit) = cpu.memory.take_watchpoint_hit() {
+                    debugger.watchpoints.hit = Some((pc, hit));
+                }
+                rewind.update(&cpu);
+                false
+            }
+            ExecutionState::StepBack => {
+                debugger.execution_state = ExecutionState::Pause;
+                cpu.step_back();
+                false
+            }
+            _ => {
+                let breakpoints = &mut debugger.breakpoints;
+                let state = &debugger.execution_state;
+                let mut watchpoint_hit = None;
+                let finished = cpu.run_frame(|cpu, pc, result| {
+                    if let Err(e) = result {
+                        report_error(&mut errors, args.debug, pc, e);
+                    }
+                    rewind.update(cpu);
+                    if let Some(hit) = cpu.memory.take_watchpoint_hit() {
+                        watchpoint_hit = Some((pc, hit));
+                        return true;
+                    }
+                    breakpoints.check(&cpu.registers, &cpu.memory) || state.finished(cpu, pc)
+                });
+                if watchpoint_hit.is_some() {
+                    debugger.watchpoints.hit = watchpoint_hit;
+                }
+                if finished {
+                    debugger.frame_finished();
+                    speed.frame_finished();
+                } else {
+                    debugger.execution_state = ExecutionState::Pause;
+                }
+                true
+            }
+        };
+
+        sdl.set_vsync(args.vsync && speed.multiplier() == Some(1.));
+        speed.wait(running, sdl.vsync());
+
+        cpu.memory.vram.update_textures()?;
+
+        // Update graphics
+        if let Some(mut token) = sdl.update_graphics(debugger) {
+            let sdl = &mut token.0;
+
+            cpu.memory.vram.display_screen(&mut sdl.canvas)?;
+
+            if !args.debug {
+                continue;
+            }
+            let ui = debugger.imgui_context.new_frame();
+
+            let reset = Debugger::display_execution_debugger(
+                ui,
+                &mut debugger.execution_state,
+                speed,
+                &cpu,
+                &debugger.symbols,
+                &mut debugger.run_to_input,
+            );
+            Debugger::display_breakpoint_debugger(
+                ui,
+                &mut debugger.breakpoints,
+                &mut debugger.watchpoints,
+                cpu.registers.pc,
+                &cpu.memory,
+                &debugger.symbols,
+            );
+            debugger.disassembly.display(
+                ui,
+                &cpu.memory,
+                cpu.registers.pc,
+                &mut debugger.breakpoints,
+                &debugger.symbols,
+                &mut debugger.execution_state,
+            );
+            Debugger::display_call_stack(ui, &cpu, &debugger.symbols, &mut debugger.disassembly);
+            Debugger::display_history(
+                ui,
+                &cpu,
+                &mut debugger.execution_state,
+                &mut debugger.disassembly,
+            );
+
+            debugger.console.display(
+                ui,
+                &mut Target {
+                    cpu: &mut cpu,
+                    breakpoints: &mut debugger.breakpoints,
+                    watchpoints: &mut debugger.watchpoints,
+                    execution_state: &mut debugger.execution_state,
+                },
+                &debugger.symbols,
+                args.rom(),
+            );
+
+            cpu.registers
+                .display_debugger(ui, &mut cpu.memory.interrupt);
+            Debugger::display_io(ui, &mut cpu.memory);
+            cpu.memory
+                .display_debugger(ui, cpu.registers.pc, &debugger.symbols);
+            cpu.memory.vram.display_debugger(ui);
+            rewind.display_debugger(ui);
+
+            ui.window("Errors")
+                .position([500., 50.], imgui::Condition::FirstUseEver)
+                .size([300., 200.], imgui::Condition::FirstUseEver)
+                .horizontal_scrollbar(true)
+                .build(|| {
+                    for (pc, err) in &errors {
+                        ui.text(format!("PC: 0x{pc:04x} -> {err}"));
+                    }
+                });
+
+            debugger.render(&mut sdl.canvas, &cpu.memory.vram.textures)?;
+            if reset {
+                return Ok(true);
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+/// Writes the RGBDS assembly of `file` to `output`, or stdout
+fn disasm(file: &Path, output: Option<&Path>) -> Result<(), Error> {
+    let rom = fs::read(file).with_context(|| format!("Couldn't read {}", file.display()))?;
+    let asm = disassembler::disassemble_rom(&rom);
+    match output {
+        Some(output) => fs::write(output, asm)
+            .with_context(|| format!("Couldn't write {}", output.display()))?,
+        None => print!("{asm}"),
+    }
+    Ok(())
+}
+
+/// Runs the emulator window with the command line arguments
+pub fn run() {
+    let args = Args::new();
+    if let Some(Command::Disasm { file, output }) = &args.command {
+        if let Err(e) = disasm(file, output.as_deref()) {
+            eprintln!("{e:?}");
+            exit(1);
+        }
+        return;
+    }
+    let debugger_str = if args.debug { " (Debug)" } else { "" };
+    let window_name = format!(
+        "Emulator{}: {}",
+        debugger_str,
+        args.rom().to_str().unwrap_or("")
+    );
+
+    let mut sdl = SdlInstance::new(&window_name, 1600, 900).expect("Error Initializing SDL");
+    let texture_creator = sdl.canvas.texture_creator();
+    let mut debugger = Debugger::new(&texture_creator).expect("Error Initializing Imgui");
+    if !args.debug {
+        debugger.execution_state = ExecutionState::Execute;
+    }
+    if let Some(path) = args.symbols() {
+        match Symbols::load(&path) {
+            Ok(symbols) => debugger.symbols = symbols,
+            Err(e) => {
+                eprintln!("{e:?}");
+                exit(1);
+            }
+        }
+    }
+
+    let mut gdb = match args.gdb.map(GdbStub::bind).transpose() {
+        Ok(gdb) => gdb,
+        Err(e) => {
+            eprintln!("{e:?}");
+            exit(1);
+        }
+    };
+
+    let mut rewind = Rewind::new();
+    let mut speed = Speed::new();
+    loop {
+        match gameboy_emulator(
+            &args,
+            &mut sdl,
+            &mut debugger,
+            &mut rewind,
+            &mut speed,
+            &mut gdb,
+        ) {
+            Err(e) => {
+                eprintln!("{e:?}");
+                exit(1);
+            }
+            Ok(false) => {
+                break;
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::{
+    cpu::Cpu,
+    interrupt::InterruptPosition,
+    joypad::Button,
+    memory_mapping::{MemoryMapping, Rom},
+    save_state,
+    trace::Trace,
+};
+
+/// A Game Boy to embed in other programs, independent of the SDL frontend
+#[derive(Debug)]
+pub struct GameBoy {
+    cpu: Cpu<'static>,
+}
+
+impl GameBoy {
+    /// Powers on with the ROM loaded, in the state left by the boot ROM
+    pub fn new(rom: Vec<u8>) -> Self {
+        Self {
+            cpu: Cpu::new(MemoryMapping::new(Rom { rom })),
+        }
+    }
+
+    /// Runs one instruction, returns the M-cycles it took
+    pub fn step(&mut self) -> Result<u16> {
+        self.cpu.step()
+    }
+
+    /// Runs until the PPU enters VBlank, stops at the first failed instruction
+    pub fn run_frame(&mut self) -> Result<()> {
+        let mut error = None;
+        self.cpu.run_frame(|_, _, result| match result {
+            Ok(_) => false,
+            Err(e) => {
+                error = Some(e);
+                true
+            }
+        });
+        error.map_or(Ok(()), Err)
+    }
+
+    /// Sets all held buttons, the others are released
+    pub fn set_buttons(&mut self, buttons: &[Button]) {
+        let pressed = buttons.iter().fold(0, |pressed, b| pressed | u8::from(*b));
+        if self.cpu.memory.joypad.set_pressed(pressed) {
+            self.cpu
+                .memory
+                .interrupt
+                .request_int(InterruptPosition::Joypad);
+        }
+    }
+
+    /// Width and height of the framebuffer, 256x224 when the SGB border is shown
+    pub fn screen_size(&self) -> (usize, usize) {
+        self.cpu.memory.vram.output_size()
+    }
+
+    /// RGB24 pixels of the screen, row by row
+    pub fn framebuffer(&mut self) -> &[u8] {
+        self.cpu.memory.vram.update_sgb_border();
+        &self.cpu.memory.vram.framebuffer
+    }
+
+    /// Stereo samples generated since the last call
+    ///
+    /// Audio isn't emulated yet, no samples are generated.
+    pub fn audio_samples(&mut self) -> Vec<[f32; 2]> {
+        Vec::new()
+    }
+
+    /// Reads memory like the debugger, without the access restrictions of the CPU
+    pub fn read_memory(&self, address: u16) -> Result<u8> {
+        self.cpu.memory.get(address).copied()
+    }
+
+    /// Writes memory like the debugger, without side effects of register writes
+    pub fn write_memory(&mut self, address: u16, value: u8) -> Result<()> {
+        *self.cpu.memory.get_mut(address)? = value;
+        Ok(())
+    }
+
+    /// Logs every instruction to `writer` in Gameboy Doctor format, like `--trace`
+    ///
+    /// `extended` adds M-cycles, LY and the instruction to every line.
+    pub fn start_trace(&mut self, writer: impl Write + 'static, extended: bool) {
+        self.cpu.trace = Some(Trace::new(writer, extended));
+    }
+
+    pub fn stop_trace(&mut self) {
+        self.cpu.trace = None;
+    }
+
+    /// Save state in the same format as the frontend's slots
+    pub fn save_state(&self) -> Vec<u8> {
+        save_state::save(&self.cpu)
+    }
+
+    /// Fails without changing the machine when the state is invalid or from another ROM
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        save_state::load(&mut self.cpu, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GameBoy;
+    use crate::joypad::Button;
+
+    #[test]
+    fn embedding() {
+        let mut gameboy = GameBoy::new(vec![0; 0x8000]);
+        gameboy.run_frame().unwrap();
+        assert_eq!(gameboy.screen_size(), (160, 144));
+        assert_eq!(gameboy.framebuffer().len(), 160 * 144 * 3);
+
+        gameboy.write_memory(0xC000, 0x42).unwrap();
+        let state = gameboy.save_state();
+        gameboy.write_memory(0xC000, 0).unwrap();
+        gameboy.load_state(&state).unwrap();
+        assert_eq!(gameboy.read_memory(0xC000).unwrap(), 0x42);
+
+        gameboy.write_memory(0xFF00, 0xEF).unwrap();
+        gameboy.set_buttons(&[Button::Down]);
+        assert_eq!(gameboy.read_memory(0xFF00).unwrap() & 0x0F, 0x07);
+        assert_eq!(gameboy.read_memory(0xFF0F).unwrap() & 0x10, 0x10);
+    }
+}
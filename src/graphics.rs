@@ -1,26 +1,45 @@
+#[cfg(not(feature = "frontend"))]
+use std::marker::PhantomData;
 use std::{
     fmt::Debug,
     ops::{Index, IndexMut},
 };
 
-use anyhow::{Result, bail};
-use imgui::{Image, TextureId, Ui};
-use sdl3::{
-    pixels::{Color, Palette, PixelFormat},
-    rect::Rect,
-    render::{Canvas, FRect, Texture, TextureCreator},
-    video::{Window, WindowContext},
-};
+use anyhow::Result;
+#[cfg(feature = "frontend")]
+use imgui::TextureId;
+#[cfg(feature = "frontend")]
+use sdl3::render::Texture;
+
+#[cfg(feature = "frontend")]
+mod display;
+mod fifo;
 
-use crate::{interrupt::Interrupt, utils::BitFlag};
+pub(crate) use fifo::Renderer;
+
+use crate::{
+    interrupt::{Interrupt, InterruptPosition},
+    save_state::{Apply, SaveState, StateReader, StateWriter},
+    sgb::{self, Mask, Sgb},
+    utils::BitFlag,
+};
 
-static DEFAULT_COLORS: [Color; 4] = [
-    Color::RGB(0xc4, 0xf0, 0xc2),
-    Color::RGB(0x5a, 0xb9, 0xa8),
-    Color::RGB(0x1e, 0x60, 0x6e),
-    Color::RGB(0x2d, 0x1b, 0x00),
+static DEFAULT_COLORS: [[u8; 3]; 4] = [
+    [0xc4, 0xf0, 0xc2],
+    [0x5a, 0xb9, 0xa8],
+    [0x1e, 0x60, 0x6e],
+    [0x2d, 0x1b, 0x00],
 ];
 
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+/// RGB24 screen at half resolution stored in save states
+pub const THUMBNAIL_SIZE: usize = SCREEN_WIDTH / 2 * SCREEN_HEIGHT / 2 * 3;
+
+const DOTS_PER_LINE: u16 = 456;
+const LINES_PER_FRAME: u8 = 154;
+const OAM_SCAN_DOTS: u16 = 80;
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
 pub(crate) enum LcdControl {
@@ -70,14 +89,93 @@ impl From<LcdStatus> for u8 {
     }
 }
 
-#[derive(Debug, Default)]
-struct DebuggerContext {
-    page: usize,
-    palette_colors: [Color; 4],
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PpuMode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+/// Shared layout of BG map attributes (VRAM bank 1) and OAM attributes
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum TileAttributes {
+    /// BG: 1 = BG colors 1–3 are drawn over OBJ
+    /// OBJ: 1 = BG and Window colors 1–3 are drawn over this OBJ
+    Priority = 0x80,
+    /// 0 = Normal; 1 = Vertically mirrored
+    YFlip = 0x40,
+    /// 0 = Normal; 1 = Horizontally mirrored
+    XFlip = 0x20,
+    /// [Non CGB Mode, OBJ only]: 0 = OBP0; 1 = OBP1
+    DmgPalette = 0x10,
+    /// [CGB Mode Only]: Fetch tile from VRAM bank 0 or 1
+    Bank = 0x8,
+    /// [CGB Mode Only]: Which of BGP0–7 / OBP0–7 to use
+    CgbPalette = 0b111,
+}
+
+impl From<TileAttributes> for u8 {
+    fn from(value: TileAttributes) -> Self {
+        value as u8
+    }
+}
+
+/// CGB palette memory accessed through BCPS/BCPD or OCPS/OCPD
+#[derive(Debug)]
+pub(crate) struct PaletteRam {
+    /// Bit 7: auto increment, bits 0-5: address
+    pub spec: u8,
+    /// 8 palettes of 4 colors, 2 bytes per color (little endian RGB555)
+    pub data: [u8; 64],
+}
+
+impl Default for PaletteRam {
+    fn default() -> Self {
+        Self {
+            spec: 0,
+            data: [0xFF; 64],
+        }
+    }
+}
+
+impl PaletteRam {
+    pub fn value(&self) -> &u8 {
+        &self.data[(self.spec & 0x3F) as usize]
+    }
+
+    pub fn value_mut(&mut self) -> &mut u8 {
+        &mut self.data[(self.spec & 0x3F) as usize]
+    }
+
+    /// Called after a write to the data register
+    pub fn auto_increment(&mut self) {
+        if self.spec & 0x80 != 0 {
+            self.spec = 0x80 | (self.spec.wrapping_add(1) & 0x3F);
+        }
+    }
+
+    pub fn color(&self, palette: u8, color_idx: u8) -> u16 {
+        let addr = (palette as usize & 0b111) * 8 + color_idx as usize * 2;
+        u16::from_le_bytes([self.data[addr], self.data[addr + 1]])
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sprite {
+    y: u8,
+    x: u8,
+    tile: u8,
+    attributes: u8,
+    /// Position in OAM
+    index: u8,
 }
 
 pub(crate) struct Graphics<'a> {
-    pub vram: [u8; 0x2000],
+    /// 2 banks of 0x2000, bank 1 is only used in CGB mode
+    pub vram: [u8; 0x4000],
+    pub oam: [u8; 0xA0],
     pub lcd_control: BitFlag<u8, LcdControl>,
     pub scroll_x: u8,
     pub scroll_y: u8,
@@ -85,301 +183,634 @@ pub(crate) struct Graphics<'a> {
     x_coord: u16,
     pub y_comp: u8,
     pub lcd_status: BitFlag<u8, LcdStatus>,
+    mode3_length: u16,
+    stat_line: bool,
+    /// The first frame after the LCD is turned on isn't displayed
+    skip_frame: bool,
+    pub renderer: Renderer,
+    fifo: fifo::PixelFifo,
+    /// Number of VBlanks since power on, not part of save states
+    pub frame_count: u64,
+    pub bg_palette: u8,
+    pub obj_palette: [u8; 2],
+    pub window_y: u8,
+    pub window_x: u8,
+    window_line: u8,
+
+    pub cgb: bool,
+    /// VBK, bit 0 selects the bank and the other bits read as 1
+    pub vram_bank: u8,
+    pub bg_palettes: PaletteRam,
+    pub obj_palettes: PaletteRam,
+    pub color_correction: bool,
+    pub sgb: Option<Sgb>,
+
+    /// RGB24 output of the PPU, including the SGB border
+    pub framebuffer: Vec<u8>,
+    #[cfg(feature = "frontend")]
+    changed_lines: Vec<u8>,
+    dmg_colors: [[u8; 3]; 4],
+
+    #[cfg(feature = "frontend")]
     pub textures: Vec<Texture<'a>>,
+    #[cfg(not(feature = "frontend"))]
+    textures: PhantomData<&'a ()>,
+    #[cfg(feature = "frontend")]
     changed_textures: Vec<u16>,
 
+    #[cfg(feature = "frontend")]
     pub bg_id: Option<TextureId>,
 
-    debug: DebuggerContext,
+    #[cfg(feature = "frontend")]
+    debug: display::DebuggerContext,
 }
 
 impl<'a> Graphics<'a> {
     pub(crate) fn new() -> Self {
         Graphics {
-            vram: [0; 0x2000],
-            lcd_control: BitFlag::default(),
+            vram: [0; 0x4000],
+            oam: [0; 0xA0],
+            // Value after the boot ROM
+            lcd_control: BitFlag::new(0x91),
             scroll_x: 0,
             scroll_y: 0,
             y_coord: 0,
             x_coord: 0,
             y_comp: 0,
             lcd_status: BitFlag::default(),
+            mode3_length: 172,
+            stat_line: false,
+            skip_frame: false,
+            renderer: Renderer::default(),
+            fifo: fifo::PixelFifo::default(),
+            frame_count: 0,
+            bg_palette: 0xFC,
+            obj_palette: [0; 2],
+            window_y: 0,
+            window_x: 0,
+            window_line: 0,
+            cgb: false,
+            vram_bank: 0xFE,
+            bg_palettes: PaletteRam::default(),
+            obj_palettes: PaletteRam::default(),
+            color_correction: true,
+            sgb: None,
+            framebuffer: vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+            #[cfg(feature = "frontend")]
+            changed_lines: Vec::new(),
+            dmg_colors: DEFAULT_COLORS,
+            #[cfg(feature = "frontend")]
             textures: Vec::new(),
+            #[cfg(not(feature = "frontend"))]
+            textures: PhantomData,
+            #[cfg(feature = "frontend")]
             changed_textures: Vec::new(),
+            #[cfg(feature = "frontend")]
             bg_id: None,
-            debug: DebuggerContext::default(),
-        }
-    }
-
-    pub fn create_textures(
-        &mut self,
-        texture_creator: &'a mut TextureCreator<WindowContext>,
-    ) -> Result<()> {
-        self.debug.palette_colors = DEFAULT_COLORS;
-        let palette = Palette::with_colors(&DEFAULT_COLORS)?;
-
-        // Test sprite
-        //self.vram[0..16].copy_from_slice(&[
-        //    0x3C, 0x7E, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x7E, 0x5E, 0x7E, 0x0A, 0x7C, 0x56,
-        //    0x38, 0x7C,
-        //]);
-
-        let textures = (0..0x2000 / 16)
-            .map(|_| {
-                let mut texture = texture_creator
-                    .create_texture_streaming(PixelFormat::INDEX8, 8, 8)
-                    .expect("Error creating texture");
-                unsafe {
-                    sdl3_sys::render::SDL_SetTexturePalette(texture.raw(), palette.raw());
+            #[cfg(feature = "frontend")]
+            debug: display::DebuggerContext::default(),
+        }
+    }
+
+    /// SGB mode renders the screen inside a 256x224 border
+    pub fn enable_sgb(&mut self) {
+        self.sgb = Some(Sgb::default());
+        self.framebuffer = vec![0xFF; sgb::SGB_WIDTH * sgb::SGB_HEIGHT * 3];
+    }
+
+    /// Size of the framebuffer
+    pub fn output_size(&self) -> (usize, usize) {
+        match self.sgb {
+            Some(_) => (sgb::SGB_WIDTH, sgb::SGB_HEIGHT),
+            None => (SCREEN_WIDTH, SCREEN_HEIGHT),
+        }
+    }
+
+    /// Position of the Game Boy screen in the framebuffer
+    fn screen_offset(&self) -> (usize, usize) {
+        match self.sgb {
+            Some(_) => (sgb::SCREEN_X, sgb::SCREEN_Y),
+            None => (0, 0),
+        }
+    }
+
+    /// Advances the PPU by `cycles` M-cycles (4 dots each)
+    ///
+    /// The scanline renderer updates each line at once when Mode 3 ends,
+    /// the FIFO renderer outputs one pixel per dot
+    ///
+    /// Returns true when HBlank was entered
+    pub fn do_cycles(&mut self, cycles: u16, interrupt: &mut Interrupt) -> Result<bool> {
+        if !self.lcd_control.get(LcdControl::Enable) {
+            return Ok(false);
+        }
+
+        let mut hblank = false;
+        for _ in 0..cycles as u32 * 4 {
+            hblank |= self.do_dot(interrupt);
+        }
+        Ok(hblank)
+    }
+
+    fn do_dot(&mut self, interrupt: &mut Interrupt) -> bool {
+        self.x_coord += 1;
+        if self.x_coord == DOTS_PER_LINE {
+            self.x_coord = 0;
+            self.y_coord = (self.y_coord + 1) % LINES_PER_FRAME;
+        }
+
+        let old_mode = self.mode();
+        let mode = if self.y_coord >= SCREEN_HEIGHT as u8 {
+            PpuMode::VBlank
+        } else if self.x_coord < OAM_SCAN_DOTS {
+            PpuMode::OamScan
+        } else if self.x_coord < OAM_SCAN_DOTS + self.mode3_length {
+            PpuMode::Drawing
+        } else {
+            PpuMode::HBlank
+        };
+
+        if self.x_coord == OAM_SCAN_DOTS && mode == PpuMode::Drawing {
+            self.mode3_length = match self.renderer {
+                Renderer::Scanline => self.mode3_length(self.y_coord),
+                Renderer::Fifo => {
+                    self.fifo_start(self.y_coord);
+                    // Ends when the last pixel is pushed
+                    DOTS_PER_LINE - OAM_SCAN_DOTS
                 }
-                texture.set_scale_mode(sdl3::render::ScaleMode::Nearest);
-                texture
-            })
-            .collect();
+            };
+        }
+        if mode == PpuMode::Drawing && self.fifo.active && self.fifo_dot(self.y_coord) {
+            self.mode3_length = self.x_coord + 1 - OAM_SCAN_DOTS;
+        }
+
+        let mut hblank = false;
+        if mode != old_mode {
+            match mode {
+                PpuMode::HBlank => {
+                    match self.fifo.active {
+                        true => self.fifo_finish(self.y_coord),
+                        false => self.render_scanline(self.y_coord),
+                    }
+                    hblank = true;
+                }
+                PpuMode::VBlank => {
+                    self.frame_count += 1;
+                    self.skip_frame = false;
+                    self.window_line = 0;
+                    interrupt.request_int(InterruptPosition::VBlank);
+                }
+                PpuMode::OamScan | PpuMode::Drawing => {}
+            }
+            self.lcd_status.value = (self.lcd_status.value & !0b11) | mode as u8;
+        }
 
-        self.textures = textures;
+        self.lcd_status
+            .set(LcdStatus::LYCEqLY, self.y_coord == self.y_comp);
+        self.update_stat_line(interrupt);
 
-        self.textures.push({
-            let mut texture = texture_creator
-                .create_texture_streaming(PixelFormat::INDEX8, 160, 144)
-                .expect("Error creating texture");
-            unsafe {
-                sdl3_sys::render::SDL_SetTexturePalette(texture.raw(), palette.raw());
+        hblank
+    }
+
+    /// Called when LCDC is written
+    pub fn set_lcd_control(&mut self, value: u8) {
+        let was_enabled = self.lcd_control.get(LcdControl::Enable);
+        self.lcd_control.value = value;
+
+        match (was_enabled, self.lcd_control.get(LcdControl::Enable)) {
+            (true, false) => {
+                self.x_coord = 0;
+                self.y_coord = 0;
+                self.lcd_status.value &= !(LcdStatus::PPUMode as u8);
+                self.stat_line = false;
+                self.window_line = 0;
+                self.fifo.active = false;
+                self.blank_screen();
             }
-            texture.set_scale_mode(sdl3::render::ScaleMode::Nearest);
-            texture
-        });
-        // Considering +1 for font texture
-        self.bg_id = Some(TextureId::new(self.textures.len()));
+            (false, true) => {
+                self.skip_frame = true;
+                self.lcd_status
+                    .set(LcdStatus::LYCEqLY, self.y_coord == self.y_comp);
+            }
+            _ => {}
+        }
+    }
+
+    /// VRAM can't be accessed by the CPU during mode 3
+    pub fn vram_accessible(&self) -> bool {
+        self.mode() != PpuMode::Drawing
+    }
 
-        Ok(())
+    /// OAM can't be accessed by the CPU during modes 2 and 3
+    pub fn oam_accessible(&self) -> bool {
+        !matches!(self.mode(), PpuMode::OamScan | PpuMode::Drawing)
     }
 
-    pub fn update_textures(&mut self) -> Result<()> {
-        for i in &self.changed_textures {
-            let tex_id = *i as usize / 16;
-            self.textures[tex_id].with_lock(None, |data, _| {
-                let (start, end) = (tex_id * 16, tex_id * 16 + 16);
-                let m = to_8bit_indexed(&self.vram[start..end]);
-                data.copy_from_slice(&m);
-            })?;
+    /// Screen without the SGB border at half resolution
+    pub fn thumbnail(&self) -> Vec<u8> {
+        let (offset_x, offset_y) = self.screen_offset();
+        let width = self.output_size().0;
+        let mut thumbnail = Vec::with_capacity(THUMBNAIL_SIZE);
+        for y in (0..SCREEN_HEIGHT).step_by(2) {
+            for x in (0..SCREEN_WIDTH).step_by(2) {
+                let start = ((y + offset_y) * width + x + offset_x) * 3;
+                thumbnail.extend_from_slice(&self.framebuffer[start..start + 3]);
+            }
         }
-        self.changed_textures = Vec::new();
-        Ok(())
+        thumbnail
     }
 
-    /// Updating each line at once after 172 dots in Mode 3 (ignoring penalties)
-    /// TODO: Penalties and update each dot instead of whole line
-    pub fn do_cycles(&mut self, cycles: u8, interrupt: &mut Interrupt) -> Result<()> {
-        let (old_x, old_y) = (self.x_coord, self.y_coord);
+    /// Fills the screen with white while the LCD is off
+    fn blank_screen(&mut self) {
+        let (offset_x, offset_y) = self.screen_offset();
+        let width = self.output_size().0;
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let color = match self.cgb {
+                    true => [0xFF; 3],
+                    false => self.dmg_color(0, 0, x, y as u8),
+                };
+                let start = ((y + offset_y) * width + x + offset_x) * 3;
+                self.framebuffer[start..start + 3].copy_from_slice(&color);
+            }
+            #[cfg(feature = "frontend")]
+            self.changed_lines.push((y + offset_y) as u8);
+        }
+    }
 
-        self.x_coord += cycles as u16;
-        if self.x_coord > 456 {
-            self.x_coord %= 456;
-            self.y_coord += 1;
+    pub fn mode(&self) -> PpuMode {
+        match self.lcd_status.value & LcdStatus::PPUMode as u8 {
+            0 => PpuMode::HBlank,
+            1 => PpuMode::VBlank,
+            2 => PpuMode::OamScan,
+            _ => PpuMode::Drawing,
         }
-        if self.y_coord >= 154 {
-            self.y_coord %= 154
+    }
+
+    /// STAT interrupt is requested on the rising edge of the OR of all enabled sources,
+    /// a source can't trigger while another one keeps the line high ("STAT blocking")
+    fn update_stat_line(&mut self, interrupt: &mut Interrupt) {
+        let mode = self.mode();
+        let line = (self.lcd_status.get(LcdStatus::LYCInt)
+            && self.lcd_status.get(LcdStatus::LYCEqLY))
+            || (self.lcd_status.get(LcdStatus::Mode0Int) && mode == PpuMode::HBlank)
+            || (self.lcd_status.get(LcdStatus::Mode1Int) && mode == PpuMode::VBlank)
+            || (self.lcd_status.get(LcdStatus::Mode2Int) && mode == PpuMode::OamScan);
+
+        if line && !self.stat_line {
+            interrupt.request_int(InterruptPosition::Lcd);
         }
+        self.stat_line = line;
+    }
 
-        let mode0_int =
-            old_x <= 252 && self.x_coord > 252 && self.lcd_status.get(LcdStatus::Mode0Int);
-        let mode1_int =
-            old_y <= 143 && self.y_coord > 143 && self.lcd_status.get(LcdStatus::Mode1Int);
-        let mode2_int =
-            old_x > 80 && self.x_coord <= 80 && self.lcd_status.get(LcdStatus::Mode2Int);
-        let lyc_int = old_y != self.y_coord
-            && self.y_coord == self.y_comp
-            && self.lcd_status.get(LcdStatus::LYCInt);
+    /// 172 dots plus penalties for fine scrolling, the window and objects
+    fn mode3_length(&self, y_coord: u8) -> u16 {
+        let mut length = 172 + (self.scroll_x % 8) as u16;
 
-        if mode0_int || mode1_int || mode2_int || lyc_int {
-            interrupt.request_int(crate::interrupt::InterruptPosition::Lcd);
+        if self.window_visible(y_coord) {
+            length += 6;
         }
 
-        if old_x <= 252 && self.x_coord > 252 && self.y_coord < 144 {
-            self.update_background_texture(self.y_coord)?;
+        if self.lcd_control.get(LcdControl::OBJEnable) {
+            for sprite in self.line_sprites(y_coord) {
+                length += self.sprite_penalty(&sprite);
+            }
         }
 
-        Ok(())
+        length
     }
 
-    fn update_background_texture(&mut self, y_coord: u8) -> Result<()> {
-        let Some(bg_id) = self.bg_id else {
-            bail!("Background texture not created");
-        };
+    /// Dots the object fetch stalls Mode 3
+    fn sprite_penalty(&self, sprite: &Sprite) -> u16 {
+        let offset = (sprite.x.wrapping_add(self.scroll_x) % 8) as u16;
+        11 - offset.min(5)
+    }
 
-        let Some(bg) = self.textures.get_mut(bg_id.id() - 1) else {
-            bail!("Invalid background texture id");
-        };
+    fn window_visible(&self, y_coord: u8) -> bool {
+        // In CGB mode the bit only removes BG/Window priority over objects
+        (self.cgb || self.lcd_control.get(LcdControl::BGWindowEnable))
+            && self.lcd_control.get(LcdControl::WindowEnable)
+            && y_coord >= self.window_y
+            && self.window_x <= 166
+    }
 
-        let y_line = Rect::new(0, y_coord.into(), 160, 1);
+    /// Renders background, window and objects of line `y_coord` into the framebuffer
+    fn render_scanline(&mut self, y_coord: u8) {
+        if self.skip_frame {
+            return;
+        }
+
+        // Color index and BG-to-OBJ priority attribute of every BG/Window pixel
+        let mut bg_line = [(0u8, false); SCREEN_WIDTH];
+        let mut line = [[0u8; 3]; SCREEN_WIDTH];
+
+        // In CGB mode the bit only removes BG/Window priority over objects
+        let bg_enabled = self.cgb || self.lcd_control.get(LcdControl::BGWindowEnable);
+        let window_visible = self.window_visible(y_coord);
+
+        for (x, pixel) in line.iter_mut().enumerate() {
+            if !bg_enabled {
+                *pixel = self.dmg_color(self.bg_palette, 0, x, y_coord);
+                continue;
+            }
 
-        bg.with_lock(y_line, |data, _| {
-            let tile_map_start_addr = if self.lcd_control.get(LcdControl::BGTileMap) {
+            let in_window = window_visible && x + 7 >= self.window_x as usize;
+            let (tile_map_bit, map_x, map_y) = if in_window {
+                (
+                    LcdControl::WindowTileMap,
+                    x + 7 - self.window_x as usize,
+                    self.window_line as usize,
+                )
+            } else {
+                (
+                    LcdControl::BGTileMap,
+                    (x + self.scroll_x as usize) % 256,
+                    y_coord.wrapping_add(self.scroll_y) as usize,
+                )
+            };
+            let tile_map_start_addr = if self.lcd_control.get(tile_map_bit) {
                 0x1C00
             } else {
                 0x1800
             };
-            //let starting_tile_map = 0x1800;
-            let offset_y_pixels = y_coord.wrapping_add(self.scroll_y) as usize;
-            let tile_map_y_offset = 256 / 8 * (offset_y_pixels / 8);
-            let tile_map_y_start = tile_map_start_addr + tile_map_y_offset;
-
-            let tile_data_bit = self.lcd_control.get(LcdControl::BGWindowTileData);
-
-            let mut i = 0;
-            while i < 160 {
-                let (start, end) = match i {
-                    0 => (self.scroll_x as usize % 8, 8),
-                    // Ending 7 pixels remaining
-                    153..160 => (0, self.scroll_x as usize % 8),
-                    160.. => unreachable!(),
-                    _ => (0, 8),
-                };
+            let tile_map = tile_map_start_addr + 32 * (map_y / 8) + map_x / 8;
+            let attributes = BitFlag::<u8, TileAttributes>::new(self.bg_attributes(tile_map));
+            let color_idx = self.bg_tile_row(tile_map, map_y % 8)[map_x % 8];
 
-                let offset_x_pixels = (i + self.scroll_x as usize) % 256;
-                let tile_map = tile_map_y_start + offset_x_pixels / 8;
-                let tile_data_idx = self.vram[tile_map];
+            bg_line[x] = (color_idx, attributes.get(TileAttributes::Priority));
+            *pixel = if self.cgb {
+                self.cgb_color(
+                    &self.bg_palettes,
+                    attributes.value & TileAttributes::CgbPalette as u8,
+                    color_idx,
+                )
+            } else {
+                self.dmg_color(self.bg_palette, color_idx, x, y_coord)
+            };
+        }
 
-                let tile_data_addr = match (tile_data_idx, tile_data_bit) {
-                    (0..128, false) => 0x1000 + tile_data_idx as usize * 16,
-                    (_, _) => tile_data_idx as usize * 16,
-                };
+        if window_visible {
+            self.window_line += 1;
+        }
 
-                let tile_data_offset_y = offset_y_pixels % 8;
-                let b1 = self.vram[tile_data_addr + tile_data_offset_y * 2];
-                let b2 = self.vram[tile_data_addr + tile_data_offset_y * 2 + 1];
+        if self.lcd_control.get(LcdControl::OBJEnable) {
+            self.render_sprites(y_coord, &bg_line, &mut line);
+        }
+
+        self.output_line(y_coord, &line);
+    }
+
+    /// Writes a finished line to the framebuffer, applying the SGB screen mask
+    fn output_line(&mut self, y_coord: u8, line: &[[u8; 3]]) {
+        let mask_color = match &self.sgb {
+            Some(sgb) => match sgb.mask {
+                Mask::Cancel => None,
+                Mask::Freeze => return,
+                Mask::Black => Some([0; 3]),
+                Mask::Color0 => Some(sgb.screen_color(0, 0, 0)),
+            },
+            None => None,
+        };
+
+        let (offset_x, offset_y) = self.screen_offset();
+        let width = self.output_size().0;
+        let start = ((y_coord as usize + offset_y) * width + offset_x) * 3;
+        for (x, pixel) in line.iter().enumerate() {
+            let pixel = mask_color.unwrap_or(*pixel);
+            self.framebuffer[start + x * 3..start + x * 3 + 3].copy_from_slice(&pixel);
+        }
+        #[cfg(feature = "frontend")]
+        self.changed_lines.push(y_coord + offset_y as u8);
+    }
+
+    /// CGB BG map attributes of a tile map entry, 0 in DMG mode
+    fn bg_attributes(&self, tile_map: usize) -> u8 {
+        match self.cgb {
+            true => self.vram[0x2000 + tile_map],
+            false => 0,
+        }
+    }
+
+    /// Pixels of `row` of the BG/Window tile referenced by the tile map entry
+    fn bg_tile_row(&self, tile_map: usize, row: usize) -> [u8; 8] {
+        let tile_data_idx = self.vram[tile_map];
+        let attributes = BitFlag::<u8, TileAttributes>::new(self.bg_attributes(tile_map));
+
+        let tile_data_addr = match (
+            tile_data_idx,
+            self.lcd_control.get(LcdControl::BGWindowTileData),
+        ) {
+            (0..128, false) => 0x1000 + tile_data_idx as usize * 16,
+            (_, _) => tile_data_idx as usize * 16,
+        };
 
-                data[i + start..i + end]
-                    .copy_from_slice(&to_8bit_indexed_2byte(b1, b2)[start..end]);
-                i += end - start;
+        let row = match attributes.get(TileAttributes::YFlip) {
+            true => 7 - row,
+            false => row,
+        };
+        self.tile_row(
+            attributes.get(TileAttributes::Bank) as usize,
+            tile_data_addr,
+            row,
+            attributes.get(TileAttributes::XFlip),
+        )
+    }
+
+    fn render_sprites(
+        &self,
+        y_coord: u8,
+        bg_line: &[(u8, bool); SCREEN_WIDTH],
+        line: &mut [[u8; 3]; SCREEN_WIDTH],
+    ) {
+        let mut sprites = self.line_sprites(y_coord);
+
+        // DMG: smaller X has priority, CGB: only OAM order matters
+        // Stable sort keeps OAM order when X is equal
+        if !self.cgb {
+            sprites.sort_by_key(|s| s.x);
+        }
+
+        // The first opaque object pixel wins, even if it ends up hidden behind BG
+        let mut drawn = [false; SCREEN_WIDTH];
+        for sprite in sprites.iter() {
+            let attributes = BitFlag::<u8, TileAttributes>::new(sprite.attributes);
+            let tile_row = self.sprite_row(sprite, y_coord);
+            for (i, color_idx) in tile_row.iter().enumerate() {
+                let x = sprite.x as i16 - 8 + i as i16;
+                if !(0..SCREEN_WIDTH as i16).contains(&x) || *color_idx == 0 {
+                    continue;
+                }
+                let x = x as usize;
+                if drawn[x] {
+                    continue;
+                }
+                drawn[x] = true;
+
+                let (bg_color_idx, bg_priority) = bg_line[x];
+                let bg_over_obj = if self.cgb {
+                    self.lcd_control.get(LcdControl::BGWindowEnable)
+                        && bg_color_idx != 0
+                        && (bg_priority || attributes.get(TileAttributes::Priority))
+                } else {
+                    bg_color_idx != 0 && attributes.get(TileAttributes::Priority)
+                };
+                if bg_over_obj {
+                    continue;
+                }
+
+                line[x] = if self.cgb {
+                    self.cgb_color(
+                        &self.obj_palettes,
+                        attributes.value & TileAttributes::CgbPalette as u8,
+                        *color_idx,
+                    )
+                } else {
+                    let palette =
+                        self.obj_palette[attributes.get(TileAttributes::DmgPalette) as usize];
+                    self.dmg_color(palette, *color_idx, x, y_coord)
+                };
             }
-        })?;
-        Ok(())
+        }
+    }
+
+    /// Only the first 10 objects in OAM on this line are drawn
+    fn line_sprites(&self, y_coord: u8) -> Vec<Sprite> {
+        let height = if self.lcd_control.get(LcdControl::OBJSize) {
+            16
+        } else {
+            8
+        };
+
+        self.oam
+            .chunks_exact(4)
+            .enumerate()
+            .map(|(i, s)| Sprite {
+                y: s[0],
+                x: s[1],
+                tile: s[2],
+                attributes: s[3],
+                index: i as u8,
+            })
+            .filter(|s| {
+                let top = s.y as i16 - 16;
+                (top..top + height).contains(&(y_coord as i16))
+            })
+            .take(10)
+            .collect()
     }
 
-    pub fn display_screen(&self, canvas: &mut Canvas<Window>) -> Result<()> {
-        let Some(bg_id) = self.bg_id else {
-            bail!("Background texture not created");
+    /// Pixels of the object on line `y_coord`
+    fn sprite_row(&self, sprite: &Sprite, y_coord: u8) -> [u8; 8] {
+        let height = if self.lcd_control.get(LcdControl::OBJSize) {
+            16
+        } else {
+            8
         };
+        let attributes = BitFlag::<u8, TileAttributes>::new(sprite.attributes);
 
-        let Some(bg) = self.textures.get(bg_id.id() - 1) else {
-            bail!("Invalid background texture id");
+        let mut row = (y_coord as i16 - (sprite.y as i16 - 16)) as usize;
+        if attributes.get(TileAttributes::YFlip) {
+            row = height - 1 - row;
+        }
+        let tile = match height {
+            16 => (sprite.tile & 0xFE) as usize + row / 8,
+            _ => sprite.tile as usize,
         };
+        let bank = self.cgb && attributes.get(TileAttributes::Bank);
+        self.tile_row(
+            bank as usize,
+            tile * 16,
+            row % 8,
+            attributes.get(TileAttributes::XFlip),
+        )
+    }
 
-        let (texture_w, texture_h) = (bg.width(), bg.height());
-        let (window_w, window_h) = canvas.window().size();
-
-        let scale_x = window_w as f64 / texture_w as f64;
-        let scale_y = window_h as f64 / texture_h as f64;
-        let scale = if scale_x < scale_y { scale_x } else { scale_y };
-
-        let max_w = texture_w as f64 * scale;
-        let max_h = texture_h as f64 * scale;
-        let mid_x = (window_w as f64 - max_w) / 2.;
-        let mid_y = (window_h as f64 - max_h) / 2.;
-
-        let gb_screen = FRect::new(mid_x as f32, mid_y as f32, max_w as f32, max_h as f32);
-
-        canvas.copy(bg, None, Some(gb_screen))?;
-
-        Ok(())
-    }
-
-    pub fn display_debugger(&mut self, ui: &Ui) {
-        ui.window("Graphics")
-            .size([400., 500.], imgui::Condition::FirstUseEver)
-            .position([850., 250.], imgui::Condition::FirstUseEver)
-            .build(|| {
-                if let Some(_t) = ui.tab_bar("graphics") {
-                    if let Some(_r) = ui.tab_item("Tile Data") {
-                        let offset = self.debug.page * 64;
-                        for i in 0..64 {
-                            let texture_id = TextureId::new(offset + i + 1);
-                            Image::new(texture_id, [32., 32.]).build(ui);
-                            if i % 8 != 7 {
-                                ui.same_line();
-                            }
-                        }
-
-                        if ui.button("< Prev") {
-                            self.debug.page = self.debug.page.saturating_sub(1);
-                        }
-                        ui.same_line();
-                        ui.text(format!(" Page {} ", self.debug.page + 1));
-                        ui.same_line();
-                        if ui.button("Next >") {
-                            self.debug.page += 1;
-                            // 64 * 6 pages exist, 2 pages for each tile data block
-                            if self.debug.page > 5 {
-                                self.debug.page = 5;
-                            }
-                        }
-
-                        if ui.button("Tile block 0") {
-                            self.debug.page = 0;
-                        }
-                        ui.same_line();
-                        if ui.button("Tile block 1") {
-                            self.debug.page = 2;
-                        }
-                        ui.same_line();
-                        if ui.button("Tile block 2") {
-                            self.debug.page = 4;
-                        }
-
-                        let mut colors = self.debug.palette_colors.map(|color| {
-                            [
-                                color.r as f32 / 255.,
-                                color.g as f32 / 255.,
-                                color.b as f32 / 255.,
-                            ]
-                        });
-
-                        ui.new_line();
-                        for (i, c) in colors.iter_mut().enumerate() {
-                            ui.color_edit3(format!("Palette color {}", i + 1), c);
-                        }
-
-                        self.debug.palette_colors = colors.map(|color| {
-                            sdl3::pixels::Color::RGBA(
-                                (color[0] * 255.) as u8,
-                                (color[1] * 255.) as u8,
-                                (color[2] * 255.) as u8,
-                                255,
-                            )
-                        });
-
-                        if ui.button("Reset") {
-                            self.debug.palette_colors = DEFAULT_COLORS;
-                        }
-                        ui.same_line();
-                        if ui.button("Set Palette") {
-                            let palette = Palette::with_colors(&self.debug.palette_colors).unwrap();
-                            for t in &self.textures {
-                                unsafe {
-                                    sdl3_sys::render::SDL_SetTexturePalette(t.raw(), palette.raw());
-                                }
-                            }
-                        }
-                    }
-                    if let Some(_r) = ui.tab_item("Background") {
-                        Image::new(self.bg_id.unwrap(), [160., 144.]).build(ui);
-                    }
+    fn tile_row(&self, bank: usize, tile_addr: usize, row: usize, x_flip: bool) -> [u8; 8] {
+        let addr = bank * 0x2000 + tile_addr + row * 2;
+        let mut pixels = to_8bit_indexed_2byte(self.vram[addr], self.vram[addr + 1]);
+        if x_flip {
+            pixels.reverse();
+        }
+        pixels
+    }
+
+    fn dmg_color(&self, palette: u8, color_idx: u8, x: usize, y_coord: u8) -> [u8; 3] {
+        let shade = (palette >> (color_idx * 2)) & 0b11;
+        if let Some(sgb) = &self.sgb {
+            return sgb.screen_color(shade, x, y_coord as usize);
+        }
+        self.dmg_colors[shade as usize]
+    }
+
+    /// Redraws the SGB border after it was changed by a transfer
+    pub fn update_sgb_border(&mut self) {
+        if self.sgb.as_ref().is_some_and(|sgb| sgb.border_changed) {
+            self.render_sgb_border();
+        }
+    }
+
+    /// Draws the border around the screen area of the framebuffer
+    fn render_sgb_border(&mut self) {
+        let Some(sgb) = &mut self.sgb else {
+            return;
+        };
+        sgb.border_changed = false;
+
+        let screen_x = sgb::SCREEN_X..sgb::SCREEN_X + SCREEN_WIDTH;
+        let screen_y = sgb::SCREEN_Y..sgb::SCREEN_Y + SCREEN_HEIGHT;
+        for y in 0..sgb::SGB_HEIGHT {
+            for x in 0..sgb::SGB_WIDTH {
+                if screen_x.contains(&x) && screen_y.contains(&y) {
+                    continue;
                 }
-            });
+                let start = (y * sgb::SGB_WIDTH + x) * 3;
+                self.framebuffer[start..start + 3].copy_from_slice(&sgb.border_color(x, y));
+            }
+        }
+        #[cfg(feature = "frontend")]
+        {
+            self.changed_lines = (0..sgb::SGB_HEIGHT as u8).collect();
+        }
     }
-}
 
-/// Convert indexed 2 bit msb to indexed 8 bit
-/// joining byte 0 and byte 1 to maked 8 bit index for sdl
-fn to_8bit_indexed(bytes: &[u8]) -> [u8; 64] {
-    let mut ans = [0; 64];
-    for i in 0..8 {
-        let (b1, b2) = (bytes[2 * i], bytes[2 * i + 1]);
-        ans[i * 8..i * 8 + 8].copy_from_slice(&to_8bit_indexed_2byte(b1, b2));
+    /// Passes P1 writes to the SGB, returns the controller id to show in P1
+    pub fn sgb_write_p1(&mut self, value: u8) -> Option<u8> {
+        let transfer = self.sgb.as_mut()?.write_p1(value);
+        if let Some(transfer) = transfer {
+            let data = self.sgb_vram_data();
+            self.sgb.as_mut()?.vram_transfer(transfer, &data);
+        }
+        self.sgb.as_ref()?.joypad_id()
+    }
+
+    /// SGB reads transferred data from the tiles displayed on screen in BG map order
+    fn sgb_vram_data(&self) -> Vec<u8> {
+        let tile_map_start_addr = if self.lcd_control.get(LcdControl::BGTileMap) {
+            0x1C00
+        } else {
+            0x1800
+        };
+
+        (0..256)
+            .flat_map(|i| {
+                let tile_data_idx = self.vram[tile_map_start_addr + (i / 20) * 32 + i % 20];
+                let tile_data_addr = match (
+                    tile_data_idx,
+                    self.lcd_control.get(LcdControl::BGWindowTileData),
+                ) {
+                    (0..128, false) => 0x1000 + tile_data_idx as usize * 16,
+                    (_, _) => tile_data_idx as usize * 16,
+                };
+                self.vram[tile_data_addr..tile_data_addr + 16]
+                    .iter()
+                    .copied()
+            })
+            .collect()
+    }
+
+    fn cgb_color(&self, palettes: &PaletteRam, palette: u8, color_idx: u8) -> [u8; 3] {
+        rgb555_to_rgb888(palettes.color(palette, color_idx), self.color_correction)
     }
-    ans
 }
 
 fn to_8bit_indexed_2byte(b1: u8, b2: u8) -> [u8; 8] {
@@ -391,14 +822,35 @@ fn to_8bit_indexed_2byte(b1: u8, b2: u8) -> [u8; 8] {
     ans
 }
 
+/// Convert CGB 15 bit color to 24 bit
+/// With `correction`, channels are mixed to approximate the CGB LCD colors
+pub(crate) fn rgb555_to_rgb888(color: u16, correction: bool) -> [u8; 3] {
+    let (r, g, b) = (
+        (color & 0x1F) as u32,
+        ((color >> 5) & 0x1F) as u32,
+        ((color >> 10) & 0x1F) as u32,
+    );
+
+    if correction {
+        let r_out = (r * 26 + g * 4 + b * 2).min(960) >> 2;
+        let g_out = (g * 24 + b * 8).min(960) >> 2;
+        let b_out = (r * 6 + g * 4 + b * 22).min(960) >> 2;
+        [r_out as u8, g_out as u8, b_out as u8]
+    } else {
+        [r, g, b].map(|c| ((c << 3) | (c >> 2)) as u8)
+    }
+}
+
 impl<'a> Index<u16> for Graphics<'a> {
     type Output = u8;
     fn index(&self, index: u16) -> &Self::Output {
-        &self.vram[index as usize]
+        &self.vram[(self.vram_bank as usize & 1) * 0x2000 + index as usize]
     }
 }
 impl<'a> IndexMut<u16> for Graphics<'a> {
     fn index_mut(&mut self, index: u16) -> &mut Self::Output {
+        let index = (self.vram_bank as u16 & 1) * 0x2000 + index;
+        #[cfg(feature = "frontend")]
         self.changed_textures.push(index);
         &mut self.vram[index as usize]
     }
@@ -412,3 +864,349 @@ impl<'a> Debug for Graphics<'a> {
             .finish()
     }
 }
+
+impl SaveState for PaletteRam {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.spec);
+        state.bytes(&self.data);
+    }
+
+    fn parse<'s>(&self, state: &mut StateReader) -> Result<Apply<'s, Self>>
+    where
+        Self: 's,
+    {
+        let spec = state.u8()?;
+        let mut data = [0; 64];
+        state.bytes(&mut data)?;
+        Ok(Box::new(move |this: &mut Self| {
+            this.spec = spec;
+            this.data = data;
+        }))
+    }
+}
+
+/// Frontend settings like the renderer and colors are kept
+impl<'a> SaveState for Graphics<'a> {
+    fn save(&self, state: &mut StateWriter) {
+        state.bytes(&self.vram);
+        state.bytes(&self.oam);
+        state.bytes(&[
+            self.lcd_control.value,
+            self.scroll_x,
+            self.scroll_y,
+            self.y_coord,
+            self.y_comp,
+            self.lcd_status.value,
+        ]);
+        state.u16(self.x_coord);
+        state.u16(self.mode3_length);
+        state.bool(self.stat_line);
+        state.bool(self.skip_frame);
+        state.bytes(&[
+            self.bg_palette,
+            self.obj_palette[0],
+            self.obj_palette[1],
+            self.window_y,
+            self.window_x,
+            self.window_line,
+            self.vram_bank,
+        ]);
+        self.bg_palettes.save(state);
+        self.obj_palettes.save(state);
+        if let Some(sgb) = &self.sgb {
+            sgb.save(state);
+        }
+        state.vec(&self.framebuffer);
+    }
+
+    fn parse<'s>(&self, state: &mut StateReader) -> Result<Apply<'s, Self>>
+    where
+        Self: 's,
+    {
+        let mut vram = [0; 0x4000];
+        state.bytes(&mut vram)?;
+        let mut oam = [0; 0xA0];
+        state.bytes(&mut oam)?;
+        let mut lcd_registers = [0; 6];
+        state.bytes(&mut lcd_registers)?;
+        let (x_coord, mode3_length) = (state.u16()?, state.u16()?);
+        let (stat_line, skip_frame) = (state.bool()?, state.bool()?);
+        let mut registers = [0; 7];
+        state.bytes(&mut registers)?;
+        let bg_palettes = self.bg_palettes.parse(state)?;
+        let obj_palettes = self.obj_palettes.parse(state)?;
+        let sgb = self.sgb.as_ref().map(|sgb| sgb.parse(state)).transpose()?;
+        let framebuffer = state.vec(self.framebuffer.len())?;
+
+        Ok(Box::new(move |this: &mut Self| {
+            this.vram = vram;
+            this.oam = oam;
+            [
+                this.lcd_control.value,
+                this.scroll_x,
+                this.scroll_y,
+                this.y_coord,
+                this.y_comp,
+                this.lcd_status.value,
+            ] = lcd_registers;
+            this.x_coord = x_coord;
+            this.mode3_length = mode3_length;
+            this.stat_line = stat_line;
+            this.skip_frame = skip_frame;
+            [
+                this.bg_palette,
+                this.obj_palette[0],
+                this.obj_palette[1],
+                this.window_y,
+                this.window_x,
+                this.window_line,
+                this.vram_bank,
+            ] = registers;
+            bg_palettes(&mut this.bg_palettes);
+            obj_palettes(&mut this.obj_palettes);
+            if let (Some(apply), Some(sgb)) = (sgb, &mut this.sgb) {
+                apply(sgb);
+            }
+            this.framebuffer = framebuffer;
+
+            // The FIFO state isn't saved, a line in Mode 3 finishes with the scanline renderer
+            if this.fifo.active {
+                this.fifo.active = false;
+                this.mode3_length = this.mode3_length(this.y_coord);
+            }
+            #[cfg(feature = "frontend")]
+            {
+                this.changed_textures = (0..this.vram.len() as u16).step_by(16).collect();
+                this.changed_lines = (0..this.output_size().1 as u8).collect();
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Graphics, LcdStatus, PpuMode, Renderer, rgb555_to_rgb888};
+    use crate::{
+        interrupt::{Interrupt, InterruptPosition},
+        memory_mapping::{MemoryMapping, Rom},
+    };
+
+    #[test]
+    fn ppu_modes() {
+        let mut graphics = Graphics::new();
+        let mut interrupt = Interrupt::new();
+
+        // 80 dots of OAM scan
+        graphics.do_cycles(19, &mut interrupt).unwrap();
+        assert_eq!(graphics.mode(), PpuMode::OamScan);
+        graphics.do_cycles(1, &mut interrupt).unwrap();
+        assert_eq!(graphics.mode(), PpuMode::Drawing);
+
+        // Mode 3 is 172 dots without penalties
+        assert!(graphics.do_cycles(43, &mut interrupt).unwrap());
+        assert_eq!(graphics.mode(), PpuMode::HBlank);
+
+        graphics.do_cycles(114 - 63, &mut interrupt).unwrap();
+        assert_eq!(graphics.y_coord, 1);
+        assert_eq!(graphics.mode(), PpuMode::OamScan);
+
+        graphics.do_cycles(114 * 143, &mut interrupt).unwrap();
+        assert_eq!(graphics.y_coord, 144);
+        assert_eq!(graphics.mode(), PpuMode::VBlank);
+        assert!(interrupt.interrupt_flag.get(InterruptPosition::VBlank));
+
+        graphics.do_cycles(114 * 10, &mut interrupt).unwrap();
+        assert_eq!(graphics.y_coord, 0);
+    }
+
+    #[test]
+    fn stat_blocking() {
+        let mut graphics = Graphics::new();
+        let mut interrupt = Interrupt::new();
+        graphics.y_comp = 1;
+        graphics.lcd_status.set(LcdStatus::LYCInt, true);
+        graphics.lcd_status.set(LcdStatus::Mode2Int, true);
+
+        // LY == LYC keeps the line high through mode 2 of line 1
+        graphics.do_cycles(114, &mut interrupt).unwrap();
+        assert!(graphics.lcd_status.get(LcdStatus::LYCEqLY));
+        assert!(interrupt.interrupt_flag.get(InterruptPosition::Lcd));
+        interrupt.interrupt_flag.set(InterruptPosition::Lcd, false);
+
+        graphics.do_cycles(10, &mut interrupt).unwrap();
+        assert!(!interrupt.interrupt_flag.get(InterruptPosition::Lcd));
+
+        // Mode 2 of line 2 starts while LY == LYC drops, the line stays high
+        graphics.do_cycles(104, &mut interrupt).unwrap();
+        assert!(!interrupt.interrupt_flag.get(InterruptPosition::Lcd));
+
+        // Rising edge again on mode 2 of line 3
+        graphics.do_cycles(114, &mut interrupt).unwrap();
+        assert!(interrupt.interrupt_flag.get(InterruptPosition::Lcd));
+    }
+
+    #[test]
+    fn lcd_disable() {
+        let mut graphics = Graphics::new();
+        let mut interrupt = Interrupt::new();
+
+        graphics.do_cycles(114 * 3 + 30, &mut interrupt).unwrap();
+        assert_eq!(graphics.mode(), PpuMode::Drawing);
+        assert!(!graphics.vram_accessible());
+        assert!(!graphics.oam_accessible());
+
+        graphics.set_lcd_control(0x11);
+        assert_eq!(graphics.y_coord, 0);
+        assert_eq!(graphics.mode(), PpuMode::HBlank);
+        assert!(graphics.vram_accessible() && graphics.oam_accessible());
+
+        // The PPU doesn't run while the LCD is off
+        assert!(!graphics.do_cycles(114 * 200, &mut interrupt).unwrap());
+        assert_eq!(graphics.y_coord, 0);
+
+        // Restarts at the beginning of line 0 without displaying the first frame
+        graphics.set_lcd_control(0x91);
+        graphics.do_cycles(1, &mut interrupt).unwrap();
+        assert_eq!(graphics.mode(), PpuMode::OamScan);
+        assert!(graphics.skip_frame);
+        graphics.do_cycles(114 * 144, &mut interrupt).unwrap();
+        assert!(!graphics.skip_frame);
+    }
+
+    /// Background with fine scroll, window and overlapping objects
+    fn test_scene(renderer: Renderer) -> Graphics<'static> {
+        let mut graphics = Graphics::new();
+        graphics.renderer = renderer;
+        graphics.lcd_control.value = 0xF3;
+        graphics.scroll_x = 13;
+        graphics.scroll_y = 5;
+        graphics.window_x = 87;
+        graphics.window_y = 40;
+        graphics.bg_palette = 0xE4;
+        graphics.obj_palette = [0xD2, 0x1B];
+        for (i, byte) in graphics.vram[..0x1000].iter_mut().enumerate() {
+            *byte = (i * 37 % 251) as u8;
+        }
+        for (i, byte) in graphics.vram[0x1800..0x2000].iter_mut().enumerate() {
+            *byte = (i % 7) as u8;
+        }
+        let sprites = [
+            [60, 4, 1, 0x00],
+            [60, 30, 2, 0x80],
+            [62, 33, 3, 0x10],
+            [70, 120, 4, 0x20],
+        ];
+        for (i, sprite) in sprites.iter().enumerate() {
+            graphics.oam[i * 4..i * 4 + 4].copy_from_slice(sprite);
+        }
+        graphics
+    }
+
+    #[test]
+    fn fifo_renderer() {
+        let mut interrupt = Interrupt::new();
+        let mut scanline = test_scene(Renderer::Scanline);
+        let mut fifo = test_scene(Renderer::Fifo);
+
+        // Same Mode 3 length on a line with the window and objects
+        for graphics in [&mut scanline, &mut fifo] {
+            graphics.do_cycles(114 * 46 + 100, &mut interrupt).unwrap();
+        }
+        assert_eq!(scanline.y_coord, 46);
+        assert_eq!(scanline.mode3_length, 172 + 5 + 6 + 10 + 8 + 6);
+        assert_eq!(fifo.mode3_length, scanline.mode3_length);
+
+        for graphics in [&mut scanline, &mut fifo] {
+            graphics.do_cycles(114 * 154, &mut interrupt).unwrap();
+        }
+        assert!(scanline.framebuffer == fifo.framebuffer);
+    }
+
+    #[test]
+    fn cgb_registers() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80;
+        let mut memory = MemoryMapping::new(Rom { rom });
+
+        memory.set(0xFF4F, 0x03).unwrap();
+        assert_eq!(memory.peek(0xFF4F), 0xFF);
+        memory.set(0x8000, 0x12).unwrap();
+        assert_eq!(memory.vram.vram[0x2000], 0x12);
+        memory.set(0xFF70, 0x03).unwrap();
+        memory.set(0xD000, 0x34).unwrap();
+        assert_eq!(memory.wram.wram[0x3000], 0x34);
+
+        // Auto increment wraps within the 64 bytes, without it the index stays
+        memory.set(0xFF68, 0xBF).unwrap();
+        memory.set(0xFF69, 0x1F).unwrap();
+        memory.set(0xFF69, 0x00).unwrap();
+        assert_eq!(memory.vram.bg_palettes.data[0x3F], 0x1F);
+        assert_eq!(memory.vram.bg_palettes.data[0], 0x00);
+        assert_eq!(memory.peek(0xFF68), 0x81);
+        memory.set(0xFF6A, 0x02).unwrap();
+        memory.set(0xFF6B, 0x11).unwrap();
+        memory.set(0xFF6B, 0x22).unwrap();
+        assert_eq!(memory.vram.obj_palettes.data[2], 0x22);
+        assert_eq!(memory.peek(0xFF6A), 0x02);
+
+        // DMG mode ignores the bank registers
+        let mut memory = MemoryMapping::new(Rom {
+            rom: vec![0; 0x8000],
+        });
+        memory.set(0xFF4F, 0x01).unwrap();
+        memory.set(0x8000, 0x12).unwrap();
+        assert_eq!(memory.vram.vram[0], 0x12);
+        memory.set(0xFF70, 0x03).unwrap();
+        memory.set(0xD000, 0x34).unwrap();
+        assert_eq!(memory.wram.wram[0x1000], 0x34);
+    }
+
+    #[test]
+    fn rgb555() {
+        assert_eq!(rgb555_to_rgb888(0x7FFF, false), [0xFF; 3]);
+        assert_eq!(rgb555_to_rgb888(0x001F, false), [0xFF, 0, 0]);
+        assert_eq!(rgb555_to_rgb888(0x0210, false), [0x84, 0x84, 0]);
+        // Channels bleed into each other and white is dimmer
+        assert_eq!(rgb555_to_rgb888(0x7FFF, true), [240, 240, 240]);
+        assert_eq!(rgb555_to_rgb888(0x001F, true), [201, 0, 46]);
+    }
+
+    #[test]
+    fn cgb_attributes() {
+        let mut graphics = Graphics::new();
+        graphics.cgb = true;
+        graphics.color_correction = false;
+        graphics.lcd_control.value = 0x93;
+        let set_color = |data: &mut [u8; 64], palette: usize, color: usize, value: u16| {
+            let addr = palette * 8 + color * 2;
+            data[addr..addr + 2].copy_from_slice(&value.to_le_bytes());
+        };
+        // Palette 2: white and red, palette 0 color 1: green, OBJ palette 0 color 3: blue
+        set_color(&mut graphics.bg_palettes.data, 2, 0, 0x7FFF);
+        set_color(&mut graphics.bg_palettes.data, 2, 1, 0x001F);
+        set_color(&mut graphics.bg_palettes.data, 0, 1, 0x03E0);
+        set_color(&mut graphics.obj_palettes.data, 0, 3, 0x7C00);
+
+        // Tile 0 in bank 1 has its leftmost pixel set, in bank 0 the whole row
+        graphics.vram[0x2000] = 0x80;
+        graphics.vram[..2].copy_from_slice(&[0xFF, 0x00]);
+        // Tile 1 is all color 3
+        graphics.vram[16..18].copy_from_slice(&[0xFF, 0xFF]);
+        // First tile from bank 1, flipped with palette 2, second tile over objects
+        graphics.vram[0x3800] = 0x08 | 0x20 | 2;
+        graphics.vram[0x3801] = 0x80;
+        // Object over the second tile
+        graphics.oam[..4].copy_from_slice(&[16, 16, 1, 0x00]);
+
+        let pixel = |graphics: &Graphics, x: usize| graphics.framebuffer[x * 3..x * 3 + 3].to_vec();
+        graphics.render_scanline(0);
+        assert_eq!(pixel(&graphics, 0), [0xFF, 0xFF, 0xFF]);
+        assert_eq!(pixel(&graphics, 7), [0xFF, 0, 0]);
+        assert_eq!(pixel(&graphics, 8), [0, 0xFF, 0]);
+
+        // Without LCDC bit 0 objects are drawn over every BG pixel
+        graphics.lcd_control.value = 0x92;
+        graphics.render_scanline(0);
+        assert_eq!(pixel(&graphics, 8), [0, 0, 0xFF]);
+    }
+}
@@ -0,0 +1,249 @@
+use anyhow::{Result, bail};
+use imgui::{Image, TextureId, Ui};
+use sdl3::{
+    pixels::{Color, Palette, PixelFormat},
+    rect::Rect,
+    render::{Canvas, FRect, TextureCreator},
+    video::{Window, WindowContext},
+};
+
+use super::{DEFAULT_COLORS, Graphics, Renderer, to_8bit_indexed_2byte};
+
+/// Number of 16 byte tiles textures created for both VRAM banks
+const TILE_TEXTURES: usize = 0x4000 / 16;
+
+#[derive(Debug, Default)]
+pub(super) struct DebuggerContext {
+    page: usize,
+    bank: usize,
+    palette_colors: [[u8; 3]; 4],
+}
+
+/// SDL textures of the screen and tile data, and the graphics debugger
+impl<'a> Graphics<'a> {
+    pub fn create_textures(
+        &mut self,
+        texture_creator: &'a mut TextureCreator<WindowContext>,
+    ) -> Result<()> {
+        self.debug.palette_colors = DEFAULT_COLORS;
+        let palette = Palette::with_colors(&DEFAULT_COLORS.map(sdl_color))?;
+
+        // Test sprite
+        //self.vram[0..16].copy_from_slice(&[
+        //    0x3C, 0x7E, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x7E, 0x5E, 0x7E, 0x0A, 0x7C, 0x56,
+        //    0x38, 0x7C,
+        //]);
+
+        let textures = (0..TILE_TEXTURES)
+            .map(|_| {
+                let mut texture = texture_creator
+                    .create_texture_streaming(PixelFormat::INDEX8, 8, 8)
+                    .expect("Error creating texture");
+                unsafe {
+                    sdl3_sys::render::SDL_SetTexturePalette(texture.raw(), palette.raw());
+                }
+                texture.set_scale_mode(sdl3::render::ScaleMode::Nearest);
+                texture
+            })
+            .collect();
+
+        self.textures = textures;
+
+        let (width, height) = self.output_size();
+        self.textures.push({
+            let mut texture = texture_creator
+                .create_texture_streaming(PixelFormat::RGB24, width as u32, height as u32)
+                .expect("Error creating texture");
+            texture.set_scale_mode(sdl3::render::ScaleMode::Nearest);
+            texture
+        });
+        // Considering +1 for font texture
+        self.bg_id = Some(TextureId::new(self.textures.len()));
+
+        Ok(())
+    }
+
+    pub fn update_textures(&mut self) -> Result<()> {
+        for i in &self.changed_textures {
+            let tex_id = *i as usize / 16;
+            self.textures[tex_id].with_lock(None, |data, _| {
+                let (start, end) = (tex_id * 16, tex_id * 16 + 16);
+                let m = to_8bit_indexed(&self.vram[start..end]);
+                data.copy_from_slice(&m);
+            })?;
+        }
+        self.changed_textures = Vec::new();
+
+        self.update_sgb_border();
+
+        if !self.changed_lines.is_empty() {
+            let width = self.output_size().0;
+            let Some(bg_id) = self.bg_id else {
+                bail!("Background texture not created");
+            };
+            let Some(bg) = self.textures.get_mut(bg_id.id() - 1) else {
+                bail!("Invalid background texture id");
+            };
+            for y in &self.changed_lines {
+                let y_line = Rect::new(0, *y as i32, width as u32, 1);
+                let start = *y as usize * width * 3;
+                bg.with_lock(y_line, |data, _| {
+                    data[..width * 3].copy_from_slice(&self.framebuffer[start..start + width * 3]);
+                })?;
+            }
+            self.changed_lines = Vec::new();
+        }
+        Ok(())
+    }
+
+    pub fn display_screen(&self, canvas: &mut Canvas<Window>) -> Result<()> {
+        let Some(bg_id) = self.bg_id else {
+            bail!("Background texture not created");
+        };
+
+        let Some(bg) = self.textures.get(bg_id.id() - 1) else {
+            bail!("Invalid background texture id");
+        };
+
+        let (texture_w, texture_h) = (bg.width(), bg.height());
+        let (window_w, window_h) = canvas.window().size();
+
+        let scale_x = window_w as f64 / texture_w as f64;
+        let scale_y = window_h as f64 / texture_h as f64;
+        let scale = if scale_x < scale_y { scale_x } else { scale_y };
+
+        let max_w = texture_w as f64 * scale;
+        let max_h = texture_h as f64 * scale;
+        let mid_x = (window_w as f64 - max_w) / 2.;
+        let mid_y = (window_h as f64 - max_h) / 2.;
+
+        let gb_screen = FRect::new(mid_x as f32, mid_y as f32, max_w as f32, max_h as f32);
+
+        canvas.copy(bg, None, Some(gb_screen))?;
+
+        Ok(())
+    }
+
+    pub fn display_debugger(&mut self, ui: &Ui) {
+        ui.window("Graphics")
+            .size([400., 500.], imgui::Condition::FirstUseEver)
+            .position([850., 250.], imgui::Condition::FirstUseEver)
+            .build(|| {
+                if let Some(_t) = ui.tab_bar("graphics") {
+                    if let Some(_r) = ui.tab_item("Tile Data") {
+                        let offset = self.debug.bank * 0x2000 / 16 + self.debug.page * 64;
+                        for i in 0..64 {
+                            let texture_id = TextureId::new(offset + i + 1);
+                            Image::new(texture_id, [32., 32.]).build(ui);
+                            if i % 8 != 7 {
+                                ui.same_line();
+                            }
+                        }
+
+                        if ui.button("< Prev") {
+                            self.debug.page = self.debug.page.saturating_sub(1);
+                        }
+                        ui.same_line();
+                        ui.text(format!(" Page {} ", self.debug.page + 1));
+                        ui.same_line();
+                        if ui.button("Next >") {
+                            self.debug.page += 1;
+                            // 64 * 6 pages exist, 2 pages for each tile data block
+                            if self.debug.page > 5 {
+                                self.debug.page = 5;
+                            }
+                        }
+
+                        if ui.button("Tile block 0") {
+                            self.debug.page = 0;
+                        }
+                        ui.same_line();
+                        if ui.button("Tile block 1") {
+                            self.debug.page = 2;
+                        }
+                        ui.same_line();
+                        if ui.button("Tile block 2") {
+                            self.debug.page = 4;
+                        }
+
+                        if self.cgb {
+                            let mut bank1 = self.debug.bank == 1;
+                            ui.checkbox("VRAM bank 1", &mut bank1);
+                            self.debug.bank = bank1 as usize;
+                        }
+
+                        let mut colors = self
+                            .debug
+                            .palette_colors
+                            .map(|color| color.map(|c| c as f32 / 255.));
+
+                        ui.new_line();
+                        for (i, c) in colors.iter_mut().enumerate() {
+                            ui.color_edit3(format!("Palette color {}", i + 1), c);
+                        }
+
+                        self.debug.palette_colors =
+                            colors.map(|color| color.map(|c| (c * 255.) as u8));
+
+                        if ui.button("Reset") {
+                            self.debug.palette_colors = DEFAULT_COLORS;
+                        }
+                        ui.same_line();
+                        if ui.button("Set Palette") {
+                            let palette =
+                                Palette::with_colors(&self.debug.palette_colors.map(sdl_color))
+                                    .unwrap();
+                            for t in &self.textures[..TILE_TEXTURES] {
+                                unsafe {
+                                    sdl3_sys::render::SDL_SetTexturePalette(t.raw(), palette.raw());
+                                }
+                            }
+                            self.dmg_colors = self.debug.palette_colors;
+                        }
+                    }
+                    if let Some(_r) = ui.tab_item("Background") {
+                        let mut fifo = self.renderer == Renderer::Fifo;
+                        if ui.checkbox("Pixel FIFO renderer", &mut fifo) {
+                            self.renderer = match fifo {
+                                true => Renderer::Fifo,
+                                false => Renderer::Scanline,
+                            };
+                        }
+                        Image::new(self.bg_id.unwrap(), [160., 144.]).build(ui);
+                    }
+                    if self.cgb
+                        && let Some(_r) = ui.tab_item("CGB Palettes")
+                    {
+                        ui.checkbox("Color correction", &mut self.color_correction);
+                        for (name, palettes) in
+                            [("BG", &self.bg_palettes), ("OBJ", &self.obj_palettes)]
+                        {
+                            ui.text(name);
+                            for palette in 0..8 {
+                                let colors = (0..4)
+                                    .map(|i| format!("{:04X}", palettes.color(palette, i)))
+                                    .collect::<Vec<_>>()
+                                    .join(" ");
+                                ui.text(format!("{palette}: {colors}"));
+                            }
+                        }
+                    }
+                }
+            });
+    }
+}
+
+fn sdl_color([r, g, b]: [u8; 3]) -> Color {
+    Color::RGB(r, g, b)
+}
+
+/// Convert indexed 2 bit msb to indexed 8 bit
+/// joining byte 0 and byte 1 to maked 8 bit index for sdl
+fn to_8bit_indexed(bytes: &[u8]) -> [u8; 64] {
+    let mut ans = [0; 64];
+    for i in 0..8 {
+        let (b1, b2) = (bytes[2 * i], bytes[2 * i + 1]);
+        ans[i * 8..i * 8 + 8].copy_from_slice(&to_8bit_indexed_2byte(b1, b2));
+    }
+    ans
+}
@@ -0,0 +1,280 @@
+use std::collections::VecDeque;
+
+use super::{Graphics, LcdControl, SCREEN_WIDTH, Sprite, TileAttributes};
+use crate::utils::BitFlag;
+
+/// Selects how Mode 3 is emulated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Renderer {
+    /// Renders the whole line when Mode 3 ends, its length is computed from penalties
+    #[default]
+    Scanline,
+    /// Fetchers and pixel FIFOs output one pixel per dot,
+    /// mid-scanline changes of scroll, palettes and LCDC are visible
+    Fifo,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BgPixel {
+    color_idx: u8,
+    /// CGB palette
+    palette: u8,
+    /// BG-to-OBJ priority attribute
+    priority: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ObjPixel {
+    color_idx: u8,
+    /// OBP0/OBP1 in DMG mode, OBP0-7 in CGB mode
+    palette: u8,
+    priority: bool,
+    oam_index: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum FetcherStep {
+    #[default]
+    TileNumber,
+    TileDataLow,
+    TileDataHigh,
+    /// Waits until the BG FIFO is empty
+    Push,
+}
+
+/// Mode 3 state of the FIFO renderer
+#[derive(Debug, Default)]
+pub(super) struct PixelFifo {
+    /// Set at the start of Mode 3 when the FIFO renderer is selected
+    pub active: bool,
+    bg_fifo: VecDeque<BgPixel>,
+    obj_fifo: VecDeque<ObjPixel>,
+
+    step: FetcherStep,
+    /// Each fetcher step takes 2 dots
+    step_dot: u8,
+    /// Tile column of the next fetch, relative to SCX or the window
+    fetcher_x: u8,
+    tile_map: usize,
+    tile_row: [u8; 8],
+
+    /// Dots left of the first tile fetch, which is thrown away
+    startup_dots: u8,
+    /// Pixels left to discard for SCX fine scroll
+    discard: u8,
+    /// Next pixel sent to the LCD
+    lx: u8,
+    in_window: bool,
+
+    /// Objects found by OAM scan, removed once fetched
+    sprites: Vec<Sprite>,
+    /// Object being fetched while the BG fetcher and the LCD are stalled
+    fetching: Option<Sprite>,
+    sprite_stall: u16,
+
+    line: Vec<[u8; 3]>,
+}
+
+impl<'a> Graphics<'a> {
+    /// Called on the first dot of Mode 3
+    pub(super) fn fifo_start(&mut self, y_coord: u8) {
+        self.fifo = PixelFifo {
+            active: true,
+            startup_dots: 6,
+            discard: self.scroll_x % 8,
+            sprites: self.line_sprites(y_coord),
+            line: vec![[0; 3]; SCREEN_WIDTH],
+            ..Default::default()
+        };
+    }
+
+    /// Advances Mode 3 by one dot
+    /// Returns true when the last pixel of the line was sent to the LCD
+    pub(super) fn fifo_dot(&mut self, y_coord: u8) -> bool {
+        if self.fifo.startup_dots > 0 {
+            self.fifo.startup_dots -= 1;
+            return false;
+        }
+
+        if self.fifo.fetching.is_none() && self.lcd_control.get(LcdControl::OBJEnable) {
+            let lx = self.fifo.lx as u16;
+            if let Some(i) = self.fifo.sprites.iter().position(|s| s.x as u16 <= lx + 8) {
+                let sprite = self.fifo.sprites.remove(i);
+                self.fifo.sprite_stall = self.sprite_penalty(&sprite);
+                self.fifo.fetching = Some(sprite);
+            }
+        }
+        if let Some(sprite) = self.fifo.fetching {
+            self.fifo.sprite_stall -= 1;
+            if self.fifo.sprite_stall == 0 {
+                self.fifo.fetching = None;
+                self.fifo_push_sprite(&sprite, y_coord);
+            }
+            return false;
+        }
+
+        if !self.fifo.in_window && self.window_visible(y_coord) {
+            let wx = self.window_x as u16;
+            if self.fifo.lx as u16 + 7 >= wx {
+                // Restarting the fetcher costs 6 dots
+                self.fifo.in_window = true;
+                self.fifo.bg_fifo.clear();
+                self.fifo.step = FetcherStep::TileNumber;
+                self.fifo.step_dot = 0;
+                self.fifo.fetcher_x = 0;
+            }
+        }
+
+        self.fifo_fetcher_step(y_coord);
+        self.fifo_shift(y_coord)
+    }
+
+    /// Called when Mode 3 ends
+    pub(super) fn fifo_finish(&mut self, y_coord: u8) {
+        self.fifo.active = false;
+        if self.fifo.in_window {
+            self.window_line += 1;
+        }
+        if self.skip_frame {
+            return;
+        }
+        let line = std::mem::take(&mut self.fifo.line);
+        self.output_line(y_coord, &line);
+    }
+
+    fn fifo_fetcher_step(&mut self, y_coord: u8) {
+        if self.fifo.step != FetcherStep::Push {
+            self.fifo.step_dot += 1;
+            if self.fifo.step_dot < 2 {
+                return;
+            }
+            self.fifo.step_dot = 0;
+        }
+
+        match self.fifo.step {
+            FetcherStep::TileNumber => {
+                // SCX and SCY are read on every fetch
+                let (tile_map_bit, map_x, map_y) = if self.fifo.in_window {
+                    (
+                        LcdControl::WindowTileMap,
+                        self.fifo.fetcher_x as usize,
+                        self.window_line as usize,
+                    )
+                } else {
+                    (
+                        LcdControl::BGTileMap,
+                        (self.scroll_x as usize / 8 + self.fifo.fetcher_x as usize) % 32,
+                        y_coord.wrapping_add(self.scroll_y) as usize,
+                    )
+                };
+                let tile_map_start_addr = if self.lcd_control.get(tile_map_bit) {
+                    0x1C00
+                } else {
+                    0x1800
+                };
+                self.fifo.tile_map = tile_map_start_addr + 32 * (map_y / 8) + map_x;
+                self.fifo.step = FetcherStep::TileDataLow;
+            }
+            FetcherStep::TileDataLow => self.fifo.step = FetcherStep::TileDataHigh,
+            FetcherStep::TileDataHigh => {
+                let map_y = match self.fifo.in_window {
+                    true => self.window_line as usize,
+                    false => y_coord.wrapping_add(self.scroll_y) as usize,
+                };
+                self.fifo.tile_row = self.bg_tile_row(self.fifo.tile_map, map_y % 8);
+                self.fifo.step = FetcherStep::Push;
+            }
+            FetcherStep::Push => {
+                if !self.fifo.bg_fifo.is_empty() {
+                    return;
+                }
+                let attributes =
+                    BitFlag::<u8, TileAttributes>::new(self.bg_attributes(self.fifo.tile_map));
+                // DMG: BG and window are blank while disabled
+                let enabled = self.cgb || self.lcd_control.get(LcdControl::BGWindowEnable);
+                for color_idx in self.fifo.tile_row {
+                    self.fifo.bg_fifo.push_back(BgPixel {
+                        color_idx: if enabled { color_idx } else { 0 },
+                        palette: attributes.value & TileAttributes::CgbPalette as u8,
+                        priority: attributes.get(TileAttributes::Priority),
+                    });
+                }
+                self.fifo.fetcher_x = self.fifo.fetcher_x.wrapping_add(1);
+                self.fifo.step = FetcherStep::TileNumber;
+            }
+        }
+    }
+
+    /// Mixes objects into the object FIFO, pixels already there keep priority
+    fn fifo_push_sprite(&mut self, sprite: &Sprite, y_coord: u8) {
+        let attributes = BitFlag::<u8, TileAttributes>::new(sprite.attributes);
+        let tile_row = self.sprite_row(sprite, y_coord);
+        let palette = match self.cgb {
+            true => attributes.value & TileAttributes::CgbPalette as u8,
+            false => attributes.get(TileAttributes::DmgPalette) as u8,
+        };
+
+        // Objects partially left of the screen
+        let skip = (self.fifo.lx as usize + 8).saturating_sub(sprite.x as usize);
+        for (slot, color_idx) in tile_row.into_iter().skip(skip).enumerate() {
+            let pixel = ObjPixel {
+                color_idx,
+                palette,
+                priority: attributes.get(TileAttributes::Priority),
+                oam_index: sprite.index,
+            };
+            match self.fifo.obj_fifo.get_mut(slot) {
+                Some(existing) => {
+                    // CGB: only OAM order matters
+                    let replace = existing.color_idx == 0
+                        || (self.cgb && color_idx != 0 && pixel.oam_index < existing.oam_index);
+                    if replace {
+                        *existing = pixel;
+                    }
+                }
+                None => self.fifo.obj_fifo.push_back(pixel),
+            }
+        }
+    }
+
+    /// Sends one pixel to the LCD
+    fn fifo_shift(&mut self, y_coord: u8) -> bool {
+        let Some(bg) = self.fifo.bg_fifo.pop_front() else {
+            return false;
+        };
+        if self.fifo.discard > 0 && !self.fifo.in_window {
+            self.fifo.discard -= 1;
+            return false;
+        }
+        let obj = self.fifo.obj_fifo.pop_front();
+
+        let x = self.fifo.lx as usize;
+        let obj = obj.filter(|obj| {
+            let bg_over_obj = if self.cgb {
+                self.lcd_control.get(LcdControl::BGWindowEnable)
+                    && bg.color_idx != 0
+                    && (bg.priority || obj.priority)
+            } else {
+                bg.color_idx != 0 && obj.priority
+            };
+            obj.color_idx != 0 && !bg_over_obj
+        });
+
+        // Palettes are read when the pixel is output
+        let color = match (obj, self.cgb) {
+            (Some(obj), true) => self.cgb_color(&self.obj_palettes, obj.palette, obj.color_idx),
+            (Some(obj), false) => self.dmg_color(
+                self.obj_palette[obj.palette as usize],
+                obj.color_idx,
+                x,
+                y_coord,
+            ),
+            (None, true) => self.cgb_color(&self.bg_palettes, bg.palette, bg.color_idx),
+            (None, false) => self.dmg_color(self.bg_palette, bg.color_idx, x, y_coord),
+        };
+        self.fifo.line[x] = color;
+
+        self.fifo.lx += 1;
+        self.fifo.lx as usize == SCREEN_WIDTH
+    }
+}
@@ -0,0 +1,179 @@
+use anyhow::Result;
+
+use crate::save_state::{Apply, SaveState, StateReader, StateWriter};
+
+/// CGB VRAM DMA controlled by HDMA1-HDMA5
+#[derive(Debug)]
+pub(crate) struct Hdma {
+    pub source_high: u8,
+    pub source_low: u8,
+    pub destination_high: u8,
+    pub destination_low: u8,
+    /// HDMA5: bit 7 is 0 while a HBlank transfer is active,
+    /// bits 0-6 are the remaining number of 16 byte blocks minus 1
+    pub control: u8,
+
+    hblank_active: bool,
+    /// M-cycles the CPU is halted for while VRAM DMA copies data
+    stall_cycles: u16,
+}
+
+impl Default for Hdma {
+    fn default() -> Self {
+        Self {
+            source_high: 0xFF,
+            source_low: 0xFF,
+            destination_high: 0xFF,
+            destination_low: 0xFF,
+            control: 0xFF,
+            hblank_active: false,
+            stall_cycles: 0,
+        }
+    }
+}
+
+pub(crate) enum HdmaTransfer {
+    /// General purpose DMA, copies all blocks at once
+    General(u16),
+    /// Copies one block at the start of each HBlank
+    HBlank,
+    Cancel,
+}
+
+impl Hdma {
+    /// Lower 4 bits are ignored
+    pub fn source(&self) -> u16 {
+        ((self.source_high as u16) << 8 | self.source_low as u16) & 0xFFF0
+    }
+
+    /// Offset into VRAM, upper 3 and lower 4 bits are ignored
+    pub fn destination(&self) -> u16 {
+        ((self.destination_high as u16) << 8 | self.destination_low as u16) & 0x1FF0
+    }
+
+    pub fn hblank_active(&self) -> bool {
+        self.hblank_active
+    }
+
+    /// Called after HDMA5 is written
+    pub fn start(&mut self, value: u8) -> HdmaTransfer {
+        if self.hblank_active && value & 0x80 == 0 {
+            self.hblank_active = false;
+            self.control |= 0x80;
+            return HdmaTransfer::Cancel;
+        }
+
+        if value & 0x80 != 0 {
+            self.hblank_active = true;
+            self.control = value & 0x7F;
+            HdmaTransfer::HBlank
+        } else {
+            self.control = 0xFF;
+            HdmaTransfer::General((value & 0x7F) as u16 + 1)
+        }
+    }
+
+    /// Moves source and destination to the next block
+    /// Returns true when the HBlank transfer is done
+    pub fn next_block(&mut self) -> bool {
+        let source = self.source().wrapping_add(0x10);
+        let destination = self.destination() + 0x10;
+        [self.source_high, self.source_low] = source.to_be_bytes();
+        [self.destination_high, self.destination_low] = destination.to_be_bytes();
+
+        if !self.hblank_active {
+            return true;
+        }
+        if self.control & 0x7F == 0 {
+            self.hblank_active = false;
+            self.control = 0xFF;
+            return true;
+        }
+        self.control -= 1;
+        false
+    }
+
+    /// 8 M-cycles per block in normal speed, 16 in double speed
+    pub fn stall(&mut self, blocks: u16, double_speed: bool) {
+        self.stall_cycles += blocks * if double_speed { 16 } else { 8 };
+    }
+
+    pub fn take_stall_cycles(&mut self) -> u16 {
+        std::mem::take(&mut self.stall_cycles)
+    }
+}
+
+impl SaveState for Hdma {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.source_high);
+        state.u8(self.source_low);
+        state.u8(self.destination_high);
+        state.u8(self.destination_low);
+        state.u8(self.control);
+        state.bool(self.hblank_active);
+        state.u16(self.stall_cycles);
+    }
+
+    fn parse<'s>(&self, state: &mut StateReader) -> Result<Apply<'s, Self>>
+    where
+        Self: 's,
+    {
+        let mut registers = [0; 5];
+        state.bytes(&mut registers)?;
+        let hblank_active = state.bool()?;
+        let stall_cycles = state.u16()?;
+        Ok(Box::new(move |this: &mut Self| {
+            [
+                this.source_high,
+                this.source_low,
+                this.destination_high,
+                this.destination_low,
+                this.control,
+            ] = registers;
+            this.hblank_active = hblank_active;
+            this.stall_cycles = stall_cycles;
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hdma, HdmaTransfer};
+
+    #[test]
+    fn hblank_transfer() {
+        let mut hdma = Hdma {
+            source_high: 0xC1,
+            source_low: 0x2F,
+            destination_high: 0xE0,
+            destination_low: 0x00,
+            ..Default::default()
+        };
+        assert_eq!(hdma.source(), 0xC120);
+        assert_eq!(hdma.destination(), 0x0000);
+
+        assert!(matches!(hdma.start(0x81), HdmaTransfer::HBlank));
+        assert_eq!(hdma.control, 0x01);
+        assert!(!hdma.next_block());
+        assert_eq!(hdma.control, 0x00);
+        assert_eq!(hdma.source(), 0xC130);
+        assert_eq!(hdma.destination(), 0x0010);
+        assert!(hdma.next_block());
+        assert_eq!(hdma.control, 0xFF);
+        assert!(!hdma.hblank_active());
+    }
+
+    #[test]
+    fn cancel_transfer() {
+        let mut hdma = Hdma::default();
+        hdma.start(0x85);
+        hdma.next_block();
+        assert!(matches!(hdma.start(0x00), HdmaTransfer::Cancel));
+        assert_eq!(hdma.control, 0x84);
+
+        assert!(matches!(hdma.start(0x02), HdmaTransfer::General(3)));
+        hdma.stall(3, true);
+        assert_eq!(hdma.take_stall_cycles(), 48);
+        assert_eq!(hdma.take_stall_cycles(), 0);
+    }
+}
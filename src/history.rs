@@ -0,0 +1,254 @@
+use std::collections::VecDeque;
+
+use crate::{
+    call_stack::{CallStack, FrameChange},
+    interrupt::Interrupt,
+    memory_mapping::MemoryMapping,
+    registers::{RegisterU16, Registers},
+};
+
+/// Registers before an instruction ran
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RegisterSnapshot {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+impl RegisterSnapshot {
+    pub fn new(registers: &Registers) -> Self {
+        Self {
+            af: registers.get_u16(&RegisterU16::AF),
+            bc: registers.get_u16(&RegisterU16::BC),
+            de: registers.get_u16(&RegisterU16::DE),
+            hl: registers.get_u16(&RegisterU16::HL),
+            sp: registers.sp,
+            pc: registers.pc,
+        }
+    }
+
+    pub fn restore(&self, registers: &mut Registers) {
+        registers.set_u16(&RegisterU16::AF, self.af);
+        registers.set_u16(&RegisterU16::BC, self.bc);
+        registers.set_u16(&RegisterU16::DE, self.de);
+        registers.set_u16(&RegisterU16::HL, self.hl);
+        registers.sp = self.sp;
+        registers.pc = self.pc;
+    }
+}
+
+/// Interrupt state before an instruction ran, which the CPU changes without writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct InterruptSnapshot {
+    pub ime: bool,
+    /// EI ran and IME is set after the next instruction
+    pub ime_pending: bool,
+    pub interrupt_flag: u8,
+}
+
+impl InterruptSnapshot {
+    pub fn new(interrupt: &Interrupt) -> Self {
+        Self {
+            ime: interrupt.ime(),
+            ime_pending: interrupt.ime_pending(),
+            interrupt_flag: interrupt.interrupt_flag.value,
+        }
+    }
+
+    /// Requests acknowledged by a dispatch come back, ones made since by the PPU or timer
+    /// stay since those aren't undone
+    pub fn restore(&self, interrupt: &mut Interrupt) {
+        interrupt.restore_ime(self.ime, self.ime_pending);
+        interrupt.interrupt_flag.value |= self.interrupt_flag;
+    }
+}
+
+/// CPU write of `new` over `old`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct MemoryWrite {
+    pub address: u16,
+    pub old: u8,
+    pub new: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HistoryEntry {
+    /// Opcode and immediate bytes, empty for an interrupt dispatch
+    pub bytes: Vec<u8>,
+    pub registers: RegisterSnapshot,
+    pub interrupts: InterruptSnapshot,
+    pub writes: Vec<MemoryWrite>,
+    pub frames: Vec<FrameChange>,
+    /// Vector jumped to when the entry is an interrupt dispatch
+    pub interrupt: Option<u16>,
+}
+
+impl HistoryEntry {
+    pub fn pc(&self) -> u16 {
+        self.registers.pc
+    }
+}
+
+/// The last executed instructions, which can be undone one at a time
+///
+/// Registers, interrupt state, the call stack and memory written by the CPU are restored.
+/// The PPU, timer and side effects of hardware registers like OAM DMA keep their state.
+#[derive(Debug)]
+pub(crate) struct History {
+    entries: VecDeque<HistoryEntry>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Oldest first
+    pub fn entries(&self) -> &VecDeque<HistoryEntry> {
+        &self.entries
+    }
+
+    pub fn push(&mut self, entry: HistoryEntry) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Undoes the last instruction, false when the history is empty
+    pub fn step_back(
+        &mut self,
+        registers: &mut Registers,
+        memory: &mut MemoryMapping,
+        call_stack: &mut CallStack,
+    ) -> bool {
+        let Some(entry) = self.entries.pop_back() else {
+            return false;
+        };
+        for write in entry.writes.iter().rev() {
+            if let Ok(value) = memory.get_mut(write.address) {
+                *value = write.old;
+            }
+        }
+        entry.registers.restore(registers);
+        entry.interrupts.restore(&mut memory.interrupt);
+        call_stack.undo(&entry.frames);
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+    use crate::{
+        cpu::Cpu,
+        memory_mapping::{MemoryMapping, Rom},
+    };
+
+    #[test]
+    fn step_back() {
+        let mut rom = vec![0; 0x8000];
+        // LD HL,$C000; LD A,$12; LD (HL+),A; PUSH HL
+        rom[0x100..0x107].copy_from_slice(&[0x21, 0x00, 0xC0, 0x3E, 0x12, 0x22, 0xE5]);
+        let mut cpu = Cpu::new(MemoryMapping::new(Rom { rom }));
+        cpu.history = Some(History::new(3));
+
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+        let history = cpu.history.as_ref().unwrap();
+        // The first instruction fell out of the buffer
+        assert_eq!(history.entries().len(), 3);
+        assert_eq!(history.entries()[0].pc(), 0x103);
+        assert_eq!(history.entries()[0].bytes, [0x3E, 0x12]);
+        assert_eq!(history.entries()[1].writes.len(), 1);
+        assert_eq!(history.entries()[2].writes.len(), 2);
+        assert_eq!(cpu.memory.peek(0xC000), 0x12);
+        assert_eq!(cpu.memory.peek(0xFFFD), 0xC0);
+
+        assert!(cpu.step_back());
+        assert_eq!((cpu.registers.pc, cpu.registers.sp), (0x106, 0xFFFE));
+        assert_eq!(cpu.memory.peek(0xFFFD), 0);
+        assert!(cpu.step_back());
+        assert_eq!(cpu.memory.peek(0xC000), 0);
+        assert_eq!(cpu.registers.l, 0);
+        assert!(cpu.step_back());
+        assert_eq!((cpu.registers.pc, cpu.registers.a), (0x103, 0));
+        assert!(!cpu.step_back());
+    }
+
+    #[test]
+    fn interrupt_dispatch() {
+        let mut rom = vec![0; 0x8000];
+        // NOP; VBlank handler: LD A,$77
+        rom[0x40..0x42].copy_from_slice(&[0x3E, 0x77]);
+        let mut cpu = Cpu::new(MemoryMapping::new(Rom { rom }));
+        cpu.history = Some(History::new(8));
+        cpu.step().unwrap();
+        cpu.memory.interrupt.set_ime_forced();
+        cpu.memory.interrupt.interrupt_enable.value = 1;
+        cpu.memory.interrupt.interrupt_flag.value = 1;
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        let entries = cpu.history.as_ref().unwrap().entries();
+        assert_eq!(entries.len(), 3);
+        let dispatch = &entries[1];
+        assert_eq!((dispatch.pc(), dispatch.interrupt), (0x101, Some(0x40)));
+        assert!(!cpu.memory.interrupt.ime());
+        assert!(dispatch.bytes.is_empty());
+        assert_eq!(dispatch.writes.len(), 2);
+        assert_eq!((entries[2].pc(), entries[2].interrupt), (0x40, None));
+        assert_eq!(entries[2].bytes, [0x3E, 0x77]);
+
+        assert!(cpu.step_back());
+        assert!(cpu.step_back());
+        assert_eq!((cpu.registers.pc, cpu.registers.sp), (0x101, 0xFFFE));
+        assert_eq!(cpu.memory.peek(0xFFFD), 0);
+        assert!(cpu.memory.interrupt.ime());
+        assert_eq!(cpu.memory.interrupt.interrupt_flag.value & 1, 1);
+        assert!(cpu.call_stack.frames().is_empty());
+
+        // Stepping forward dispatches the interrupt again
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.pc, 0x40);
+    }
+
+    #[test]
+    fn call_stack_and_ei() {
+        let mut rom = vec![0; 0x8000];
+        // EI; CALL $0200
+        rom[0x100..0x104].copy_from_slice(&[0xFB, 0xCD, 0x00, 0x02]);
+        // $0200: RET
+        rom[0x200] = 0xC9;
+        let mut cpu = Cpu::new(MemoryMapping::new(Rom { rom }));
+        cpu.history = Some(History::new(8));
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert!(cpu.memory.interrupt.ime());
+        assert!(cpu.call_stack.frames().is_empty());
+
+        // Undoing the RET brings the frame back, undoing the CALL removes it
+        assert!(cpu.step_back());
+        assert_eq!(cpu.call_stack.frames()[0].target, 0x200);
+        assert!(cpu.step_back());
+        assert!(cpu.call_stack.frames().is_empty());
+        // IME was set while handling the CALL's step, EI is pending again before it
+        assert!(!cpu.memory.interrupt.ime());
+        assert!(cpu.memory.interrupt.ime_pending());
+        assert!(cpu.step_back());
+        assert!(!cpu.memory.interrupt.ime_pending());
+    }
+}
@@ -156,7 +156,7 @@ impl Display for Instruction {
             Instruction::JP(Some(i), j) => write!(f, "JP {i}, {j}"),
             Instruction::JP(None, i) => write!(f, "JP {i}"),
             Instruction::PUSH(i) => write!(f, "PUSH {i}"),
-            Instruction::RST(i) => write!(f, "RST {i}"),
+            Instruction::RST(i) => write!(f, "RST ${:02X}", i * 8),
             Instruction::CALL(Some(i), j) => write!(f, "CALL {i}, {j}"),
             Instruction::CALL(None, i) => write!(f, "CALL {i}"),
             Instruction::RETI => write!(f, "RETI"),
@@ -174,7 +174,7 @@ impl Display for Instruction {
             Instruction::BIT(i, j) => write!(f, "BIT {i}, {j}"),
             Instruction::RES(i, j) => write!(f, "RES {i}, {j}"),
             Instruction::SET(i, j) => write!(f, "SET {i}, {j}"),
-            Instruction::ILLEGAL(i) => write!(f, "ILLEGAL {i}"),
+            Instruction::ILLEGAL(i) => write!(f, "ILLEGAL ${i:02X}"),
         }
     }
 }
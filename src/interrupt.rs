@@ -1,4 +1,9 @@
-use crate::utils::BitFlag;
+use anyhow::Result;
+
+use crate::{
+    save_state::{Apply, SaveState, StateReader, StateWriter},
+    utils::BitFlag,
+};
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
@@ -30,15 +35,15 @@ impl Interrupt {
         Self::default()
     }
 
+    /// Vector of the highest priority interrupt that is requested and enabled, its
+    /// request is acknowledged and IME is cleared until the handler enables it again
     pub fn handle_interrupts(&mut self) -> Option<u16> {
-        let serviceable = self.interrupt_flag.value & self.interrupt_enable.value;
+        let serviceable = self.interrupt_flag.value & self.interrupt_enable.value & 0x1F;
         if self.ime && serviceable != 0 {
-            for i in 0..=4 {
-                if self.interrupt_flag.get_into(1 << i) {
-                    self.interrupt_flag.set_into(1 << i, false);
-                    return Some(0x40 + i as u16 * 8);
-                }
-            }
+            let i = serviceable.trailing_zeros();
+            self.interrupt_flag.set_into(1 << i, false);
+            self.ime = false;
+            return Some(0x40 + i as u16 * 8);
         }
 
         // EI is delayed by 1 instruction
@@ -50,6 +55,21 @@ impl Interrupt {
         None
     }
 
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    /// EI ran, IME is set after the next instruction
+    pub fn ime_pending(&self) -> bool {
+        self.set_ime
+    }
+
+    /// Puts back IME and a pending EI, for undoing a step
+    pub fn restore_ime(&mut self, ime: bool, pending: bool) {
+        self.ime = ime;
+        self.set_ime = pending;
+    }
+
     pub fn set_ime(&mut self) {
         // Enables interrupts and returns (same as ei immediately followed by ret)
         self.set_ime = true;
@@ -76,3 +96,26 @@ impl Interrupt {
         }
     }
 }
+
+impl SaveState for Interrupt {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.interrupt_enable.value);
+        state.u8(self.interrupt_flag.value);
+        state.bool(self.ime);
+        state.bool(self.set_ime);
+    }
+
+    fn parse<'s>(&self, state: &mut StateReader) -> Result<Apply<'s, Self>>
+    where
+        Self: 's,
+    {
+        let (interrupt_enable, interrupt_flag) = (state.u8()?, state.u8()?);
+        let (ime, set_ime) = (state.bool()?, state.bool()?);
+        Ok(Box::new(move |this: &mut Self| {
+            this.interrupt_enable.value = interrupt_enable;
+            this.interrupt_flag.value = interrupt_flag;
+            this.ime = ime;
+            this.set_ime = set_ime;
+        }))
+    }
+}
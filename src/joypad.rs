@@ -0,0 +1,121 @@
+use anyhow::Result;
+
+use crate::{
+    save_state::{Apply, SaveState, StateReader, StateWriter},
+    utils::BitFlag,
+};
+
+/// The low nibble is read with the d-pad selected, the high nibble with the buttons
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Right = 0x01,
+    Left = 0x02,
+    Up = 0x04,
+    Down = 0x08,
+    A = 0x10,
+    B = 0x20,
+    Select = 0x40,
+    Start = 0x80,
+}
+
+impl From<Button> for u8 {
+    fn from(value: Button) -> Self {
+        value as u8
+    }
+}
+
+/// P1/JOYP register
+#[derive(Debug)]
+pub(crate) struct Joypad {
+    /// Bit 5: select buttons, bit 4: select d-pad (0 = selected), bits 0-3: pressed (0 = pressed)
+    pub register: u8,
+    /// Held buttons, set by the frontend and not part of save states
+    pub pressed: BitFlag<u8, Button>,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self {
+            register: 0xFF,
+            pressed: BitFlag::default(),
+        }
+    }
+}
+
+impl Joypad {
+    /// Called after P1 is written
+    pub fn select(&mut self, value: u8) {
+        self.register = 0xC0 | (value & 0x30) | self.lines(value);
+    }
+
+    /// Bits 0-3 of P1 for the selected rows
+    fn lines(&self, select: u8) -> u8 {
+        let mut pressed = 0;
+        if select & 0x10 == 0 {
+            pressed |= self.pressed.value & 0x0F;
+        }
+        if select & 0x20 == 0 {
+            pressed |= self.pressed.value >> 4;
+        }
+        !pressed & 0x0F
+    }
+
+    /// Updates the held buttons, returns true when a line of a selected row went low,
+    /// which requests the joypad interrupt
+    pub fn set_pressed(&mut self, pressed: u8) -> bool {
+        self.pressed.value = pressed;
+        let select = self.register & 0x30;
+        // Bits 0-3 hold the SGB controller id while neither row is selected
+        if select == 0x30 {
+            return false;
+        }
+        let lines = self.register & 0x0F;
+        self.register = 0xC0 | select | self.lines(select);
+        lines & !self.register != 0
+    }
+
+    /// SGB multiplayer: the controller id is read while neither row is selected
+    pub fn set_joypad_id(&mut self, id: u8) {
+        if self.register & 0x30 == 0x30 {
+            self.register = (self.register & 0xF0) | (0x0F - id);
+        }
+    }
+}
+
+impl SaveState for Joypad {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.register);
+    }
+
+    fn parse<'s>(&self, state: &mut StateReader) -> Result<Apply<'s, Self>>
+    where
+        Self: 's,
+    {
+        let register = state.u8()?;
+        Ok(Box::new(move |this: &mut Self| this.register = register))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Button, Joypad};
+
+    #[test]
+    fn buttons() {
+        let mut joypad = Joypad::default();
+        joypad.select(0x20);
+        assert!(joypad.set_pressed(Button::Right as u8 | Button::Start as u8));
+        assert_eq!(joypad.register, 0xEE);
+        // Already low, no new interrupt
+        assert!(!joypad.set_pressed(Button::Right as u8));
+
+        joypad.select(0x10);
+        assert_eq!(joypad.register, 0xDF);
+        assert!(joypad.set_pressed(Button::Start as u8));
+        assert_eq!(joypad.register, 0xD7);
+
+        joypad.select(0x30);
+        assert!(!joypad.set_pressed(0xFF));
+        assert_eq!(joypad.register, 0xFF);
+    }
+}
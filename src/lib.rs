@@ -0,0 +1,40 @@
+//! Game Boy emulator core, embedded through [`GameBoy`]
+//!
+//! The SDL window and imgui debugger are only built with the `frontend` feature.
+
+// Debugger state of the core is only read by the frontend
+#![cfg_attr(not(feature = "frontend"), allow(dead_code))]
+
+mod call_stack;
+#[cfg(feature = "frontend")]
+mod cli;
+mod cpu;
+#[cfg(feature = "frontend")]
+mod debugger;
+mod disassembler;
+#[cfg(feature = "frontend")]
+pub mod frontend;
+mod gameboy;
+mod graphics;
+mod hdma;
+mod history;
+mod instructions;
+mod interrupt;
+mod joypad;
+mod memory_mapping;
+mod registers;
+#[cfg(feature = "frontend")]
+mod rewind;
+mod save_state;
+#[cfg(feature = "frontend")]
+mod sdl;
+mod sgb;
+#[cfg(feature = "frontend")]
+mod speed;
+mod timer;
+mod trace;
+mod utils;
+mod watchpoint;
+
+pub use gameboy::GameBoy;
+pub use joypad::Button;
@@ -1,4 +1,5 @@
 use std::{
+    cell::Cell,
     fs::File,
     io::Read,
     ops::{Index, IndexMut},
@@ -6,9 +7,22 @@ use std::{
 };
 
 use anyhow::{Result, bail};
+#[cfg(feature = "frontend")]
 use imgui::{StyleColor, TableFlags};
 
-use crate::{graphics::Graphics, interrupt::Interrupt, timer::Timer};
+#[cfg(feature = "frontend")]
+use crate::debugger::Symbols;
+
+use crate::{
+    graphics::Graphics,
+    hdma::{Hdma, HdmaTransfer},
+    history::MemoryWrite,
+    interrupt::Interrupt,
+    joypad::Joypad,
+    save_state::{Apply, SaveState, StateReader, StateWriter},
+    timer::Timer,
+    watchpoint::{Watchpoint, WatchpointHit},
+};
 
 #[derive(Debug)]
 pub(crate) struct MemoryMapping<'a> {
@@ -19,6 +33,18 @@ pub(crate) struct MemoryMapping<'a> {
     pub stack: [u8; 0x7F],
     pub interrupt: Interrupt,
     pub timer: Timer,
+    pub joypad: Joypad,
+    pub dma: u8,
+    pub hdma: Hdma,
+    /// KEY1: bit 7 = current speed, bit 0 = switch armed
+    pub key1: u8,
+    speed_remainder: u16,
+
+    /// Checked on every CPU access, the first hit is kept until taken
+    pub watchpoints: Vec<Watchpoint>,
+    watchpoint_hit: Cell<Option<WatchpointHit>>,
+    /// Writes by the CPU are collected while recording history
+    pub write_log: Option<Vec<MemoryWrite>>,
 
     debugger_offset: i16,
     debugger_selected: u16,
@@ -34,6 +60,14 @@ impl<'a> Default for MemoryMapping<'a> {
             stack: [0; 0x7F],
             interrupt: Interrupt::new(),
             timer: Timer::new(),
+            joypad: Joypad::default(),
+            dma: 0,
+            hdma: Hdma::default(),
+            key1: 0,
+            speed_remainder: 0,
+            watchpoints: Vec::new(),
+            watchpoint_hit: Cell::new(None),
+            write_log: None,
             debugger_offset: 0,
             debugger_selected: 0,
         }
@@ -42,13 +76,19 @@ impl<'a> Default for MemoryMapping<'a> {
 
 impl<'a> MemoryMapping<'a> {
     pub fn new(rom: Rom) -> Self {
-        Self {
+        let mut memory = Self {
             rom,
             ..Default::default()
+        };
+        memory.vram.cgb = memory.rom.cgb_support();
+        if !memory.vram.cgb && memory.rom.sgb_support() {
+            memory.vram.enable_sgb();
         }
+        memory
     }
 
-    pub fn display_debugger(&mut self, ui: &imgui::Ui, pc: u16) {
+    #[cfg(feature = "frontend")]
+    pub fn display_debugger(&mut self, ui: &imgui::Ui, pc: u16, symbols: &Symbols) {
         ui.window("Memory")
             .size([600., 600.], imgui::Condition::FirstUseEver)
             .position([250., 250.], imgui::Condition::FirstUseEver)
@@ -138,6 +178,11 @@ impl<'a> MemoryMapping<'a> {
                         if ui.button(val) {
                             self.debugger_selected = addr as u16;
                         }
+                        if ui.is_item_hovered()
+                            && let Some(location) = symbols.describe(self, addr as u16)
+                        {
+                            ui.tooltip_text(location);
+                        }
                     }
                 }
                 if ui.button("< Prev") {
@@ -154,6 +199,9 @@ impl<'a> MemoryMapping<'a> {
                 }
                 ui.new_line();
                 self.debugger_offset = self.debugger_offset.clamp(0, 255);
+                if let Some(location) = symbols.describe(self, self.debugger_selected) {
+                    ui.text(location);
+                }
                 if let Ok(val) = self.get(self.debugger_selected) {
                     ui.text(format!(
                         "0x{:04X}: 0x{val:02X} 0b{val:08b}",
@@ -183,6 +231,8 @@ impl<'a> MemoryMapping<'a> {
             0x8000..=0x9FFF => &self.vram[index - 0x8000],
             0xA000..=0xBFFF => &self.external_ram[index as usize - 0xA000],
             0xC000..=0xDFFF => &self.wram[index - 0xC000],
+            0xFE00..=0xFE9F => &self.vram.oam[index as usize - 0xFE00],
+            0xFF00 => &self.joypad.register,
             0xFF04 => &self.timer.divider_register,
             0xFF05 => &self.timer.timer_counter,
             0xFF06 => &self.timer.timer_modulo,
@@ -190,10 +240,27 @@ impl<'a> MemoryMapping<'a> {
             0xFF0F => &self.interrupt.interrupt_flag.value,
             0xFF40 => &self.vram.lcd_control.value,
             0xFF41 => &self.vram.lcd_status.value,
-            0xFF42 => &self.vram.scroll_x,
-            0xFF43 => &self.vram.scroll_y,
+            0xFF42 => &self.vram.scroll_y,
+            0xFF43 => &self.vram.scroll_x,
             0xFF44 => &self.vram.y_coord,
             0xFF45 => &self.vram.y_comp,
+            0xFF46 => &self.dma,
+            0xFF47 => &self.vram.bg_palette,
+            0xFF48 => &self.vram.obj_palette[0],
+            0xFF49 => &self.vram.obj_palette[1],
+            0xFF4A => &self.vram.window_y,
+            0xFF4B => &self.vram.window_x,
+            0xFF4D => &self.key1,
+            0xFF4F => &self.vram.vram_bank,
+            0xFF51 => &self.hdma.source_high,
+            0xFF52 => &self.hdma.source_low,
+            0xFF53 => &self.hdma.destination_high,
+            0xFF54 => &self.hdma.destination_low,
+            0xFF55 => &self.hdma.control,
+            0xFF68 => &self.vram.bg_palettes.spec,
+            0xFF69 => self.vram.bg_palettes.value(),
+            0xFF6A => &self.vram.obj_palettes.spec,
+            0xFF6B => self.vram.obj_palettes.value(),
             0xFF70 => &self.wram.bank_select,
             0xFF80..=0xFFFE => &self.stack[index as usize - 0xFF80],
             0xFFFF => &self.interrupt.interrupt_enable.value,
@@ -203,6 +270,11 @@ impl<'a> MemoryMapping<'a> {
         })
     }
 
+    /// Debugger read, unmapped memory reads 0xFF
+    pub fn peek(&self, index: u16) -> u8 {
+        self.get(index).map_or(0xFF, |value| *value)
+    }
+
     pub fn get_mut(&mut self, index: u16) -> Result<&mut u8> {
         Ok(match index {
             0x0..=0x7FFF => {
@@ -211,6 +283,8 @@ impl<'a> MemoryMapping<'a> {
             0x8000..=0x9FFF => &mut self.vram[index - 0x8000],
             0xA000..=0xBFFF => &mut self.external_ram[index as usize - 0xA000],
             0xC000..=0xDFFF => &mut self.wram[index - 0xC000],
+            0xFE00..=0xFE9F => &mut self.vram.oam[index as usize - 0xFE00],
+            0xFF00 => &mut self.joypad.register,
             0xFF04 => &mut self.timer.divider_register,
             0xFF05 => &mut self.timer.timer_counter,
             0xFF06 => &mut self.timer.timer_modulo,
@@ -218,10 +292,27 @@ impl<'a> MemoryMapping<'a> {
             0xFF0F => &mut self.interrupt.interrupt_flag.value,
             0xFF40 => &mut self.vram.lcd_control.value,
             0xFF41 => &mut self.vram.lcd_status.value,
-            0xFF42 => &mut self.vram.scroll_x,
-            0xFF43 => &mut self.vram.scroll_y,
+            0xFF42 => &mut self.vram.scroll_y,
+            0xFF43 => &mut self.vram.scroll_x,
             0xFF44 => bail!("cannot write to: {:x}", index),
             0xFF45 => &mut self.vram.y_comp,
+            0xFF46 => &mut self.dma,
+            0xFF47 => &mut self.vram.bg_palette,
+            0xFF48 => &mut self.vram.obj_palette[0],
+            0xFF49 => &mut self.vram.obj_palette[1],
+            0xFF4A => &mut self.vram.window_y,
+            0xFF4B => &mut self.vram.window_x,
+            0xFF4D => &mut self.key1,
+            0xFF4F => &mut self.vram.vram_bank,
+            0xFF51 => &mut self.hdma.source_high,
+            0xFF52 => &mut self.hdma.source_low,
+            0xFF53 => &mut self.hdma.destination_high,
+            0xFF54 => &mut self.hdma.destination_low,
+            0xFF55 => &mut self.hdma.control,
+            0xFF68 => &mut self.vram.bg_palettes.spec,
+            0xFF69 => self.vram.bg_palettes.value_mut(),
+            0xFF6A => &mut self.vram.obj_palettes.spec,
+            0xFF6B => self.vram.obj_palettes.value_mut(),
             0xFF70 => &mut self.wram.bank_select,
             0xFF80..=0xFFFE => &mut self.stack[index as usize - 0xFF80],
             0xFFFF => &mut self.interrupt.interrupt_enable.value,
@@ -230,6 +321,179 @@ impl<'a> MemoryMapping<'a> {
             }
         })
     }
+
+    /// Reads as seen by the CPU, VRAM and OAM are inaccessible while the PPU uses them
+    pub fn read(&self, index: u16) -> Result<u8> {
+        let value = self.fetch(index)?;
+        if self.watchpoints.iter().any(|w| w.on_read(index)) {
+            self.hit_watchpoint(WatchpointHit {
+                address: index,
+                write: false,
+                old: value,
+                new: value,
+            });
+        }
+        Ok(value)
+    }
+
+    /// Instruction reads by the CPU, they don't trigger watchpoints
+    pub fn fetch(&self, index: u16) -> Result<u8> {
+        match index {
+            0x8000..=0x9FFF if !self.vram.vram_accessible() => Ok(0xFF),
+            0xFE00..=0xFE9F if !self.vram.oam_accessible() => Ok(0xFF),
+            _ => self.get(index).copied(),
+        }
+    }
+
+    /// Watchpoint triggered by the last accesses, if any
+    pub fn take_watchpoint_hit(&self) -> Option<WatchpointHit> {
+        self.watchpoint_hit.take()
+    }
+
+    fn hit_watchpoint(&self, hit: WatchpointHit) {
+        if self.watchpoint_hit.get().is_none() {
+            self.watchpoint_hit.set(Some(hit));
+        }
+    }
+
+    /// Debugger write with the side effects of a CPU write, without watchpoints or history
+    pub fn poke(&mut self, index: u16, value: u8) -> Result<()> {
+        self.write(index, value)
+    }
+
+    /// Writes as done by the CPU, checking watchpoints and recording the write
+    pub fn set(&mut self, index: u16, value: u8) -> Result<()> {
+        if self.watchpoints.iter().any(|w| w.on_write(index, value)) {
+            self.hit_watchpoint(WatchpointHit {
+                address: index,
+                write: true,
+                old: self.peek(index),
+                new: value,
+            });
+        }
+        if self.write_log.is_none() {
+            return self.write(index, value);
+        }
+
+        let old = self.peek(index);
+        self.write(index, value)?;
+        if let Some(log) = &mut self.write_log {
+            log.push(MemoryWrite {
+                address: index,
+                old,
+                new: value,
+            });
+        }
+        Ok(())
+    }
+
+    /// Writes through `get_mut` and applies the side effects of hardware registers
+    fn write(&mut self, index: u16, value: u8) -> Result<()> {
+        match index {
+            0x8000..=0x9FFF if !self.vram.vram_accessible() => return Ok(()),
+            0xFE00..=0xFE9F if !self.vram.oam_accessible() => return Ok(()),
+            0xFF40 => {
+                self.vram.set_lcd_control(value);
+                return Ok(());
+            }
+            0xFF41 => {
+                // Mode and LYC == LY bits are read only
+                let status = &mut self.vram.lcd_status.value;
+                *status = (value & 0x78) | (*status & 0b111);
+                return Ok(());
+            }
+            0xFF4D => {
+                // Only the switch armed bit is writable
+                self.key1 = (self.key1 & 0x80) | (value & 1);
+                return Ok(());
+            }
+            // VRAM and WRAM banks can't be switched in DMG mode
+            0xFF4F => {
+                if self.vram.cgb {
+                    self.vram.vram_bank = 0xFE | (value & 1);
+                }
+                return Ok(());
+            }
+            0xFF70 => {
+                if self.vram.cgb {
+                    self.wram.bank_select = value & 0b111;
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+        *self.get_mut(index)? = value;
+
+        match index {
+            0xFF00 => {
+                self.joypad.select(value);
+                if let Some(id) = self.vram.sgb_write_p1(value) {
+                    self.joypad.set_joypad_id(id);
+                }
+            }
+            0xFF46 => {
+                // OAM DMA, copied at once instead of over 160 M-cycles
+                let source = (value as u16) << 8;
+                for i in 0..0xA0 {
+                    self.vram.oam[i as usize] = *self.get(source + i)?;
+                }
+            }
+            0xFF55 if self.vram.cgb => match self.hdma.start(value) {
+                HdmaTransfer::General(blocks) => {
+                    for _ in 0..blocks {
+                        self.hdma_copy_block()?;
+                    }
+                    self.hdma.stall(blocks, self.double_speed());
+                }
+                HdmaTransfer::HBlank | HdmaTransfer::Cancel => {}
+            },
+            0xFF69 => self.vram.bg_palettes.auto_increment(),
+            0xFF6B => self.vram.obj_palettes.auto_increment(),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Advances the PPU by `cycles` CPU M-cycles, running HBlank DMA blocks
+    pub fn do_cycles(&mut self, cycles: u16) -> Result<()> {
+        // PPU runs at the same rate in double speed, taking half the CPU cycles
+        let cycles = if self.double_speed() {
+            let cycles = cycles + self.speed_remainder;
+            self.speed_remainder = cycles % 2;
+            cycles / 2
+        } else {
+            cycles
+        };
+
+        let hblank = self.vram.do_cycles(cycles, &mut self.interrupt)?;
+        if hblank && self.hdma.hblank_active() {
+            self.hdma_copy_block()?;
+            self.hdma.stall(1, self.double_speed());
+        }
+        Ok(())
+    }
+
+    fn hdma_copy_block(&mut self) -> Result<()> {
+        let (source, destination) = (self.hdma.source(), self.hdma.destination());
+        for i in 0..0x10 {
+            self.vram[(destination + i) & 0x1FFF] = *self.get(source.wrapping_add(i))?;
+        }
+        self.hdma.next_block();
+        Ok(())
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.key1 & 0x80 != 0
+    }
+
+    /// Toggles CGB double speed on STOP if it was armed through KEY1
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.vram.cgb || self.key1 & 1 == 0 {
+            return false;
+        }
+        self.key1 = (self.key1 ^ 0x80) & 0x80;
+        true
+    }
 }
 
 #[derive(Default, Debug)]
@@ -246,6 +510,41 @@ impl Rom {
         file.read_to_end(&mut buffer)?;
         Ok(Self { rom: buffer })
     }
+
+    /// Bank mapped at 0x4000-0x7FFF, always 1 until MBCs are supported
+    pub fn bank(&self) -> usize {
+        1
+    }
+
+    /// CGB flag (0x143): 0x80 = CGB enhanced, 0xC0 = CGB only
+    pub fn cgb_support(&self) -> bool {
+        self.rom.get(0x143).is_some_and(|flag| flag & 0x80 != 0)
+    }
+
+    /// Title in the cartridge header (0x134-0x143)
+    pub fn title(&self) -> String {
+        self.rom
+            .get(0x134..0x144)
+            .unwrap_or_default()
+            .iter()
+            .take_while(|c| **c != 0)
+            .filter(|c| c.is_ascii_graphic() || **c == b' ')
+            .map(|c| *c as char)
+            .collect()
+    }
+
+    /// Big endian checksum of the whole ROM (0x14E-0x14F)
+    pub fn global_checksum(&self) -> u16 {
+        match self.rom.get(0x14E..0x150) {
+            Some(checksum) => u16::from_be_bytes([checksum[0], checksum[1]]),
+            None => 0,
+        }
+    }
+
+    /// SGB flag (0x146) is only used with the new licensee code (0x14B = 0x33)
+    pub fn sgb_support(&self) -> bool {
+        self.rom.get(0x146) == Some(&0x03) && self.rom.get(0x14B) == Some(&0x33)
+    }
 }
 
 impl Index<u16> for Rom {
@@ -300,3 +599,56 @@ impl IndexMut<u16> for WRam {
         &mut self.wram[idx as usize]
     }
 }
+
+/// The ROM isn't saved, save states are only loaded with the same ROM
+impl<'a> SaveState for MemoryMapping<'a> {
+    fn save(&self, state: &mut StateWriter) {
+        self.vram.save(state);
+        state.bytes(&self.external_ram);
+        state.bytes(&self.wram.wram);
+        state.u8(self.wram.bank_select);
+        state.bytes(&self.stack);
+        self.interrupt.save(state);
+        self.timer.save(state);
+        self.joypad.save(state);
+        state.u8(self.dma);
+        self.hdma.save(state);
+        state.u8(self.key1);
+        state.u16(self.speed_remainder);
+    }
+
+    fn parse<'s>(&self, state: &mut StateReader) -> Result<Apply<'s, Self>>
+    where
+        Self: 's,
+    {
+        let vram = self.vram.parse(state)?;
+        let mut external_ram = [0; 0x2000];
+        state.bytes(&mut external_ram)?;
+        let mut wram = [0; 0x1000 * 8];
+        state.bytes(&mut wram)?;
+        let bank_select = state.u8()?;
+        let mut stack = [0; 0x7F];
+        state.bytes(&mut stack)?;
+        let interrupt = self.interrupt.parse(state)?;
+        let timer = self.timer.parse(state)?;
+        let joypad = self.joypad.parse(state)?;
+        let dma = state.u8()?;
+        let hdma = self.hdma.parse(state)?;
+        let (key1, speed_remainder) = (state.u8()?, state.u16()?);
+
+        Ok(Box::new(move |this: &mut Self| {
+            vram(&mut this.vram);
+            this.external_ram = external_ram;
+            this.wram.wram = wram;
+            this.wram.bank_select = bank_select;
+            this.stack = stack;
+            interrupt(&mut this.interrupt);
+            timer(&mut this.timer);
+            joypad(&mut this.joypad);
+            this.dma = dma;
+            hdma(&mut this.hdma);
+            this.key1 = key1;
+            this.speed_remainder = speed_remainder;
+        }))
+    }
+}
@@ -4,8 +4,17 @@ mod alu;
 
 use std::fmt::Display;
 
-use crate::{instructions::FlagCondition, utils::BitFlag};
+use anyhow::Result;
+
+#[cfg(feature = "frontend")]
+use crate::interrupt::{Interrupt, InterruptPosition};
+use crate::{
+    instructions::FlagCondition,
+    save_state::{Apply, SaveState, StateReader, StateWriter},
+    utils::BitFlag,
+};
 pub use alu::{Alu, Direction};
+#[cfg(feature = "frontend")]
 use imgui::*;
 
 #[derive(Debug, Default)]
@@ -181,7 +190,9 @@ impl Registers {
         *r = val;
     }
 
-    pub fn display_debugger(&self, ui: &Ui) {
+    /// Registers are edited in hex and applied on Enter, flags and interrupts are checkboxes
+    #[cfg(feature = "frontend")]
+    pub fn display_debugger(&mut self, ui: &Ui, interrupt: &mut Interrupt) {
         ui.window("Registers")
             .position([50., 250.], imgui::Condition::FirstUseEver)
             .always_auto_resize(true)
@@ -191,47 +202,155 @@ impl Registers {
                     ui.table_setup_column("Value (0x)");
                     ui.table_headers_row();
 
-                    fn add_column_u8(ui: &Ui, reg: &str, val: u8) {
+                    /// New value once Enter is pressed
+                    fn edit_column(ui: &Ui, reg: &str, val: u16, digits: usize) -> Option<u16> {
                         ui.table_next_row();
                         ui.table_set_column_index(0);
                         ui.text(reg);
                         ui.table_set_column_index(1);
-                        ui.text(format!("{:02x}", val));
+                        let mut str = format!("{val:0digits$x}");
+                        ui.set_next_item_width(digits as f32 * 10. + 10.);
+                        let edited = ui
+                            .input_text(format!("###register_{reg}"), &mut str)
+                            .chars_hexadecimal(true)
+                            .enter_returns_true(true)
+                            .build();
+                        edited.then(|| u16::from_str_radix(&str, 16).ok()).flatten()
                     }
-                    fn add_column_u16(ui: &Ui, reg: &str, val: u16) {
-                        ui.table_next_row();
-                        ui.table_set_column_index(0);
-                        ui.text(reg);
-                        ui.table_set_column_index(1);
-                        ui.text(format!("{:04x}", val));
+
+                    for reg in [
+                        RegisterU8::A,
+                        RegisterU8::B,
+                        RegisterU8::C,
+                        RegisterU8::D,
+                        RegisterU8::E,
+                        RegisterU8::H,
+                        RegisterU8::L,
+                    ] {
+                        let val = self.get_u8(&reg) as u16;
+                        if let Some(val) = edit_column(ui, &reg.to_string(), val, 2) {
+                            self.set_u8(&reg, val as u8);
+                        }
                     }
-                    fn add_column_flag(ui: &Ui, flag: &str, val: bool) {
-                        ui.table_next_row();
-                        ui.table_set_column_index(0);
-                        ui.text(flag);
-                        ui.table_set_column_index(1);
-                        ui.text(if val { "TRUE " } else { "FALSE" });
+                    // The low nibble of F is always 0
+                    if let Some(val) = edit_column(ui, "F", self.f.value as u16, 2) {
+                        self.f.value = val as u8 & 0xF0;
                     }
 
-                    add_column_u8(ui, "A", self.a);
-                    add_column_u8(ui, "B", self.b);
-                    add_column_u8(ui, "C", self.c);
-                    add_column_u8(ui, "D", self.d);
-                    add_column_u8(ui, "E", self.e);
-                    add_column_u8(ui, "H", self.h);
-                    add_column_u8(ui, "L", self.l);
-
-                    ui.new_line();
-                    add_column_u8(ui, "F", self.f.value);
-                    add_column_flag(ui, "Z", self.get_flag(Flags::Z));
-                    add_column_flag(ui, "N", self.get_flag(Flags::N));
-                    add_column_flag(ui, "H", self.get_flag(Flags::H));
-                    add_column_flag(ui, "CY", self.get_flag(Flags::CY));
                     ui.new_line();
+                    for reg in [
+                        RegisterU16::AF,
+                        RegisterU16::BC,
+                        RegisterU16::DE,
+                        RegisterU16::HL,
+                    ] {
+                        let val = self.get_u16(&reg);
+                        if let Some(val) = edit_column(ui, &reg.to_string(), val, 4) {
+                            self.set_u16(&reg, val);
+                        }
+                    }
+                    if let Some(val) = edit_column(ui, "SP", self.sp, 4) {
+                        self.sp = val;
+                    }
+                    if let Some(val) = edit_column(ui, "PC", self.pc, 4) {
+                        self.pc = val;
+                    }
+                }
+
+                for (name, flag) in [
+                    ("Z", Flags::Z),
+                    ("N", Flags::N),
+                    ("H", Flags::H),
+                    ("CY", Flags::CY),
+                ] {
+                    let mut set = self.get_flag(flag);
+                    if ui.checkbox(format!("{name}###flag_{name}"), &mut set) {
+                        self.f.set(flag, set);
+                    }
+                    ui.same_line();
+                }
+                ui.new_line();
+
+                ui.separator();
+                let mut ime = interrupt.ime();
+                if ui.checkbox("IME", &mut ime) {
+                    match ime {
+                        true => interrupt.set_ime_forced(),
+                        false => interrupt.reset_ime(),
+                    }
+                }
+                if interrupt.ime_pending() {
+                    ui.same_line();
+                    ui.text("(EI pending)");
+                }
+                if let Some(_t) = ui.begin_table("Interrupts", 3) {
+                    ui.table_setup_column("Interrupt");
+                    ui.table_setup_column("IE");
+                    ui.table_setup_column("IF");
+                    ui.table_headers_row();
 
-                    add_column_u16(ui, "SP", self.sp);
-                    add_column_u16(ui, "PC", self.pc);
+                    for (name, int) in [
+                        ("VBlank", InterruptPosition::VBlank),
+                        ("LCD", InterruptPosition::Lcd),
+                        ("Timer", InterruptPosition::Timer),
+                        ("Serial", InterruptPosition::Serial),
+                        ("Joypad", InterruptPosition::Joypad),
+                    ] {
+                        ui.table_next_row();
+                        ui.table_set_column_index(0);
+                        ui.text(name);
+                        ui.table_set_column_index(1);
+                        let mut enabled = interrupt.interrupt_enable.get(int);
+                        if ui.checkbox(format!("###ie_{name}"), &mut enabled) {
+                            interrupt.interrupt_enable.set(int, enabled);
+                        }
+                        ui.table_set_column_index(2);
+                        let mut requested = interrupt.interrupt_flag.get(int);
+                        if ui.checkbox(format!("###if_{name}"), &mut requested) {
+                            interrupt.interrupt_flag.set(int, requested);
+                        }
+                    }
                 }
             });
     }
 }
+
+impl SaveState for Registers {
+    fn save(&self, state: &mut StateWriter) {
+        state.bytes(&[
+            self.a,
+            self.b,
+            self.c,
+            self.d,
+            self.e,
+            self.h,
+            self.l,
+            self.f.value,
+        ]);
+        state.u16(self.sp);
+        state.u16(self.pc);
+    }
+
+    fn parse<'s>(&self, state: &mut StateReader) -> Result<Apply<'s, Self>>
+    where
+        Self: 's,
+    {
+        let mut registers = [0; 8];
+        state.bytes(&mut registers)?;
+        let (sp, pc) = (state.u16()?, state.u16()?);
+        Ok(Box::new(move |this: &mut Self| {
+            [
+                this.a,
+                this.b,
+                this.c,
+                this.d,
+                this.e,
+                this.h,
+                this.l,
+                this.f.value,
+            ] = registers;
+            this.sp = sp;
+            this.pc = pc;
+        }))
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::VecDeque;
+
+use anyhow::Result;
+use imgui::Ui;
+
+use crate::{cpu::Cpu, save_state};
+
+const FPS: u32 = 60;
+
+/// Ring buffer of machine snapshots to play the game backwards
+///
+/// Only the newest snapshot is stored whole, each older one is the compressed
+/// difference to the snapshot after it, so the oldest can be dropped at any time.
+#[derive(Debug)]
+pub(crate) struct Rewind {
+    /// Seconds of gameplay kept
+    pub length: u32,
+    /// Frames between snapshots
+    pub granularity: u32,
+    /// Set while the rewind hotkey is held
+    pub rewinding: bool,
+
+    latest: Vec<u8>,
+    deltas: VecDeque<Vec<u8>>,
+    last_frame: u64,
+    frames: u32,
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self {
+            length: 10,
+            granularity: 2,
+            rewinding: false,
+            latest: Vec::new(),
+            deltas: VecDeque::new(),
+            last_frame: 0,
+            frames: 0,
+        }
+    }
+}
+
+impl Rewind {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of snapshots kept
+    pub fn capacity(&self) -> usize {
+        (self.length * FPS / self.granularity.max(1)).max(1) as usize
+    }
+
+    /// Takes a snapshot every `granularity` frames
+    pub fn update(&mut self, cpu: &Cpu) {
+        let frame = cpu.memory.vram.frame_count;
+        if frame == self.last_frame {
+            return;
+        }
+        self.last_frame = frame;
+
+        self.frames += 1;
+        if self.frames < self.granularity {
+            return;
+        }
+        self.frames = 0;
+        self.push(save_state::snapshot(cpu));
+    }
+
+    fn push(&mut self, snapshot: Vec<u8>) {
+        if self.latest.len() == snapshot.len() {
+            self.deltas.push_back(compress(&self.latest, &snapshot));
+        } else {
+            self.deltas.clear();
+        }
+        self.latest = snapshot;
+
+        while self.deltas.len() >= self.capacity() {
+            self.deltas.pop_front();
+        }
+    }
+
+    /// Restores the previous snapshot, returns false when the buffer is exhausted
+    pub fn step_back(&mut self, cpu: &mut Cpu) -> Result<bool> {
+        if self.latest.is_empty() {
+            return Ok(false);
+        }
+        let Some(delta) = self.deltas.pop_back() else {
+            save_state::restore(cpu, &self.latest)?;
+            return Ok(false);
+        };
+        decompress(&delta, &mut self.latest);
+        save_state::restore(cpu, &self.latest)?;
+        self.frames = 0;
+        Ok(true)
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = Vec::new();
+        self.deltas.clear();
+        self.frames = 0;
+    }
+
+    /// Bytes used by snapshots
+    pub fn memory_usage(&self) -> usize {
+        self.latest.len() + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+
+    pub fn display_debugger(&mut self, ui: &Ui) {
+        ui.window("Rewind")
+            .size([300., 130.], imgui::Condition::FirstUseEver)
+            .position([1250., 50.], imgui::Condition::FirstUseEver)
+            .build(|| {
+                ui.text("Hold Backspace to rewind");
+                ui.slider("Length (s)", 1, 120, &mut self.length);
+                ui.slider("Granularity (frames)", 1, 30, &mut self.granularity);
+                ui.text(format!(
+                    "Snapshots: {}/{} ({} KB)",
+                    self.deltas.len() + !self.latest.is_empty() as usize,
+                    self.capacity(),
+                    self.memory_usage() / 1024
+                ));
+                if ui.button("Clear") {
+                    self.clear();
+                }
+            });
+    }
+}
+
+/// Difference of two snapshots of the same size
+///
+/// XOR of both is stored as segments of `[zero run: u16][literal length: u16][literals]`,
+/// as most of the machine doesn't change between frames.
+fn compress(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut i = 0;
+    while i < new.len() {
+        let start = i;
+        while i < new.len() && i - start < u16::MAX as usize && old[i] == new[i] {
+            i += 1;
+        }
+        delta.extend_from_slice(&((i - start) as u16).to_le_bytes());
+
+        let literals = i;
+        // A literal run ends at 4 unchanged bytes, shorter runs cost more as a new segment
+        while i < new.len()
+            && i - literals < u16::MAX as usize
+            && !(i + 4 <= new.len() && old[i..i + 4] == new[i..i + 4])
+        {
+            i += 1;
+        }
+        delta.extend_from_slice(&((i - literals) as u16).to_le_bytes());
+        delta.extend(
+            old[literals..i]
+                .iter()
+                .zip(&new[literals..i])
+                .map(|(a, b)| a ^ b),
+        );
+    }
+    delta
+}
+
+/// Applies a difference made by `compress` in place
+fn decompress(delta: &[u8], data: &mut [u8]) {
+    let mut i = 0;
+    let mut pos = 0;
+    while i + 4 <= delta.len() {
+        let zeros = u16::from_le_bytes([delta[i], delta[i + 1]]) as usize;
+        let literals = u16::from_le_bytes([delta[i + 2], delta[i + 3]]) as usize;
+        i += 4;
+        pos += zeros;
+        for (byte, xor) in data[pos..pos + literals]
+            .iter_mut()
+            .zip(&delta[i..i + literals])
+        {
+            *byte ^= xor;
+        }
+        pos += literals;
+        i += literals;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rewind, compress, decompress};
+    use crate::{
+        cpu::Cpu,
+        memory_mapping::{MemoryMapping, Rom},
+    };
+
+    #[test]
+    fn delta_compression() {
+        let old: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        let mut new = old.clone();
+        new[0] = 0xFF;
+        new[1000..1003].copy_from_slice(&[1, 2, 3]);
+        new[199_999] = 0;
+
+        let delta = compress(&new, &old);
+        assert!(delta.len() < 64);
+        let mut data = new.clone();
+        decompress(&delta, &mut data);
+        assert_eq!(data, old);
+    }
+
+    #[test]
+    fn step_back() {
+        let mut cpu = Cpu::new(MemoryMapping::new(Rom {
+            rom: vec![0; 0x8000],
+        }));
+        let mut rewind = Rewind {
+            length: 1,
+            granularity: 2,
+            ..Default::default()
+        };
+
+        for frame in 1..=200 {
+            cpu.memory.set(0xC000, frame as u8).unwrap();
+            cpu.memory.vram.frame_count = frame;
+            rewind.update(&cpu);
+        }
+        assert_eq!(rewind.deltas.len(), rewind.capacity() - 1);
+
+        assert!(rewind.step_back(&mut cpu).unwrap());
+        assert_eq!(*cpu.memory.get(0xC000).unwrap(), 198);
+        assert!(rewind.step_back(&mut cpu).unwrap());
+        assert_eq!(*cpu.memory.get(0xC000).unwrap(), 196);
+
+        while rewind.step_back(&mut cpu).unwrap() {}
+        assert_eq!(*cpu.memory.get(0xC000).unwrap(), 200 - 2 * 29);
+    }
+}
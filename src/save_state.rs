@@ -0,0 +1,330 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result, bail};
+
+use crate::cpu::Cpu;
+
+const MAGIC: &[u8; 4] = b"GBSS";
+/// Increased whenever the layout of the saved state changes
+pub const VERSION: u16 = 1;
+
+/// Applies state that was parsed, which can't fail anymore
+pub(crate) type Apply<'s, T> = Box<dyn FnOnce(&mut T) + 's>;
+
+/// Machine state that is written to and restored from save states
+///
+/// The whole state is read before anything is applied, so loading an invalid state
+/// leaves the machine as it was.
+pub(crate) trait SaveState: Sized {
+    fn save(&self, state: &mut StateWriter);
+    /// Reads the state without changing anything, `self` only gives the expected sizes
+    fn parse<'s>(&self, state: &mut StateReader) -> Result<Apply<'s, Self>>
+    where
+        Self: 's;
+
+    fn load(&mut self, state: &mut StateReader) -> Result<()> {
+        let apply = self.parse(state)?;
+        apply(self);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Fixed size data, the reader needs to know its length
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.data.extend_from_slice(value);
+    }
+
+    /// Length prefixed data
+    pub fn vec(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.bytes(value);
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            bail!("Save state is truncated");
+        }
+        let (value, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(value)
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    pub fn bytes(&mut self, value: &mut [u8]) -> Result<()> {
+        value.copy_from_slice(self.take(value.len())?);
+        Ok(())
+    }
+
+    /// Length prefixed data, `expected` guards against loading a different layout
+    pub fn vec(&mut self, expected: usize) -> Result<Vec<u8>> {
+        let len = self.u32()? as usize;
+        if len != expected {
+            bail!("Invalid save state: expected {expected} bytes of data, found {len}");
+        }
+        Ok(self.take(len)?.to_vec())
+    }
+}
+
+/// Stored in front of the machine state to identify the save state without loading it
+#[derive(Debug)]
+pub(crate) struct StateHeader {
+    pub version: u16,
+    /// ROM title and global checksum of the cartridge header
+    pub title: String,
+    pub global_checksum: u16,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// RGB24 screen at half resolution
+    pub thumbnail: Vec<u8>,
+}
+
+impl StateHeader {
+    fn new(cpu: &Cpu) -> Self {
+        Self {
+            version: VERSION,
+            title: cpu.memory.rom.title(),
+            global_checksum: cpu.memory.rom.global_checksum(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            thumbnail: cpu.memory.vram.thumbnail(),
+        }
+    }
+
+    fn write(&self, state: &mut StateWriter) {
+        state.bytes(MAGIC);
+        state.u16(self.version);
+        state.vec(self.title.as_bytes());
+        state.u16(self.global_checksum);
+        state.u64(self.timestamp);
+        state.vec(&self.thumbnail);
+    }
+
+    pub fn read(state: &mut StateReader) -> Result<Self> {
+        let mut magic = [0; 4];
+        state.bytes(&mut magic).context("Not a save state")?;
+        if &magic != MAGIC {
+            bail!("Not a save state");
+        }
+
+        let version = state.u16()?;
+        if version != VERSION {
+            bail!("Unsupported save state version {version}, expected version {VERSION}");
+        }
+
+        let title_len = state.u32()? as usize;
+        let mut title = vec![0; title_len];
+        state.bytes(&mut title)?;
+
+        Ok(Self {
+            version,
+            title: String::from_utf8_lossy(&title).into_owned(),
+            global_checksum: state.u16()?,
+            timestamp: state.u64()?,
+            thumbnail: state.vec(crate::graphics::THUMBNAIL_SIZE)?,
+        })
+    }
+}
+
+/// Serializes the whole machine
+pub(crate) fn save(cpu: &Cpu) -> Vec<u8> {
+    let mut state = StateWriter::default();
+    StateHeader::new(cpu).write(&mut state);
+
+    state.vec(&snapshot(cpu));
+
+    state.data
+}
+
+/// Machine state without a header, used by rewind
+pub(crate) fn snapshot(cpu: &Cpu) -> Vec<u8> {
+    let mut state = StateWriter::default();
+    cpu.save(&mut state);
+    state.data
+}
+
+pub(crate) fn restore(cpu: &mut Cpu, data: &[u8]) -> Result<()> {
+    cpu.load(&mut StateReader::new(data))
+}
+
+/// Restores the machine, the state has to be made with the same ROM
+pub(crate) fn load(cpu: &mut Cpu, data: &[u8]) -> Result<()> {
+    let mut state = StateReader::new(data);
+    let header = StateHeader::read(&mut state)?;
+
+    let (title, global_checksum) = (cpu.memory.rom.title(), cpu.memory.rom.global_checksum());
+    if header.title != title || header.global_checksum != global_checksum {
+        bail!(
+            "Save state was made with ROM \"{}\" (checksum {:04X}), but the loaded ROM is \"{}\" (checksum {:04X})",
+            header.title,
+            header.global_checksum,
+            title,
+            global_checksum
+        );
+    }
+
+    // Checked before anything is overwritten
+    let len = state.u32()? as usize;
+    if state.data.len() != len {
+        bail!("Save state is truncated");
+    }
+    cpu.load(&mut state)
+}
+
+/// `game.gb` uses `game.ss1` to `game.ss10`
+pub(crate) fn slot_path(rom: &Path, slot: u8) -> PathBuf {
+    rom.with_extension(format!("ss{slot}"))
+}
+
+pub(crate) fn save_slot(cpu: &Cpu, rom: &Path, slot: u8) -> Result<()> {
+    let path = slot_path(rom, slot);
+    fs::write(&path, save(cpu))
+        .with_context(|| format!("Couldn't write save state {}", path.display()))
+}
+
+pub(crate) fn load_slot(cpu: &mut Cpu, rom: &Path, slot: u8) -> Result<()> {
+    let path = slot_path(rom, slot);
+    let data =
+        fs::read(&path).with_context(|| format!("Couldn't read save state {}", path.display()))?;
+    load(cpu, &data).with_context(|| format!("Couldn't load save state {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, restore, save, snapshot};
+    use crate::{
+        cpu::Cpu,
+        memory_mapping::{MemoryMapping, Rom},
+    };
+
+    fn test_cpu(title: &[u8]) -> Cpu<'static> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x14E..0x150].copy_from_slice(&[0x12, 0x34]);
+        Cpu::new(MemoryMapping::new(Rom { rom }))
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut cpu = test_cpu(b"TETRIS");
+        cpu.registers.a = 0x3F;
+        cpu.registers.pc = 0x150;
+        cpu.memory.set(0xC123, 0x42).unwrap();
+        cpu.memory.set(0xFF05, 0x99).unwrap();
+        cpu.memory.set(0x9800, 0x01).unwrap();
+        cpu.memory.do_cycles(1000).unwrap();
+        let state = save(&cpu);
+
+        let mut loaded = test_cpu(b"TETRIS");
+        load(&mut loaded, &state).unwrap();
+        assert_eq!(loaded.registers.a, 0x3F);
+        assert_eq!(loaded.registers.pc, 0x150);
+        assert_eq!(*loaded.memory.get(0xC123).unwrap(), 0x42);
+        assert_eq!(*loaded.memory.get(0xFF05).unwrap(), 0x99);
+        assert_eq!(loaded.memory.vram.vram[0x1800], 0x01);
+        assert_eq!(loaded.memory.vram.y_coord, cpu.memory.vram.y_coord);
+    }
+
+    #[test]
+    fn different_rom() {
+        let cpu = test_cpu(b"TETRIS");
+        let state = save(&cpu);
+
+        let mut other = test_cpu(b"POKEMON RED");
+        let error = load(&mut other, &state).unwrap_err();
+        assert!(error.to_string().contains("\"TETRIS\""));
+
+        assert!(load(&mut other, &state[..state.len() - 1]).is_err());
+        assert!(load(&mut other, b"not a state").is_err());
+    }
+
+    #[test]
+    fn invalid_state_changes_nothing() {
+        let mut cpu = test_cpu(b"TETRIS");
+        cpu.memory.set(0xC123, 0x42).unwrap();
+        let mut state = save(&cpu);
+
+        // Cut off the last component but keep the length consistent
+        let len = snapshot(&cpu).len();
+        let len_offset = state.len() - len - 4;
+        state.truncate(state.len() - 1);
+        state[len_offset..len_offset + 4].copy_from_slice(&(len as u32 - 1).to_le_bytes());
+
+        let mut loaded = test_cpu(b"TETRIS");
+        loaded.registers.pc = 0x150;
+        loaded.memory.set(0xC123, 0x99).unwrap();
+        loaded.memory.set(0x9800, 0x01).unwrap();
+        assert!(load(&mut loaded, &state).is_err());
+        assert_eq!(loaded.registers.pc, 0x150);
+        assert_eq!(*loaded.memory.get(0xC123).unwrap(), 0x99);
+        assert_eq!(loaded.memory.vram.vram[0x1800], 0x01);
+
+        // Rewind snapshots have no header or length to check up front
+        let snapshot = snapshot(&cpu);
+        assert!(restore(&mut loaded, &snapshot[..snapshot.len() - 1]).is_err());
+        assert_eq!(loaded.registers.pc, 0x150);
+        assert_eq!(*loaded.memory.get(0xC123).unwrap(), 0x99);
+        restore(&mut loaded, &snapshot).unwrap();
+        assert_eq!(*loaded.memory.get(0xC123).unwrap(), 0x42);
+    }
+}
@@ -1,7 +1,13 @@
 use std::{sync::atomic::AtomicU64, time::Duration};
 
 use anyhow::Result;
-use sdl3::{EventPump, Sdl, event::Event, render::Canvas, video::Window};
+use sdl3::{
+    EventPump, Sdl,
+    event::Event,
+    keyboard::{Keycode, Mod},
+    render::Canvas,
+    video::Window,
+};
 
 use crate::debugger::Debugger;
 
@@ -15,10 +21,40 @@ impl<'a> Drop for UpdateToken<'a> {
     }
 }
 
+/// Requests from the window or hotkeys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    /// Shift + F1-F10
+    SaveState(u8),
+    /// F1-F10
+    LoadState(u8),
+    /// Backspace pressed or released
+    Rewind(bool),
+    /// Tab pressed or released
+    FastForward(bool),
+    /// N: runs one frame, then pauses
+    FrameAdvance,
+    /// P
+    TogglePause,
+    /// S: one instruction while paused
+    Step,
+    /// O
+    StepOver,
+    /// U
+    StepOut,
+    /// L: runs until LY changes, then pauses
+    Scanline,
+    /// B: undoes the last instruction while paused
+    StepBack,
+}
+
 pub struct SdlInstance {
     pub sdl_context: Sdl,
     pub canvas: Canvas<Window>,
     pub event_pump: EventPump,
+    /// Every frame is presented, waiting for the display refresh
+    vsync: bool,
 }
 
 const FPS: u64 = 60;
@@ -45,20 +81,75 @@ impl SdlInstance {
             sdl_context,
             canvas,
             event_pump,
+            vsync: false,
         })
     }
 
-    pub fn handle_event(&mut self, debugger: &mut Debugger) -> bool {
+    pub fn vsync(&self) -> bool {
+        self.vsync
+    }
+
+    pub fn set_vsync(&mut self, vsync: bool) {
+        if self.vsync == vsync {
+            return;
+        }
+        self.vsync = vsync;
+        unsafe {
+            sdl3_sys::render::SDL_SetRenderVSync(self.canvas.raw(), vsync as i32);
+        }
+    }
+
+    pub fn handle_event(&mut self, debugger: &mut Debugger) -> Vec<Action> {
+        let mut actions = Vec::new();
         for event in self.event_pump.poll_iter() {
             debugger
                 .platform
                 .handle_event(&mut debugger.imgui_context, &event);
-            if let Event::Quit { .. } = event {
-                return true;
+            // Keys typed into imgui widgets aren't hotkeys
+            let imgui_keyboard = debugger.imgui_context.io().want_capture_keyboard;
+
+            match event {
+                Event::Quit { .. } => actions.push(Action::Quit),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if !imgui_keyboard => {
+                    match keycode {
+                        Keycode::Backspace => actions.push(Action::Rewind(true)),
+                        Keycode::Tab => actions.push(Action::FastForward(true)),
+                        Keycode::N => actions.push(Action::FrameAdvance),
+                        Keycode::P => actions.push(Action::TogglePause),
+                        Keycode::S => actions.push(Action::Step),
+                        Keycode::O => actions.push(Action::StepOver),
+                        Keycode::U => actions.push(Action::StepOut),
+                        Keycode::L => actions.push(Action::Scanline),
+                        Keycode::B => actions.push(Action::StepBack),
+                        _ => {}
+                    }
+                    if let Some(slot) = state_slot(keycode) {
+                        actions.push(if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            Action::SaveState(slot)
+                        } else {
+                            Action::LoadState(slot)
+                        });
+                    }
+                }
+                // Released keys always pass so held actions don't get stuck
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => actions.push(Action::Rewind(false)),
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => actions.push(Action::FastForward(false)),
+                _ => {}
             }
         }
 
-        false
+        actions
     }
 
     pub fn to_sleep(&self) -> Duration {
@@ -72,7 +163,8 @@ impl SdlInstance {
     }
 
     pub fn update_graphics(&mut self, debugger: &mut Debugger) -> Option<UpdateToken<'_>> {
-        if self.to_sleep() != Duration::ZERO {
+        // Without vsync frames are only presented at FPS, while running faster than that
+        if !self.vsync && self.to_sleep() != Duration::ZERO {
             return None;
         }
 
@@ -90,3 +182,20 @@ impl SdlInstance {
         Some(UpdateToken(self))
     }
 }
+
+/// Save state slot of F1-F10
+fn state_slot(keycode: Keycode) -> Option<u8> {
+    Some(match keycode {
+        Keycode::F1 => 1,
+        Keycode::F2 => 2,
+        Keycode::F3 => 3,
+        Keycode::F4 => 4,
+        Keycode::F5 => 5,
+        Keycode::F6 => 6,
+        Keycode::F7 => 7,
+        Keycode::F8 => 8,
+        Keycode::F9 => 9,
+        Keycode::F10 => 10,
+        _ => return None,
+    })
+}
@@ -0,0 +1,483 @@
+use anyhow::{Result, bail};
+
+use crate::{
+    graphics::rgb555_to_rgb888,
+    save_state::{Apply, SaveState, StateReader, StateWriter},
+};
+
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+/// Position of the Game Boy screen inside the border
+pub const SCREEN_X: usize = 48;
+pub const SCREEN_Y: usize = 40;
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    PAL01 = 0x00,
+    PAL23 = 0x01,
+    PAL03 = 0x02,
+    PAL12 = 0x03,
+    ATTR_BLK = 0x04,
+    ATTR_LIN = 0x05,
+    ATTR_DIV = 0x06,
+    ATTR_CHR = 0x07,
+    MLT_REQ = 0x11,
+    CHR_TRN = 0x13,
+    PCT_TRN = 0x14,
+    MASK_EN = 0x17,
+}
+
+impl TryFrom<u8> for Command {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0x00 => Command::PAL01,
+            0x01 => Command::PAL23,
+            0x02 => Command::PAL03,
+            0x03 => Command::PAL12,
+            0x04 => Command::ATTR_BLK,
+            0x05 => Command::ATTR_LIN,
+            0x06 => Command::ATTR_DIV,
+            0x07 => Command::ATTR_CHR,
+            0x11 => Command::MLT_REQ,
+            0x13 => Command::CHR_TRN,
+            0x14 => Command::PCT_TRN,
+            0x17 => Command::MASK_EN,
+            _ => return Err(value),
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mask {
+    #[default]
+    Cancel,
+    /// Keep showing the last frame
+    Freeze,
+    Black,
+    /// Fill the screen with color 0
+    Color0,
+}
+
+/// 4KB copied from VRAM through the displayed BG tiles
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum VramTransfer {
+    /// Border tiles, `true` for tiles 0x80-0xFF
+    Chr(bool),
+    /// Border tile map and palettes 4-7
+    Pct,
+}
+
+#[derive(Debug)]
+pub(crate) struct Sgb {
+    packet: [u8; 16],
+    packets: Vec<u8>,
+    /// Next bit of the packet, `None` while waiting for a reset pulse
+    bit: Option<usize>,
+    last_p1: u8,
+
+    /// 8 palettes of 16 colors, 0-3 for the screen and 4-7 for the border
+    pub palettes: [[u16; 16]; 8],
+    /// Palette used by each 8x8 cell of the screen
+    pub attributes: [u8; 20 * 18],
+    pub mask: Mask,
+    border_tiles: Vec<u8>,
+    border_map: Vec<u8>,
+    pub border_changed: bool,
+
+    players: u8,
+    pub player: u8,
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        let mut palettes = [[0; 16]; 8];
+        for palette in &mut palettes {
+            palette[..4].copy_from_slice(&[0x7FFF, 0x56B5, 0x294A, 0x0000]);
+        }
+
+        Self {
+            packet: [0; 16],
+            packets: Vec::new(),
+            bit: None,
+            last_p1: 0x30,
+            palettes,
+            attributes: [0; 20 * 18],
+            mask: Mask::Cancel,
+            border_tiles: vec![0; 0x2000],
+            border_map: vec![0; 0x800],
+            border_changed: true,
+            players: 1,
+            player: 0,
+        }
+    }
+}
+
+impl Sgb {
+    /// Receives packet bits written to P1
+    ///
+    /// P14 and P15 low is a reset pulse, P15 low is a 1 and P14 low is a 0.
+    /// Both lines go high between bits and a 0 stop bit ends each 16 byte packet.
+    pub fn write_p1(&mut self, value: u8) -> Option<VramTransfer> {
+        let p1 = value & 0x30;
+        let last_p1 = std::mem::replace(&mut self.last_p1, p1);
+
+        match (p1, self.bit) {
+            (0x00, _) => {
+                self.packet = [0; 16];
+                self.bit = Some(0);
+            }
+            (0x10 | 0x20, Some(bit)) if last_p1 == 0x30 => {
+                if bit < 128 {
+                    if p1 == 0x10 {
+                        self.packet[bit / 8] |= 1 << (bit % 8);
+                    }
+                    self.bit = Some(bit + 1);
+                } else {
+                    self.bit = None;
+                    return self.receive_packet();
+                }
+            }
+            // Controller id switches on P15 rising edge while not transferring
+            (0x30, None) if last_p1 & 0x20 == 0 && self.players > 1 => {
+                self.player = (self.player + 1) % self.players;
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Controller id shown in P1 when no buttons are selected
+    pub fn joypad_id(&self) -> Option<u8> {
+        (self.players > 1).then_some(self.player)
+    }
+
+    fn receive_packet(&mut self) -> Option<VramTransfer> {
+        if self.packets.is_empty() && self.packet[0] & 0b111 == 0 {
+            return None;
+        }
+        self.packets.extend_from_slice(&self.packet);
+
+        let length = (self.packets[0] & 0b111) as usize;
+        if self.packets.len() < length * 16 {
+            return None;
+        }
+
+        let data = std::mem::take(&mut self.packets);
+        match Command::try_from(data[0] >> 3) {
+            Ok(command) => self.execute(command, &data),
+            Err(_) => None,
+        }
+    }
+
+    fn execute(&mut self, command: Command, data: &[u8]) -> Option<VramTransfer> {
+        match command {
+            Command::PAL01 => self.set_palettes(0, 1, data),
+            Command::PAL23 => self.set_palettes(2, 3, data),
+            Command::PAL03 => self.set_palettes(0, 3, data),
+            Command::PAL12 => self.set_palettes(1, 2, data),
+            Command::ATTR_BLK => {
+                let sets = data[1] as usize;
+                for set in data[2..].chunks_exact(6).take(sets) {
+                    let (control, palettes) = (set[0], set[1]);
+                    let (x1, y1, x2, y2) = (set[2], set[3], set[4], set[5]);
+                    let inside = palettes & 0b11;
+                    let outside = (palettes >> 4) & 0b11;
+                    // Only inside or outside being set changes the border too
+                    let (change_border, border) = match control & 0b111 {
+                        0b001 => (true, inside),
+                        0b100 => (true, outside),
+                        c => (c & 0b010 != 0, (palettes >> 2) & 0b11),
+                    };
+
+                    for y in 0..18 {
+                        for x in 0..20 {
+                            let in_x = (x1..=x2).contains(&x);
+                            let in_y = (y1..=y2).contains(&y);
+                            let on_border =
+                                (in_x && (y == y1 || y == y2)) || (in_y && (x == x1 || x == x2));
+                            let palette = if on_border {
+                                change_border.then_some(border)
+                            } else if in_x && in_y {
+                                (control & 0b001 != 0).then_some(inside)
+                            } else {
+                                (control & 0b100 != 0).then_some(outside)
+                            };
+                            if let Some(palette) = palette {
+                                self.attributes[y as usize * 20 + x as usize] = palette;
+                            }
+                        }
+                    }
+                }
+            }
+            Command::ATTR_LIN => {
+                let sets = data[1] as usize;
+                for line in data[2..].iter().take(sets) {
+                    let (number, palette) = ((line & 0x1F) as usize, (line >> 5) & 0b11);
+                    if line & 0x80 != 0 && number < 18 {
+                        self.attributes[number * 20..number * 20 + 20].fill(palette);
+                    } else if line & 0x80 == 0 && number < 20 {
+                        for y in 0..18 {
+                            self.attributes[y * 20 + number] = palette;
+                        }
+                    }
+                }
+            }
+            Command::ATTR_DIV => {
+                let (after, before, on) =
+                    (data[1] & 0b11, (data[1] >> 2) & 0b11, (data[1] >> 4) & 0b11);
+                let horizontal = data[1] & 0x40 != 0;
+                let line = data[2] as usize;
+                for y in 0..18 {
+                    for x in 0..20 {
+                        let position = if horizontal { y } else { x };
+                        self.attributes[y * 20 + x] = match position.cmp(&line) {
+                            std::cmp::Ordering::Less => before,
+                            std::cmp::Ordering::Equal => on,
+                            std::cmp::Ordering::Greater => after,
+                        };
+                    }
+                }
+            }
+            Command::ATTR_CHR => {
+                let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+                let sets = u16::from_le_bytes([data[3], data[4]]) as usize;
+                let vertical = data[5] & 1 != 0;
+                for i in 0..sets.min(20 * 18) {
+                    let Some(byte) = data.get(6 + i / 4) else {
+                        break;
+                    };
+                    if x < 20 && y < 18 {
+                        self.attributes[y * 20 + x] = (byte >> (6 - (i % 4) * 2)) & 0b11;
+                    }
+                    if vertical {
+                        y += 1;
+                        if y >= 18 {
+                            (x, y) = (x + 1, 0);
+                        }
+                    } else {
+                        x += 1;
+                        if x >= 20 {
+                            (x, y) = (0, y + 1);
+                        }
+                    }
+                }
+            }
+            Command::MLT_REQ => {
+                self.players = match data[1] & 0b11 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            Command::CHR_TRN => return Some(VramTransfer::Chr(data[1] & 1 != 0)),
+            Command::PCT_TRN => return Some(VramTransfer::Pct),
+            Command::MASK_EN => {
+                self.mask = match data[1] & 0b11 {
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    3 => Mask::Color0,
+                    _ => Mask::Cancel,
+                };
+            }
+        }
+        None
+    }
+
+    /// Color 0 is shared by all screen palettes
+    fn set_palettes(&mut self, a: usize, b: usize, data: &[u8]) {
+        let colors: Vec<u16> = data[1..15]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        for palette in &mut self.palettes[0..4] {
+            palette[0] = colors[0];
+        }
+        self.palettes[a][1..4].copy_from_slice(&colors[1..4]);
+        self.palettes[b][1..4].copy_from_slice(&colors[4..7]);
+    }
+
+    pub fn vram_transfer(&mut self, transfer: VramTransfer, data: &[u8]) {
+        match transfer {
+            VramTransfer::Chr(upper) => {
+                let start = upper as usize * 0x1000;
+                self.border_tiles[start..start + 0x1000].copy_from_slice(&data[..0x1000]);
+            }
+            VramTransfer::Pct => {
+                self.border_map.copy_from_slice(&data[..0x800]);
+                for (i, color) in data[0x800..0x880].chunks_exact(2).enumerate() {
+                    self.palettes[4 + i / 16][i % 16] = u16::from_le_bytes([color[0], color[1]]);
+                }
+            }
+        }
+        self.border_changed = true;
+    }
+
+    /// Color of a DMG shade at screen position `x`, `y`
+    pub fn screen_color(&self, shade: u8, x: usize, y: usize) -> [u8; 3] {
+        let palette = self.attributes[(y / 8) * 20 + x / 8] as usize;
+        rgb555_to_rgb888(self.palettes[palette][shade as usize], false)
+    }
+
+    /// Color of the border at `x`, `y` of the 256x224 output
+    pub fn border_color(&self, x: usize, y: usize) -> [u8; 3] {
+        let entry_addr = ((y / 8) * 32 + x / 8) * 2;
+        let entry =
+            u16::from_le_bytes([self.border_map[entry_addr], self.border_map[entry_addr + 1]]);
+        let tile = (entry & 0xFF) as usize * 32;
+        let palette = ((entry >> 10) & 0b111) as usize;
+        let row = if entry & 0x8000 != 0 {
+            7 - y % 8
+        } else {
+            y % 8
+        };
+        let bit = if entry & 0x4000 != 0 {
+            x % 8
+        } else {
+            7 - x % 8
+        };
+
+        // SNES 4bpp: bitplanes 0-1 in the first 16 bytes, 2-3 in the next 16
+        let color_idx = [
+            self.border_tiles[tile + row * 2],
+            self.border_tiles[tile + row * 2 + 1],
+            self.border_tiles[tile + 16 + row * 2],
+            self.border_tiles[tile + 16 + row * 2 + 1],
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |idx, (plane, byte)| idx | ((byte >> bit) & 1) << plane);
+
+        let color = match color_idx {
+            0 => self.palettes[0][0],
+            _ => self.palettes[palette][color_idx as usize],
+        };
+        rgb555_to_rgb888(color, false)
+    }
+}
+
+impl SaveState for Sgb {
+    fn save(&self, state: &mut StateWriter) {
+        state.bytes(&self.packet);
+        state.vec(&self.packets);
+        // 0xFF while waiting for a reset pulse
+        state.u8(self.bit.map_or(0xFF, |bit| bit as u8));
+        state.u8(self.last_p1);
+        for color in self.palettes.iter().flatten() {
+            state.u16(*color);
+        }
+        state.bytes(&self.attributes);
+        state.u8(self.mask as u8);
+        state.vec(&self.border_tiles);
+        state.vec(&self.border_map);
+        state.u8(self.players);
+        state.u8(self.player);
+    }
+
+    fn parse<'s>(&self, state: &mut StateReader) -> Result<Apply<'s, Self>>
+    where
+        Self: 's,
+    {
+        let mut packet = [0; 16];
+        state.bytes(&mut packet)?;
+        let len = state.u32()? as usize;
+        let mut packets = vec![0; len];
+        state.bytes(&mut packets)?;
+        let bit = match state.u8()? {
+            0xFF => None,
+            bit => Some(bit as usize),
+        };
+        let last_p1 = state.u8()?;
+        let mut palettes = [[0; 16]; 8];
+        for color in palettes.iter_mut().flatten() {
+            *color = state.u16()?;
+        }
+        let mut attributes = [0; 20 * 18];
+        state.bytes(&mut attributes)?;
+        let mask = match state.u8()? {
+            0 => Mask::Cancel,
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            3 => Mask::Color0,
+            mask => bail!("Invalid save state: SGB mask {mask}"),
+        };
+        let border_tiles = state.vec(self.border_tiles.len())?;
+        let border_map = state.vec(self.border_map.len())?;
+        let (players, player) = (state.u8()?, state.u8()?);
+        Ok(Box::new(move |this: &mut Self| {
+            this.packet = packet;
+            this.packets = packets;
+            this.bit = bit;
+            this.last_p1 = last_p1;
+            this.palettes = palettes;
+            this.attributes = attributes;
+            this.mask = mask;
+            this.border_tiles = border_tiles;
+            this.border_map = border_map;
+            this.players = players;
+            this.player = player;
+            this.border_changed = true;
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mask, Sgb};
+
+    fn send_packet(sgb: &mut Sgb, packet: &[u8; 16]) {
+        sgb.write_p1(0x00);
+        sgb.write_p1(0x30);
+        for bit in 0..128 {
+            let one = packet[bit / 8] & (1 << (bit % 8)) != 0;
+            sgb.write_p1(if one { 0x10 } else { 0x20 });
+            sgb.write_p1(0x30);
+        }
+        sgb.write_p1(0x20);
+        sgb.write_p1(0x30);
+    }
+
+    #[test]
+    fn pal01() {
+        let mut sgb = Sgb::default();
+        let mut packet = [0; 16];
+        // PAL01, 1 packet
+        packet[0] = 1;
+        for (i, color) in [0x1111u16, 0x2222, 0x3333, 0x4444, 0x5555, 0x6666, 0x7777]
+            .iter()
+            .enumerate()
+        {
+            packet[1 + i * 2..3 + i * 2].copy_from_slice(&color.to_le_bytes());
+        }
+        send_packet(&mut sgb, &packet);
+
+        assert_eq!(sgb.palettes[0][..4], [0x1111, 0x2222, 0x3333, 0x4444]);
+        assert_eq!(sgb.palettes[1][..4], [0x1111, 0x5555, 0x6666, 0x7777]);
+        assert_eq!(sgb.palettes[3][0], 0x1111);
+    }
+
+    #[test]
+    fn attr_div_and_mask() {
+        let mut sgb = Sgb::default();
+        let mut packet = [0; 16];
+        packet[0] = 0x06 << 3 | 1;
+        // Horizontal division at row 5: below = 3 (bits 0-1), above = 1 (bits 2-3),
+        // on the line = 2 (bits 4-5)
+        packet[1] = 0x40 | 2 << 4 | 1 << 2 | 3;
+        packet[2] = 5;
+        send_packet(&mut sgb, &packet);
+
+        assert_eq!(sgb.attributes[4 * 20], 1);
+        assert_eq!(sgb.attributes[5 * 20 + 19], 2);
+        assert_eq!(sgb.attributes[17 * 20], 3);
+
+        let mut packet = [0; 16];
+        packet[0] = 0x17 << 3 | 1;
+        packet[1] = 2;
+        send_packet(&mut sgb, &packet);
+        assert_eq!(sgb.mask, Mask::Black);
+    }
+}
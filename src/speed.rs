@@ -0,0 +1,142 @@
+use std::{
+    hint::spin_loop,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use imgui::Ui;
+
+/// 70224 dots at 4194304 Hz
+pub const FRAME_DURATION: Duration = Duration::from_nanos(16_742_706);
+/// Frames per second of the Game Boy, about 59.73
+const GAMEBOY_FPS: f64 = 4194304. / 70224.;
+/// `sleep` isn't precise, the rest is spent spinning
+const SPIN_DURATION: Duration = Duration::from_millis(1);
+
+const MULTIPLIERS: [f64; 6] = [0.25, 0.5, 1., 2., 4., 8.];
+const LABELS: [&str; 6] = ["0.25x", "0.5x", "1x", "2x", "4x", "8x"];
+
+/// Emulation speed relative to the Game Boy
+#[derive(Debug)]
+pub(crate) struct Speed {
+    /// Index into `MULTIPLIERS`
+    pub multiplier: usize,
+    /// Runs as fast as possible
+    pub turbo: bool,
+    /// Set while the fast-forward hotkey is held, same as turbo
+    pub fast_forward: bool,
+
+    next_frame: Instant,
+    measure_start: Instant,
+    measure_frames: u32,
+    /// Emulated frames per second relative to the Game Boy
+    pub measured: f64,
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Self {
+            multiplier: 2,
+            turbo: false,
+            fast_forward: false,
+            next_frame: Instant::now(),
+            measure_start: Instant::now(),
+            measure_frames: 0,
+            measured: 0.,
+        }
+    }
+}
+
+impl Speed {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `None` while uncapped
+    pub fn multiplier(&self) -> Option<f64> {
+        match self.turbo || self.fast_forward {
+            true => None,
+            false => Some(MULTIPLIERS[self.multiplier]),
+        }
+    }
+
+    /// Real time taken by `duration` of emulated time
+    pub fn scale(&self, duration: Duration) -> Duration {
+        match self.multiplier() {
+            Some(multiplier) => duration.div_f64(multiplier),
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Waits until the next frame is due
+    ///
+    /// `running` frames are scaled by the multiplier, otherwise the UI is updated at the
+    /// Game Boy frame rate. With `vsync` presenting the frame already waits.
+    pub fn wait(&mut self, running: bool, vsync: bool) {
+        let now = Instant::now();
+        let frame = match running {
+            true => self.scale(FRAME_DURATION),
+            false => FRAME_DURATION,
+        };
+        self.next_frame += frame;
+        // Late frames aren't caught up, running behind would otherwise end in a burst
+        if vsync || self.next_frame <= now {
+            self.next_frame = now;
+            return;
+        }
+
+        let remaining = self.next_frame - now;
+        if remaining > SPIN_DURATION {
+            sleep(remaining - SPIN_DURATION);
+        }
+        while Instant::now() < self.next_frame {
+            spin_loop();
+        }
+    }
+
+    /// Called after every emulated frame to measure the speed
+    pub fn frame_finished(&mut self) {
+        self.measure_frames += 1;
+        let elapsed = self.measure_start.elapsed();
+        if elapsed >= Duration::from_millis(500) {
+            self.measured = self.measure_frames as f64 / elapsed.as_secs_f64() / GAMEBOY_FPS;
+            self.measure_start = Instant::now();
+            self.measure_frames = 0;
+        }
+    }
+
+    pub fn display(&mut self, ui: &Ui) {
+        ui.text(format!(
+            "Emulation speed: {:.0}% ({:.1} fps)",
+            self.measured * 100.,
+            self.measured * GAMEBOY_FPS
+        ));
+        ui.set_next_item_width(80.);
+        ui.combo_simple_string("Speed", &mut self.multiplier, &LABELS);
+        ui.same_line();
+        ui.checkbox("Turbo", &mut self.turbo);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Speed;
+
+    #[test]
+    fn scale() {
+        let mut speed = Speed::new();
+        let frame = Duration::from_micros(16_742);
+        assert_eq!(speed.scale(frame), frame);
+
+        speed.multiplier = 0;
+        assert_eq!(speed.scale(frame), frame * 4);
+        speed.multiplier = 5;
+        assert_eq!(speed.scale(frame), frame / 8);
+
+        speed.fast_forward = true;
+        assert_eq!(speed.multiplier(), None);
+        assert_eq!(speed.scale(frame), Duration::ZERO);
+    }
+}
@@ -1,6 +1,11 @@
-use crate::interrupt::{Interrupt, InterruptPosition};
+use anyhow::Result;
 
-enum TimerController {
+use crate::{
+    interrupt::{Interrupt, InterruptPosition},
+    save_state::{Apply, SaveState, StateReader, StateWriter},
+};
+
+pub(crate) enum TimerController {
     ClockSelect = 0b11,
     Enable = 0b100,
 }
@@ -21,7 +26,7 @@ impl Timer {
         Self::default()
     }
 
-    pub fn do_cycles(&mut self, interrupt: &mut Interrupt, cycles: u8) {
+    pub fn do_cycles(&mut self, interrupt: &mut Interrupt, cycles: u16) {
         for _ in 0..cycles {
             self.do_cycle(interrupt);
         }
@@ -56,3 +61,30 @@ impl Timer {
         }
     }
 }
+
+impl SaveState for Timer {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.internal_counter);
+        state.u8(self.divider_register);
+        state.u8(self.timer_counter);
+        state.u8(self.timer_modulo);
+        state.u8(self.timer_controller);
+    }
+
+    fn parse<'s>(&self, state: &mut StateReader) -> Result<Apply<'s, Self>>
+    where
+        Self: 's,
+    {
+        let mut registers = [0; 5];
+        state.bytes(&mut registers)?;
+        Ok(Box::new(move |this: &mut Self| {
+            [
+                this.internal_counter,
+                this.divider_register,
+                this.timer_counter,
+                this.timer_modulo,
+                this.timer_controller,
+            ] = registers;
+        }))
+    }
+}
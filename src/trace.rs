@@ -0,0 +1,166 @@
+use std::{fmt::Debug, io::Write};
+
+use anyhow::Result;
+
+use crate::{instructions::Instruction, memory_mapping::MemoryMapping, registers::Registers};
+
+/// Logs the CPU state before every instruction in the format compared by Gameboy Doctor
+///
+/// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+///
+/// The extended format appends the M-cycles since tracing started, LY and the instruction.
+pub(crate) struct Trace {
+    writer: Box<dyn Write>,
+    extended: bool,
+    cycles: u64,
+}
+
+impl Trace {
+    pub fn new(writer: impl Write + 'static, extended: bool) -> Self {
+        Self {
+            writer: Box::new(writer),
+            extended,
+            cycles: 0,
+        }
+    }
+
+    pub fn log(
+        &mut self,
+        registers: &Registers,
+        memory: &MemoryMapping,
+        instruction: &Instruction,
+    ) -> Result<()> {
+        let pc = registers.pc;
+        let pcmem = [0, 1, 2, 3].map(|i| memory.peek(pc.wrapping_add(i)));
+        write!(
+            self.writer,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            registers.a,
+            registers.f.value,
+            registers.b,
+            registers.c,
+            registers.d,
+            registers.e,
+            registers.h,
+            registers.l,
+            registers.sp,
+            pc,
+            pcmem[0],
+            pcmem[1],
+            pcmem[2],
+            pcmem[3],
+        )?;
+        if self.extended {
+            write!(
+                self.writer,
+                " CY:{} LY:{:02X} {instruction}",
+                self.cycles, memory.vram.y_coord
+            )?;
+        }
+        writeln!(self.writer)?;
+        Ok(())
+    }
+
+    /// Called after every step with the M-cycles it took, interrupt dispatches are not logged
+    /// but count
+    pub fn add_cycles(&mut self, cycles: u16) {
+        self.cycles += cycles as u64;
+    }
+}
+
+impl Debug for Trace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Trace")
+            .field("extended", &self.extended)
+            .field("cycles", &self.cycles)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, io::Write, rc::Rc};
+
+    use super::Trace;
+    use crate::{
+        cpu::Cpu,
+        memory_mapping::{MemoryMapping, Rom},
+    };
+
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn traced_cpu(extended: bool) -> (Cpu<'static>, Buffer) {
+        let mut rom = vec![0; 0x8000];
+        // NOP, JP $0213
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x13, 0x02]);
+        let mut cpu = Cpu::new(MemoryMapping::new(Rom { rom }));
+        cpu.registers.a = 0x01;
+        cpu.registers.f.value = 0xB0;
+        cpu.registers.c = 0x13;
+        cpu.registers.e = 0xD8;
+        cpu.registers.h = 0x01;
+        cpu.registers.l = 0x4D;
+
+        let buffer = Buffer::default();
+        cpu.trace = Some(Trace::new(buffer.clone(), extended));
+        (cpu, buffer)
+    }
+
+    #[test]
+    fn doctor_format() {
+        let (mut cpu, buffer) = traced_cpu(false);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        let log = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        assert_eq!(
+            log,
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02\n\
+             A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:C3,13,02,00\n"
+        );
+    }
+
+    #[test]
+    fn extended_format() {
+        let (mut cpu, buffer) = traced_cpu(true);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+
+        let log = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let lines: Vec<_> = log.lines().collect();
+        assert!(lines[0].ends_with("PCMEM:00,C3,13,02 CY:0 LY:00 NOP"));
+        assert!(lines[1].contains(" CY:1 LY:00 JP "));
+        assert!(lines[2].contains("PC:0213") && lines[2].contains(" CY:5 "));
+    }
+
+    #[test]
+    fn interrupt_dispatch() {
+        let (mut cpu, buffer) = traced_cpu(true);
+        cpu.step().unwrap();
+        cpu.memory.interrupt.set_ime_forced();
+        cpu.memory.interrupt.interrupt_enable.value = 1;
+        cpu.memory.interrupt.interrupt_flag.value = 1;
+        cpu.step().unwrap();
+        assert!(!cpu.memory.interrupt.ime());
+        cpu.step().unwrap();
+
+        // The line after the NOP is the first instruction of the handler
+        let log = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let lines: Vec<_> = log.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].contains("SP:FFFC PC:0040 PCMEM:00,00,00,00 CY:6 "));
+    }
+}
@@ -27,6 +27,13 @@ where
     T: Default + Copy + PartialEq + BitAnd<Output = T> + BitXor<Output = T>,
     U: Into<T> + Copy,
 {
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            _phantom: PhantomData,
+        }
+    }
+
     pub fn get(&self, flag: U) -> bool {
         self.get_into(flag.into())
     }
@@ -0,0 +1,167 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{Context, Error, Result, bail};
+
+/// Accesses a watchpoint triggers on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+    /// Writes of this value
+    Value(u8),
+}
+
+/// Stops execution when the CPU accesses an address in `start..=end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+/// Access that triggered a watchpoint, `old` and `new` are equal for reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct WatchpointHit {
+    pub address: u16,
+    pub write: bool,
+    pub old: u8,
+    pub new: u8,
+}
+
+impl Watchpoint {
+    pub fn new(start: u16, end: u16, kind: WatchKind) -> Self {
+        Self {
+            start: start.min(end),
+            end: start.max(end),
+            kind,
+        }
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        (self.start..=self.end).contains(&address)
+    }
+
+    pub fn on_read(&self, address: u16) -> bool {
+        self.contains(address) && matches!(self.kind, WatchKind::Read | WatchKind::ReadWrite)
+    }
+
+    pub fn on_write(&self, address: u16, value: u8) -> bool {
+        self.contains(address)
+            && match self.kind {
+                WatchKind::Read => false,
+                WatchKind::Write | WatchKind::ReadWrite => true,
+                WatchKind::Value(v) => v == value,
+            }
+    }
+}
+
+/// `r`, `w`, `rw` or `=$12`
+impl FromStr for WatchKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "r" => Self::Read,
+            "w" => Self::Write,
+            "rw" => Self::ReadWrite,
+            s => match s.strip_prefix('=') {
+                Some(value) => Self::Value(
+                    u8::from_str_radix(value.trim_start_matches('$'), 16)
+                        .with_context(|| format!("invalid value `{value}`"))?,
+                ),
+                None => bail!("unknown access `{s}`, expected r, w, rw or =$xx"),
+            },
+        })
+    }
+}
+
+impl fmt::Display for WatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read => write!(f, "r"),
+            Self::Write => write!(f, "w"),
+            Self::ReadWrite => write!(f, "rw"),
+            Self::Value(v) => write!(f, "=${v:02X}"),
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.start == self.end {
+            true => write!(f, "${:04X} {}", self.start, self.kind),
+            false => write!(f, "${:04X}-${:04X} {}", self.start, self.end, self.kind),
+        }
+    }
+}
+
+impl fmt::Display for WatchpointHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.write {
+            true => write!(
+                f,
+                "write ${:04X}: ${:02X} -> ${:02X}",
+                self.address, self.old, self.new
+            ),
+            false => write!(f, "read ${:04X}: ${:02X}", self.address, self.old),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WatchKind, Watchpoint, WatchpointHit};
+    use crate::memory_mapping::MemoryMapping;
+
+    #[test]
+    fn triggers() {
+        let mut memory = MemoryMapping::default();
+        memory
+            .watchpoints
+            .push(Watchpoint::new(0xC010, 0xC000, WatchKind::Write));
+        memory
+            .watchpoints
+            .push(Watchpoint::new(0xFF80, 0xFF80, WatchKind::Value(0x12)));
+        memory
+            .watchpoints
+            .push(Watchpoint::new(0xD000, 0xD000, WatchKind::Read));
+
+        memory.set(0xC100, 1).unwrap();
+        memory.read(0xC008).unwrap();
+        assert_eq!(memory.take_watchpoint_hit(), None);
+
+        memory.set(0xC008, 0x34).unwrap();
+        let hit = WatchpointHit {
+            address: 0xC008,
+            write: true,
+            old: 0,
+            new: 0x34,
+        };
+        assert_eq!(memory.take_watchpoint_hit(), Some(hit));
+        assert_eq!(memory.take_watchpoint_hit(), None);
+
+        memory.set(0xFF80, 0x11).unwrap();
+        assert_eq!(memory.take_watchpoint_hit(), None);
+        memory.set(0xFF80, 0x12).unwrap();
+        assert!(memory.take_watchpoint_hit().is_some());
+
+        memory.read(0xD000).unwrap();
+        assert_eq!(
+            memory.take_watchpoint_hit().map(|hit| hit.to_string()),
+            Some("read $D000: $00".to_string())
+        );
+        // The debugger's reads don't trigger
+        memory.peek(0xD000);
+        assert_eq!(memory.take_watchpoint_hit(), None);
+    }
+
+    #[test]
+    fn parse_kind() {
+        assert_eq!("rw".parse::<WatchKind>().unwrap(), WatchKind::ReadWrite);
+        assert_eq!("=$3F".parse::<WatchKind>().unwrap(), WatchKind::Value(0x3F));
+        assert!("x".parse::<WatchKind>().is_err());
+        let watchpoint = Watchpoint::new(0xC000, 0xC0FF, WatchKind::Value(1));
+        assert_eq!(watchpoint.to_string(), "$C000-$C0FF =$01");
+    }
+}